@@ -0,0 +1,425 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! `xmark check`: find everything wrong with the site, without rendering it.
+//!
+//! Unlike a build, this doesn't stop at the first problem, so CI can show them
+//! all at once. Problems in one book don't stop the others being checked.
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use eyre::{bail, Result};
+use pulldown_cmark::{Event, Options, Parser, Tag};
+use tracing::instrument;
+
+use crate::cli;
+use crate::cli::config::{self, OrphanLevel, OrphansConf};
+use crate::cli::summary::SummaryError;
+use crate::content::{self, front_matter, links, orphans, Content};
+use crate::html_render::{dedupe, expand_containers, slugify};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Diagnostic {
+    pub(crate) severity: Severity,
+    /// Relative to the site, when it's in it.
+    pub(crate) file: PathBuf,
+    /// 1 based.
+    pub(crate) line: Option<usize>,
+    pub(crate) message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.severity {
+            Severity::Warning => write!(f, "warning: ")?,
+            Severity::Error => write!(f, "error: ")?,
+        }
+        write!(f, "{}", self.file.display())?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Check the site, print what's wrong, and fail if any of it is an error.
+#[instrument]
+pub(crate) fn run(args: &cli::Args) -> Result<()> {
+    let diagnostics = check(args);
+    for d in &diagnostics {
+        println!("{}", d);
+    }
+
+    let errors = diagnostics
+        .iter()
+        .filter(|d| d.severity == Severity::Error)
+        .count();
+    let warnings = diagnostics.len() - errors;
+    if errors > 0 {
+        bail!("Found {} errors and {} warnings", errors, warnings);
+    }
+    println!("No errors, {} warnings", warnings);
+    Ok(())
+}
+
+/// A chapter that exists, and what can be linked to in it.
+struct Source {
+    ids: HashSet<String>,
+}
+
+struct Checker<'a> {
+    args: &'a cli::Args,
+    diagnostics: Vec<Diagnostic>,
+}
+
+#[instrument]
+pub(crate) fn check(args: &cli::Args) -> Vec<Diagnostic> {
+    let mut checker = Checker {
+        args,
+        diagnostics: Vec::new(),
+    };
+    checker.site();
+    checker.diagnostics
+}
+
+impl Checker<'_> {
+    fn report(&mut self, severity: Severity, file: &Path, line: Option<usize>, message: String) {
+        let file = file.strip_prefix(&self.args.dir).unwrap_or(file).to_owned();
+        self.diagnostics.push(Diagnostic {
+            severity,
+            file,
+            line,
+            message,
+        });
+    }
+
+    fn error(&mut self, file: &Path, line: Option<usize>, message: String) {
+        self.report(Severity::Error, file, line, message)
+    }
+
+    fn site(&mut self) {
+        let toml = self.args.dir.join("xmark.toml");
+        let repr = match config::load_repr(self.args) {
+            Ok(repr) => repr,
+            Err(e) => {
+                let line = e
+                    .downcast_ref::<toml::de::Error>()
+                    .and_then(|e| e.line_col())
+                    .map(|(line, _)| line + 1);
+                return self.error(&toml, line, chain(&e));
+            }
+        };
+
+        let mut books = Vec::new();
+        // Links into these aren't checked, as the problem is their summary.
+        let mut broken = Vec::new();
+        for name in &repr.books {
+            match config::load_book(self.args, name) {
+                Ok(book) => books.push(book),
+                Err(e) => {
+                    broken.push(self.args.dir.join(name));
+                    let summary = self.args.dir.join(name).join("SUMMARY.md");
+                    match e.chain().find_map(|e| e.downcast_ref::<SummaryError>()) {
+                        Some(SummaryError(problems)) => {
+                            for p in problems {
+                                let message = format!("{} (hint: {})", p.message, p.hint);
+                                self.error(&summary, Some(p.line), message);
+                            }
+                        }
+                        None => self.error(&summary, None, chain(&e)),
+                    }
+                }
+            }
+        }
+
+        // Only the output locations matter here, so the site url doesn't.
+        let build = match config::hydrate_build(repr.build.clone(), &repr.books, self.args) {
+            Ok(build) => build,
+            Err(e) => return self.error(&toml, None, chain(&e)),
+        };
+        let conf = config::GlobalConf {
+            build,
+            ..Default::default()
+        };
+        let dirs = content::Dirs::new(&conf, self.args);
+
+        let mut sources = BTreeMap::new();
+        for book in &books {
+            self.chapters(book, &dirs, &mut sources);
+            self.orphans(book, &repr.orphans, &conf.build.out_dir);
+        }
+        for (path, _) in sources.iter().filter(|(_, s)| s.is_some()) {
+            self.links(path, &sources, &broken);
+        }
+
+        // Everything else (redirects, output config) is checked when loading
+        // the content, but that stops at the first problem, so only bother if
+        // there's nothing else wrong.
+        if !self
+            .diagnostics
+            .iter()
+            .any(|d| d.severity == Severity::Error)
+        {
+            let loaded = config::load(self.args)
+                .and_then(|conf| Content::new(&conf, &content::Dirs::new(&conf, self.args)));
+            if let Err(e) = loaded {
+                self.error(&toml, None, chain(&e));
+            }
+        }
+    }
+
+    /// Check each chapter in the summary exists once, and read the ones that do.
+    ///
+    /// `sources` is every chapter, with `None` for ones that couldn't be read.
+    fn chapters(
+        &mut self,
+        book: &config::Book,
+        dirs: &content::Dirs,
+        sources: &mut BTreeMap<PathBuf, Option<Source>>,
+    ) {
+        let summary = book.location.join("SUMMARY.md");
+        let lines = fs::read_to_string(&summary)
+            .ok()
+            .and_then(|src| book.summary.chapter_lines(&src));
+
+        match content::Book::clashes(book, dirs) {
+            Ok(clashes) => {
+                for clash in clashes {
+                    self.error(&summary, clash.line, clash.message);
+                }
+            }
+            Err(e) => self.error(&summary, None, chain(&e)),
+        }
+
+        for (i, chapter) in book.summary.chapters().into_iter().enumerate() {
+            let path = match &chapter.location {
+                Some(path) if !sources.contains_key(path) => path,
+                _ => continue,
+            };
+            if !path.is_file() {
+                let line = lines.as_ref().map(|l| l[i]);
+                let rel = path.strip_prefix(&book.location).unwrap_or(path);
+                self.error(
+                    &summary,
+                    line,
+                    format!("{:?} is missing, {:?} doesn't exist", chapter.name, rel),
+                );
+                sources.insert(path.clone(), None);
+                continue;
+            }
+
+            let source = fs::read_to_string(path)
+                .map_err(eyre::Report::from)
+                .and_then(|src| front_matter::split(&src).map(|(_, body)| ids(body)));
+            match source {
+                Ok(ids) => {
+                    sources.insert(path.clone(), Some(Source { ids }));
+                }
+                Err(e) => {
+                    self.error(path, Some(1), chain(&e));
+                    sources.insert(path.clone(), None);
+                }
+            }
+        }
+    }
+
+    /// Markdown files in the book that aren't in the summary.
+    fn orphans(&mut self, book: &config::Book, conf: &OrphansConf, out_dir: &Path) {
+        let orphans = match orphans::find(book, conf, out_dir) {
+            Ok(orphans) => orphans,
+            Err(e) => return self.error(&self.args.dir.join("xmark.toml"), None, chain(&e)),
+        };
+        let severity = match conf.level {
+            OrphanLevel::Warn => Severity::Warning,
+            OrphanLevel::Error => Severity::Error,
+        };
+        for path in orphans {
+            self.report(
+                severity,
+                &path,
+                None,
+                "isn't in the SUMMARY.md, so won't be rendered".to_owned(),
+            );
+        }
+    }
+
+    fn links(
+        &mut self,
+        path: &Path,
+        sources: &BTreeMap<PathBuf, Option<Source>>,
+        broken: &[PathBuf],
+    ) {
+        let src = match fs::read_to_string(path) {
+            Ok(src) => src,
+            Err(_) => return,
+        };
+        let body = match front_matter::split(&src) {
+            Ok((_, body)) => body,
+            Err(_) => return,
+        };
+        // The body is the end of the file, so this is how many lines the
+        // front matter took.
+        let skipped = src[..src.len() - body.len()].matches('\n').count();
+
+        for (ev, range) in Parser::new_ext(body, Options::all()).into_offset_iter() {
+            let dest = match ev {
+                Event::Start(Tag::Link(_, dest, _)) | Event::Start(Tag::Image(_, dest, _)) => dest,
+                _ => continue,
+            };
+            let link = match links::resolve(path, &dest) {
+                Some(link) => link,
+                None => continue,
+            };
+            let line = Some(skipped + body[..range.start].matches('\n').count() + 1);
+
+            let problem = if !link.path.exists() {
+                Some("no such file".to_owned())
+            } else if link.path.extension().is_some_and(|x| x == "md") {
+                match (sources.get(&link.path), link.fragment) {
+                    (None, _) if broken.iter().any(|b| link.path.starts_with(b)) => None,
+                    (None, _) => Some("it isn't in a SUMMARY.md".to_owned()),
+                    (Some(Some(target)), Some(frag)) if !target.ids.contains(frag) => {
+                        Some(format!("there's no heading with the id {:?}", frag))
+                    }
+                    _ => None,
+                }
+            } else {
+                None
+            };
+
+            if let Some(problem) = problem {
+                self.error(
+                    path,
+                    line,
+                    format!("Broken link to {:?}, {}", &*dest, problem),
+                );
+            }
+        }
+    }
+}
+
+/// The ids a link into this chapter can have as it's fragment.
+fn ids(body: &str) -> HashSet<String> {
+    let body = expand_containers(body);
+    let mut ids = HashSet::new();
+    let mut seen = HashMap::new();
+    let mut heading: Option<String> = None;
+
+    for ev in Parser::new_ext(&body, Options::all()) {
+        match (ev, &mut heading) {
+            (Event::Start(Tag::Heading(_)), _) => heading = Some(String::new()),
+            (Event::End(Tag::Heading(_)), _) => {
+                let text = heading.take().unwrap_or_default();
+                ids.insert(dedupe(&mut seen, slugify(&text)));
+            }
+            (Event::Text(t), Some(text)) | (Event::Code(t), Some(text)) => text.push_str(&t),
+            (Event::Start(Tag::FootnoteDefinition(name)), _) => {
+                ids.insert(name.into_string());
+            }
+            // Anything with an id in raw html can be linked to too, but not
+            // a `data-id` or the like.
+            (Event::Html(html), _) => {
+                for quote in &["id=\"", "id='"] {
+                    for (start, _) in html.match_indices(quote) {
+                        if !html[..start].ends_with(char::is_whitespace) {
+                            continue;
+                        }
+                        let rest = &html[start + quote.len()..];
+                        let end = rest.find(&quote[3..]).unwrap_or(rest.len());
+                        ids.insert(rest[..end].to_owned());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    ids
+}
+
+/// The error, and everything that caused it.
+fn chain(e: &eyre::Report) -> String {
+    e.chain()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(": ")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use assert_fs::prelude::*;
+
+    use super::*;
+
+    fn site(files: &[(&str, &str)]) -> assert_fs::TempDir {
+        let temp = assert_fs::TempDir::new().unwrap();
+        for (path, contents) in files {
+            temp.child(path).write_str(contents).unwrap();
+        }
+        temp
+    }
+
+    fn messages(temp: &assert_fs::TempDir) -> Vec<String> {
+        let args = cli::Args {
+            dir: temp.path().to_owned(),
+            ..Default::default()
+        };
+        check(&args).iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn finds_everything() {
+        let temp = site(&[
+            ("xmark.toml", "books = ['a', 'b']"),
+            (
+                "a/SUMMARY.md",
+                "# A\n\n- [One](one.md)\n    - [Gone](gone.md)\n- [Again](one.md)\n- [Bad](bad.md)\n\
+                 - [Sub](sub.md)\n- [Sub index](sub/README.md)\n",
+            ),
+            (
+                "a/one.md",
+                "# One\n\n## Two words\n\n[ok](#two-words) [ok](../b/x.md) [nope](#three)\n\n\
+                 ![img](missing.png) [web](https://example.com) [orphan](notes.md)\n\n\
+                 [other](bad.md#x) <a id=\"custom\"></a> [html](#custom)\n\n\
+                 <span data-id=\"fake\"></span> [data](#fake)\n",
+            ),
+            ("a/bad.md", "+++\ntitle = 1\n+++\n# Bad\n"),
+            ("a/notes.md", "# Notes\n"),
+            ("a/sub.md", "# Sub\n"),
+            ("a/sub/README.md", "# Sub index\n"),
+            ("a/_drafts/skip.md", "# Skip\n"),
+            ("b/SUMMARY.md", "# B\n\n- [X](x.md)\n  - bad\n"),
+            ("b/x.md", "# X\n"),
+        ]);
+        insta::assert_yaml_snapshot!(messages(&temp));
+    }
+
+    #[test]
+    fn dummy_book() {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("dummy-book");
+        let args = cli::Args {
+            dir,
+            ..Default::default()
+        };
+        let errors: Vec<_> = check(&args)
+            .into_iter()
+            .filter(|d| d.severity == Severity::Error)
+            .collect();
+        assert_eq!(errors, vec![]);
+    }
+
+    #[test]
+    fn bad_config() {
+        let temp = site(&[("xmark.toml", "books = 'a'\n")]);
+        assert_eq!(messages(&temp).len(), 1);
+        assert!(messages(&temp)[0].starts_with("error: xmark.toml:1: "));
+    }
+}
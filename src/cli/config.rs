@@ -1,21 +1,43 @@
 // SPDX-License-Identifier: GPL-3.0-only
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use eyre::{Result, WrapErr};
+use eyre::{bail, Result, WrapErr};
 use serde::{Deserialize, Serialize};
 use tracing::instrument;
 
 use super::create_missing::create_missing;
 use super::summary::{parse_summary, Chapter, Summary};
 use crate::cli;
+use crate::content::links;
 
 /// The Config as represented in the global xmark.toml
 #[derive(Clone, Debug, Hash, Serialize, Deserialize, PartialEq, Default)]
 pub(crate) struct GlobalConfigRepr {
     pub(crate) books: Vec<String>,
     #[serde(default)]
-    pub(crate) html: HtmlConf,
+    pub(crate) html: HtmlConfRepr,
+    /// Old chapter paths, and the chapter they've moved to. Both are relative
+    /// to the xmark.toml.
+    #[serde(default)]
+    pub(crate) redirects: BTreeMap<PathBuf, PathBuf>,
+    /// Only rendered if present.
+    #[serde(default)]
+    pub(crate) pdf: Option<PdfConf>,
+    /// Only rendered if present.
+    #[serde(default)]
+    pub(crate) epub: Option<EpubConf>,
+    /// Only rendered if present.
+    #[serde(default)]
+    pub(crate) markdown: Option<MarkdownConf>,
+    /// Only rendered if present.
+    #[serde(default)]
+    pub(crate) man: Option<ManConfRepr>,
+    #[serde(default)]
+    pub(crate) orphans: OrphansConf,
+    #[serde(default)]
+    pub(crate) build: BuildConfRepr,
 }
 
 /// The config as usable for the programm
@@ -23,6 +45,14 @@ pub(crate) struct GlobalConfigRepr {
 pub(crate) struct GlobalConf {
     pub(crate) books: Vec<Book>,
     pub(crate) html: HtmlConf,
+    /// Absolute paths of old chapters, and the chapter to redirect to.
+    pub(crate) redirects: BTreeMap<PathBuf, PathBuf>,
+    pub(crate) pdf: Option<PdfConf>,
+    pub(crate) epub: Option<EpubConf>,
+    pub(crate) markdown: Option<MarkdownConf>,
+    pub(crate) man: Option<ManConf>,
+    pub(crate) orphans: OrphansConf,
+    pub(crate) build: BuildConf,
 }
 
 // An book.
@@ -36,17 +66,337 @@ pub(crate) struct Book {
 // https://doc.rust-lang.org/1.47.0/cargo/reference/specifying-dependencies.html#development-dependencies
 // Cargo uses kebab, and so shall we
 #[serde(default, rename_all = "kebab-case")]
-pub(crate) struct HtmlConf {
+pub(crate) struct HtmlConfRepr {
+    /// Where the site is hosted, like `https://example.com/docs/`, or just
+    /// the path, `/docs/`.
     pub(crate) site_url: Option<String>,
+    /// Link between pages with relative urls, so the site works from any
+    /// path, not just the one in the `site-url`.
+    pub(crate) relative_links: bool,
+    /// Make the site work when opened from disk, over `file://`. This means
+    /// relative links, that point at `index.html` files.
+    pub(crate) offline: bool,
+    pub(crate) robots: RobotsConf,
+    /// Only written if present.
+    pub(crate) feed: Option<FeedConf>,
+    /// Linked to from every page.
+    pub(crate) git_repository_url: Option<String>,
+    /// The FontAwesome icon for the repository link. Defaults to
+    /// `fa-github`.
+    pub(crate) git_repository_icon: Option<String>,
+    /// Where to edit a chapter, with `{path}` for it's path in the repo, like
+    /// `https://github.com/me/docs/edit/main/{path}`.
+    pub(crate) edit_url_template: Option<String>,
+    #[serde(flatten)]
+    pub(crate) theme: ThemeConfRepr,
+    /// Theme settings for just one book, like `[html.books.cli]`.
+    pub(crate) books: BTreeMap<String, ThemeConfRepr>,
+}
+
+/// The look of the pages, for the whole site or a book. Unset things come
+/// from the site, and then the defaults.
+#[derive(Clone, Debug, Hash, PartialEq, Serialize, Deserialize, Default, Eq)]
+#[serde(default, rename_all = "kebab-case")]
+pub(crate) struct ThemeConfRepr {
+    pub(crate) language: Option<String>,
+    /// One of the themes in `variables.css`.
+    pub(crate) default_theme: Option<String>,
+    /// For readers who prefer a dark theme.
+    pub(crate) preferred_dark_theme: Option<String>,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Serialize, Deserialize, Eq)]
+pub(crate) struct Theme {
+    pub(crate) language: String,
+    pub(crate) default_theme: String,
+    pub(crate) preferred_dark_theme: String,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            language: "en".to_owned(),
+            default_theme: "rust".to_owned(),
+            preferred_dark_theme: "coal".to_owned(),
+        }
+    }
+}
+
+/// The themes the bundled css has, which are the top level classes in it's
+/// `variables.css`.
+fn themes() -> Vec<&'static str> {
+    include_str!("../../www/static/css/variables.css")
+        .lines()
+        .filter_map(|l| l.strip_prefix('.')?.strip_suffix(" {"))
+        .collect()
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Serialize, Deserialize, Default, Eq)]
+pub(crate) struct HtmlConf {
+    pub(crate) site_url: SiteUrl,
+    pub(crate) relative_links: bool,
+    pub(crate) offline: bool,
+    pub(crate) robots: RobotsConf,
+    pub(crate) feed: Option<FeedConf>,
+    pub(crate) git_repository_url: Option<String>,
+    pub(crate) git_repository_icon: Option<String>,
+    pub(crate) edit_url_template: Option<String>,
+    pub(crate) theme: Theme,
+    /// Books that have their own theme, by their absolute path.
+    pub(crate) books: BTreeMap<PathBuf, Theme>,
+}
+
+impl HtmlConf {
+    /// The theme for the book at `location`.
+    pub(crate) fn theme(&self, location: &Path) -> &Theme {
+        self.books.get(location).unwrap_or(&self.theme)
+    }
+}
+
+/// The `robots.txt`, which is only written with the sitemap. Crawlers only
+/// look for it at the root of the host, so it isn't written if the site is in
+/// a subdirectory.
+#[derive(Clone, Debug, Hash, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(default, rename_all = "kebab-case")]
+pub(crate) struct RobotsConf {
+    /// Turn this off if you've got your own.
+    pub(crate) enabled: bool,
+    /// Paths crawlers shouldn't visit, relative to the site.
+    pub(crate) disallow: Vec<String>,
+}
+
+/// An Atom feed of the most recent chapters. Like the sitemap, this needs a
+/// `site-url` with a host.
+#[derive(Clone, Debug, Hash, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(default, rename_all = "kebab-case")]
+pub(crate) struct FeedConf {
+    pub(crate) scope: FeedScope,
+    /// How many chapters are in each feed.
+    pub(crate) limit: usize,
+    /// For a site wide feed. Defaults to the titles of the books.
+    pub(crate) title: Option<String>,
+}
+
+impl Default for FeedConf {
+    fn default() -> Self {
+        Self {
+            scope: FeedScope::default(),
+            limit: 20,
+            title: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Serialize, Deserialize, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum FeedScope {
+    /// A feed for each book.
+    #[default]
+    Book,
+    /// One feed for every book.
+    Site,
+}
+
+impl Default for RobotsConf {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            disallow: Vec::new(),
+        }
+    }
+}
+
+/// The `site-url`, split into the bits we need.
+#[derive(Clone, Debug, Hash, PartialEq, Serialize, Deserialize, Eq)]
+pub(crate) struct SiteUrl {
+    /// The scheme and host, like `https://example.com`, if they were given.
+    pub(crate) origin: Option<String>,
+    /// Always starts and ends with a `/`.
+    pub(crate) path: String,
+}
+
+impl Default for SiteUrl {
+    fn default() -> Self {
+        Self {
+            origin: None,
+            path: "/".to_owned(),
+        }
+    }
+}
+
+impl SiteUrl {
+    pub(crate) fn parse(url: &str) -> Result<Self> {
+        if url.contains(['?', '#']) {
+            bail!("The site-url {:?} can't have a query or fragment", url);
+        }
+        let (origin, path) = match url.find("://") {
+            Some(at) => {
+                let (scheme, rest) = (&url[..at], &url[at + 3..]);
+                let (host, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+                let valid_scheme = scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                    && scheme
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c));
+                if !valid_scheme || host.is_empty() {
+                    bail!("The site-url {:?} isn't a valid url", url);
+                }
+                (
+                    Some(format!("{}://{}", scheme.to_ascii_lowercase(), host)),
+                    path,
+                )
+            }
+            None => (None, url),
+        };
+
+        let mut normal = String::from("/");
+        for segment in path.split('/').filter(|s| !s.is_empty() && *s != ".") {
+            if segment == ".." {
+                bail!("The site-url {:?} can't have `..` in it's path", url);
+            }
+            normal.push_str(segment);
+            normal.push('/');
+        }
+        Ok(Self {
+            origin,
+            path: normal,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Serialize, Deserialize, Default, Eq)]
+#[serde(default, rename_all = "kebab-case")]
+pub(crate) struct PdfConf {
+    pub(crate) paper_size: PaperSize,
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Serialize, Deserialize, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum PaperSize {
+    #[default]
+    A4,
+    Letter,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Serialize, Deserialize, Default, Eq)]
+#[serde(default, rename_all = "kebab-case")]
+pub(crate) struct EpubConf {
+    /// The books language, as a BCP 47 tag. Defaults to `en`.
+    pub(crate) language: Option<String>,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Serialize, Deserialize, Default, Eq)]
+#[serde(default, rename_all = "kebab-case")]
+pub(crate) struct MarkdownConf {
+    pub(crate) layout: MarkdownLayout,
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Serialize, Deserialize, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum MarkdownLayout {
+    /// A file per chapter, where they were in the book.
+    #[default]
+    Tree,
+    /// All the chapters in one file.
+    Single,
+}
+
+/// Which chapters of each book are man pages, as `[man.<book>]` tables of
+/// chapter paths (relative to the book).
+#[derive(Clone, Debug, Hash, PartialEq, Serialize, Deserialize, Default, Eq)]
+pub(crate) struct ManConfRepr {
+    #[serde(flatten)]
+    pub(crate) books: BTreeMap<String, BTreeMap<PathBuf, ManPage>>,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Serialize, Deserialize, Default, Eq)]
+pub(crate) struct ManConf {
+    /// Absolute paths of chapters, and the page to make of them.
+    pub(crate) pages: BTreeMap<PathBuf, ManPage>,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct ManPage {
+    /// The manual section, eg 1 for commands and 5 for file formats.
+    #[serde(default = "default_man_section")]
+    pub(crate) section: u8,
+    /// Defaults to the chapters file name.
+    #[serde(default)]
+    pub(crate) name: Option<String>,
+}
+
+/// What to do about markdown files in a book that aren't in it's summary.
+#[derive(Clone, Debug, Hash, PartialEq, Serialize, Deserialize, Default, Eq)]
+#[serde(default, rename_all = "kebab-case")]
+pub(crate) struct OrphansConf {
+    pub(crate) level: OrphanLevel,
+    /// Globs, relative to the book, for files that are meant to be left out.
+    /// They work like a .gitignore, so `*.inc.md` is anywhere in the book, and
+    /// `/*.inc.md` only at the top.
+    pub(crate) ignore: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Serialize, Deserialize, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum OrphanLevel {
+    #[default]
+    Warn,
+    /// Fail the build.
+    Error,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Serialize, Deserialize, Default, Eq)]
+#[serde(default, rename_all = "kebab-case")]
+pub(crate) struct BuildConfRepr {
+    /// Where everything is rendered to, relative to the xmark.toml. Defaults
+    /// to `_out`.
+    pub(crate) out_dir: Option<PathBuf>,
+    pub(crate) url_style: UrlStyle,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Serialize, Deserialize, Default, Eq)]
+pub(crate) struct BuildConf {
+    /// Absolute. Each renderer gets a directory in here.
+    pub(crate) out_dir: PathBuf,
+    pub(crate) url_style: UrlStyle,
+}
+
+/// How chapters are laid out in the html output.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Serialize, Deserialize, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum UrlStyle {
+    /// `foo.md` becomes `foo/index.html`, linked to as `foo`.
+    #[default]
+    Pretty,
+    /// `foo.md` becomes `foo.html`, for hosts that don't serve `index.html`
+    /// for a directory.
+    Flat,
+}
+
+fn default_man_section() -> u8 {
+    1
+}
+
+impl Default for ManPage {
+    fn default() -> Self {
+        Self {
+            section: default_man_section(),
+            name: None,
+        }
+    }
 }
 
 #[instrument]
 pub(crate) fn load(args: &cli::Args) -> Result<GlobalConf> {
+    hydrate(load_repr(args)?, args)
+}
+
+/// The xmark.toml, as it's written.
+#[instrument]
+pub(crate) fn load_repr(args: &cli::Args) -> Result<GlobalConfigRepr> {
     let conf = fs::read_to_string(args.dir.clone().join("xmark.toml"))
         .with_context(|| "Couldn't find xmark.toml")?;
-    let conf: GlobalConfigRepr = toml::from_str(&conf)?;
-
-    hydrate(conf, &args)
+    Ok(toml::from_str(&conf)?)
 }
 
 // Convert the disk format to a usable form
@@ -56,39 +406,198 @@ fn hydrate(gcr: GlobalConfigRepr, args: &cli::Args) -> Result<GlobalConf> {
         books: gcr
             .books
             .iter()
-            .map(|name| {
-                let location = args.dir.join(name);
-                let summary_location = location.join("SUMMARY.md");
-                let mut summary = parse_summary(
-                    &fs::read_to_string(&summary_location)
-                        .wrap_err_with(|| format!("Couldn't open {:?}", summary_location))?,
-                )?;
-
-                if args.create {
-                    create_missing(&location, &summary)?;
-                }
-
-                let fix_chap_loc = |chap: &mut Chapter| {
-                    if let Some(loc) = chap.location.as_deref() {
-                        chap.location = Some(location.join(loc));
-                    }
-                };
+            .map(|name| load_book(args, name))
+            .collect::<Result<_>>()?,
+        html: hydrate_html(gcr.html, &gcr.books, args)?,
+        redirects: gcr
+            .redirects
+            .into_iter()
+            .map(|(from, to)| (args.dir.join(from), args.dir.join(to)))
+            .collect(),
+        pdf: gcr.pdf,
+        epub: gcr.epub,
+        markdown: gcr.markdown,
+        man: match gcr.man {
+            Some(man) => Some(hydrate_man(man, &gcr.books, args)?),
+            None => None,
+        },
+        orphans: gcr.orphans,
+        build: hydrate_build(gcr.build, &gcr.books, args)?,
+    })
+}
 
-                summary.prefix_chapters.iter_mut().for_each(fix_chap_loc);
+fn hydrate_html(html: HtmlConfRepr, books: &[String], args: &cli::Args) -> Result<HtmlConf> {
+    if let Some(template) = &html.edit_url_template {
+        if !template.contains("{path}") {
+            bail!(
+                "The edit-url-template {:?} needs a `{{path}}` for the chapter",
+                template
+            );
+        }
+    }
+    let theme = hydrate_theme(&Theme::default(), html.theme).wrap_err("Invalid [html]")?;
+    let mut book_themes = BTreeMap::new();
+    for (book, repr) in html.books {
+        if !books.contains(&book) {
+            bail!("[html.books.{}] isn't one of the books", book);
+        }
+        let book_theme = hydrate_theme(&theme, repr)
+            .wrap_err_with(|| format!("Invalid [html.books.{}]", book))?;
+        book_themes.insert(args.dir.join(book), book_theme);
+    }
 
-                summary.suffix_chapters.iter_mut().for_each(fix_chap_loc);
+    Ok(HtmlConf {
+        site_url: match &html.site_url {
+            Some(url) => SiteUrl::parse(url)?,
+            None => SiteUrl::default(),
+        },
+        relative_links: html.relative_links,
+        offline: html.offline,
+        robots: html.robots,
+        feed: html.feed,
+        git_repository_url: html.git_repository_url,
+        git_repository_icon: html.git_repository_icon,
+        edit_url_template: html.edit_url_template,
+        theme,
+        books: book_themes,
+    })
+}
 
-                summary.numbered_chapters.iter_mut().for_each(|chap| {
-                    chap.map_mut(fix_chap_loc);
-                });
+/// Fill in `repr` from `base`, and check the themes exist.
+fn hydrate_theme(base: &Theme, repr: ThemeConfRepr) -> Result<Theme> {
+    let theme = Theme {
+        language: repr.language.unwrap_or_else(|| base.language.clone()),
+        default_theme: repr
+            .default_theme
+            .unwrap_or_else(|| base.default_theme.clone()),
+        preferred_dark_theme: repr
+            .preferred_dark_theme
+            .unwrap_or_else(|| base.preferred_dark_theme.clone()),
+    };
+    let themes = themes();
+    for (key, name) in &[
+        ("default-theme", &theme.default_theme),
+        ("preferred-dark-theme", &theme.preferred_dark_theme),
+    ] {
+        if !themes.contains(&name.as_str()) {
+            bail!(
+                "Unknown {} {:?}, it must be one of {}",
+                key,
+                name,
+                themes.join(", ")
+            );
+        }
+    }
+    Ok(theme)
+}
 
-                Ok(Book { location, summary })
-            })
-            .collect::<Result<_>>()?,
-        html: gcr.html,
+/// The output is deleted on each build, so it can't have any sources in it.
+pub(crate) fn hydrate_build(
+    build: BuildConfRepr,
+    books: &[String],
+    args: &cli::Args,
+) -> Result<BuildConf> {
+    let out_dir = links::normalize(
+        &args
+            .dir
+            .join(build.out_dir.unwrap_or_else(|| "_out".into())),
+    );
+    if links::normalize(&args.dir).starts_with(&out_dir) {
+        bail!("The out-dir {:?} can't contain the site", out_dir);
+    }
+    for book in books {
+        if links::normalize(&args.dir.join(book)).starts_with(&out_dir) {
+            bail!(
+                "The out-dir {:?} can't contain the book {:?}",
+                out_dir,
+                book
+            );
+        }
+    }
+    Ok(BuildConf {
+        out_dir,
+        url_style: build.url_style,
     })
 }
 
+/// Read the `SUMMARY.md` for the book `name`, and make it's chapter paths
+/// absolute.
+#[instrument]
+pub(crate) fn load_book(args: &cli::Args, name: &str) -> Result<Book> {
+    let location = args.dir.join(name);
+    let summary_location = location.join("SUMMARY.md");
+    let mut summary = parse_summary(
+        &fs::read_to_string(&summary_location)
+            .wrap_err_with(|| format!("Couldn't open {:?}", summary_location))?,
+    )
+    .wrap_err_with(|| format!("Couldn't parse {:?}", summary_location))?;
+
+    if args.create {
+        create_missing(&location, &summary)?;
+    }
+
+    let fix_chap_loc = |chap: &mut Chapter| {
+        if let Some(loc) = chap.location.as_deref() {
+            chap.location = Some(location.join(loc));
+        }
+    };
+
+    summary.prefix_chapters.iter_mut().for_each(fix_chap_loc);
+
+    summary.suffix_chapters.iter_mut().for_each(fix_chap_loc);
+
+    summary.numbered_chapters.iter_mut().for_each(|chap| {
+        chap.map_mut(fix_chap_loc);
+    });
+
+    Ok(Book { location, summary })
+}
+
+fn hydrate_man(man: ManConfRepr, books: &[String], args: &cli::Args) -> Result<ManConf> {
+    let mut pages = BTreeMap::new();
+    for (book, chapters) in man.books {
+        if !books.contains(&book) {
+            bail!("[man.{}] isn't one of the books", book);
+        }
+        let location = args.dir.join(&book);
+        pages.extend(
+            chapters
+                .into_iter()
+                .map(|(path, page)| (location.join(path), page)),
+        );
+    }
+
+    // The name ends up in the file name, so it can't go anywhere else, or
+    // write over another page.
+    let mut names = BTreeMap::new();
+    for (path, page) in &pages {
+        let name = match &page.name {
+            Some(name) => name.clone(),
+            None => path
+                .file_stem()
+                .map(|x| x.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        };
+        if name.is_empty() || name == "." || name == ".." || name.contains(&['/', '\\'][..]) {
+            bail!(
+                "The man page name {:?} for {:?} isn't a file name",
+                name,
+                path
+            );
+        }
+        if let Some(other) = names.insert((name.clone(), page.section), path) {
+            bail!(
+                "{:?} and {:?} would both be the man page {}({})",
+                other,
+                path,
+                name,
+                page.section
+            );
+        }
+    }
+    Ok(ManConf { pages })
+}
+
 #[cfg(test)]
 mod tests {
     use insta::assert_yaml_snapshot;
@@ -109,12 +618,19 @@ mod tests {
         assert_eq!(
             conf,
             GlobalConfigRepr {
-                books: (&["book-1", "book-2", "book-3"])
+                books: ["book-1", "book-2", "book-3"]
                     .iter()
                     .copied()
                     .map(String::from)
                     .collect(),
-                html: HtmlConf { site_url: None }
+                html: HtmlConfRepr::default(),
+                redirects: Default::default(),
+                pdf: None,
+                epub: None,
+                markdown: None,
+                man: None,
+                orphans: Default::default(),
+                build: Default::default(),
             }
         );
 
@@ -127,12 +643,268 @@ mod tests {
         assert_eq!(
             conf,
             GlobalConfigRepr {
-                html: HtmlConf {
-                    site_url: Some("book".into())
+                html: HtmlConfRepr {
+                    site_url: Some("book".into()),
+                    ..Default::default()
                 },
                 ..Default::default()
             }
+        );
+
+        let inp = "books = []\n[html.robots]\ndisallow = ['drafts/']";
+        let conf: GlobalConfigRepr = toml::from_str(inp).unwrap();
+        assert_eq!(
+            conf.html.robots,
+            RobotsConf {
+                enabled: true,
+                disallow: vec!["drafts/".into()]
+            }
+        );
+
+        let inp = "books = []\n[html.feed]\nscope = 'site'";
+        let conf: GlobalConfigRepr = toml::from_str(inp).unwrap();
+        assert_eq!(
+            conf.html.feed,
+            Some(FeedConf {
+                scope: FeedScope::Site,
+                limit: 20,
+                title: None,
+            })
+        );
+
+        let inp = "books = []\n[redirects]\n'b/old.md' = 'b/new.md'";
+        let conf: GlobalConfigRepr = toml::from_str(inp).unwrap();
+        assert_eq!(
+            conf.redirects,
+            vec![("b/old.md".into(), "b/new.md".into())]
+                .into_iter()
+                .collect()
+        );
+
+        let inp = "books = []\n[pdf]\npaper-size = 'letter'";
+        let conf: GlobalConfigRepr = toml::from_str(inp).unwrap();
+        assert_eq!(
+            conf.pdf,
+            Some(PdfConf {
+                paper_size: PaperSize::Letter
+            })
+        );
+
+        let inp = "books = []\n[epub]";
+        let conf: GlobalConfigRepr = toml::from_str(inp).unwrap();
+        assert_eq!(conf.epub, Some(EpubConf { language: None }));
+
+        let inp = "books = []\n[markdown]\nlayout = 'single'";
+        let conf: GlobalConfigRepr = toml::from_str(inp).unwrap();
+        assert_eq!(
+            conf.markdown,
+            Some(MarkdownConf {
+                layout: MarkdownLayout::Single
+            })
+        );
+
+        let inp = "books = []\n[man.cli]\n'build.md' = {}\n'config.md' = { section = 5, name = 'xmark.toml' }";
+        let conf: GlobalConfigRepr = toml::from_str(inp).unwrap();
+        let pages = &conf.man.unwrap().books["cli"];
+        assert_eq!(
+            pages[&PathBuf::from("build.md")],
+            ManPage {
+                section: 1,
+                name: None
+            }
+        );
+        assert_eq!(
+            pages[&PathBuf::from("config.md")],
+            ManPage {
+                section: 5,
+                name: Some("xmark.toml".into())
+            }
+        );
+
+        let inp = "books = []\n[orphans]\nlevel = 'error'\nignore = ['drafts/**']";
+        let conf: GlobalConfigRepr = toml::from_str(inp).unwrap();
+        assert_eq!(
+            conf.orphans,
+            OrphansConf {
+                level: OrphanLevel::Error,
+                ignore: vec!["drafts/**".into()]
+            }
+        );
+
+        let inp = "books = []\n[build]\nout-dir = '../site'\nurl-style = 'flat'";
+        let conf: GlobalConfigRepr = toml::from_str(inp).unwrap();
+        assert_eq!(
+            conf.build,
+            BuildConfRepr {
+                out_dir: Some("../site".into()),
+                url_style: UrlStyle::Flat,
+            }
+        );
+    }
+
+    #[test]
+    fn out_dir_has_no_sources() {
+        let args = cli::Args {
+            dir: "/docs".into(),
+            ..Default::default()
+        };
+        let books = ["b".to_owned(), "html".to_owned()];
+        let build = |out_dir: &str| {
+            let repr = BuildConfRepr {
+                out_dir: Some(out_dir.into()),
+                ..Default::default()
+            };
+            hydrate_build(repr, &books, &args).map(|b| b.out_dir)
+        };
+        assert_eq!(build("../site").unwrap(), PathBuf::from("/site"));
+        assert_eq!(build("out/.").unwrap(), PathBuf::from("/docs/out"));
+        for bad in &[".", "..", "/", "b", "html/../b/.."] {
+            assert!(build(bad).is_err(), "{}", bad);
+        }
+        let err = build("b").unwrap_err().to_string();
+        assert!(err.contains("can't contain the book \"b\""), "{}", err);
+    }
+
+    #[test]
+    fn hydrate_man_pages() {
+        let args = cli::Args {
+            dir: "/docs".into(),
+            ..Default::default()
+        };
+        let man = ManConfRepr {
+            books: vec![(
+                "cli".to_owned(),
+                vec![("build.md".into(), ManPage::default())]
+                    .into_iter()
+                    .collect(),
+            )]
+            .into_iter()
+            .collect(),
+        };
+        let conf = hydrate_man(man.clone(), &["cli".to_owned()], &args).unwrap();
+        assert_eq!(
+            conf.pages.keys().collect::<Vec<_>>(),
+            vec![&PathBuf::from("/docs/cli/build.md")]
+        );
+        assert!(hydrate_man(man, &[], &args).is_err());
+
+        let man = |pages: &[(&str, u8, Option<&str>)]| ManConfRepr {
+            books: vec![(
+                "cli".to_owned(),
+                pages
+                    .iter()
+                    .map(|(path, section, name)| {
+                        let page = ManPage {
+                            section: *section,
+                            name: name.map(String::from),
+                        };
+                        (PathBuf::from(path), page)
+                    })
+                    .collect(),
+            )]
+            .into_iter()
+            .collect(),
+        };
+        let hydrate = |pages: &[(&str, u8, Option<&str>)]| {
+            hydrate_man(man(pages), &["cli".to_owned()], &args)
+        };
+        for bad in &["../xmark", "a/b", "a\\b", "..", ""] {
+            let err = hydrate(&[("build.md", 1, Some(bad))]).unwrap_err();
+            assert!(err.to_string().contains("isn't a file name"), "{}", err);
+        }
+        let err = hydrate(&[("build.md", 1, None), ("other.md", 1, Some("build"))]).unwrap_err();
+        assert!(err.to_string().contains("the man page build(1)"), "{}", err);
+        // The same name is fine in another section.
+        assert!(hydrate(&[("build.md", 1, None), ("other.md", 5, Some("build"))]).is_ok());
+    }
+
+    #[test]
+    fn site_urls() {
+        let parse = |url| {
+            let url = SiteUrl::parse(url).unwrap();
+            (url.origin, url.path)
+        };
+        assert_eq!(parse("/"), (None, "/".into()));
+        assert_eq!(parse("book"), (None, "/book/".into()));
+        assert_eq!(parse("/a//b/./c"), (None, "/a/b/c/".into()));
+        assert_eq!(
+            parse("https://host/docs/v2"),
+            (Some("https://host".into()), "/docs/v2/".into())
+        );
+        assert_eq!(
+            parse("HTTP://host:8080"),
+            (Some("http://host:8080".into()), "/".into())
+        );
+
+        for bad in &[
+            "https:///docs",
+            "://host",
+            "1x://host",
+            "/docs?v=2",
+            "/a/../b",
+        ] {
+            assert!(SiteUrl::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn hydrate_edit_url() {
+        let html = |template: &str| {
+            hydrate_html(
+                HtmlConfRepr {
+                    edit_url_template: Some(template.to_owned()),
+                    ..Default::default()
+                },
+                &[],
+                &Default::default(),
+            )
+        };
+        assert!(html("https://example.com/edit/main/{path}").is_ok());
+        assert!(html("https://example.com/edit/main/").is_err());
+    }
+
+    #[test]
+    fn hydrate_themes() {
+        let args = cli::Args {
+            dir: "/docs".into(),
+            ..Default::default()
+        };
+        let html = |toml: &str| {
+            let repr: GlobalConfigRepr = toml::from_str(toml).unwrap();
+            hydrate_html(repr.html, &["cli".to_owned()], &args)
+        };
+
+        let conf = html(
+            "books = []\n[html]\ndefault-theme = 'light'\nlanguage = 'de'\n\
+             [html.books.cli]\ndefault-theme = 'navy'\n",
         )
+        .unwrap();
+        let site = Theme {
+            language: "de".into(),
+            default_theme: "light".into(),
+            preferred_dark_theme: "coal".into(),
+        };
+        assert_eq!(conf.theme, site);
+        assert_eq!(
+            conf.theme(Path::new("/docs/cli")),
+            &Theme {
+                default_theme: "navy".into(),
+                ..site.clone()
+            }
+        );
+        assert_eq!(conf.theme(Path::new("/docs/other")), &site);
+        assert_eq!(themes(), ["ayu", "coal", "light", "navy", "rust"]);
+
+        let err = html("books = []\n[html]\ndefault-theme = 'brand'\n").unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            "Invalid [html]: Unknown default-theme \"brand\", it must be one of ayu, coal, light, navy, rust"
+        );
+        let err =
+            html("books = []\n[html.books.cli]\npreferred-dark-theme = 'dark'\n").unwrap_err();
+        assert!(format!("{:#}", err)
+            .starts_with("Invalid [html.books.cli]: Unknown preferred-dark-theme"));
+        assert!(html("books = []\n[html.books.nope]\n").is_err());
     }
 
     #[test]
@@ -145,6 +917,10 @@ mod tests {
             ..Default::default()
         };
         let gc = GlobalConf {
+            build: BuildConf {
+                out_dir: "/home/etc/bax/_out".into(),
+                url_style: UrlStyle::Pretty,
+            },
             ..Default::default()
         };
         assert_eq!(hydrate(gcr, &args).unwrap(), gc);
@@ -161,6 +937,7 @@ mod tests {
         assert_yaml_snapshot!(conf, {
             ".location" => insta::dynamic_redaction(manifest_dir_redacter),
             ".**.location" => insta::dynamic_redaction(manifest_dir_redacter),
+            ".build.out_dir" => insta::dynamic_redaction(manifest_dir_redacter),
         });
     }
 }
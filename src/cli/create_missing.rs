@@ -22,9 +22,7 @@ pub(crate) fn create_missing(src_dir: &Path, summary: &Summary) -> Result<()> {
         .chain(summary.numbered_chapters.iter().cloned())
         .collect();
 
-    while !items.is_empty() {
-        let link = items.pop().expect("already checked");
-
+    while let Some(link) = items.pop() {
         if let Some(ref location) = link.chapter.location {
             let filename = src_dir.join(location);
             if !filename.exists() {
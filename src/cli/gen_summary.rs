@@ -0,0 +1,329 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! `xmark gen-summary`: the opposite of `--create`, write a `SUMMARY.md` for
+//! a directory of markdown.
+//!
+//! Every `.md` file is a chapter, named after it's first `# Heading`. A
+//! directory with a `README.md` is a chapter too, with everything else in it
+//! nested underneath. Directories without one don't have a page to nest
+//! under, so their chapters go at the same level as the directory.
+//! Hidden files, and ones starting with `_` (like `_out`), are skipped.
+use std::cmp::Ordering;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use eyre::{bail, Result, WrapErr};
+use pulldown_cmark::{Event, Parser, Tag};
+use tracing::{info, instrument};
+
+use super::scaffold::title_for;
+use super::summary::{escape, Chapter};
+use super::{GenSummary, SummaryOrder};
+use crate::content::front_matter;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Entry {
+    name: String,
+    /// Relative to the book.
+    path: PathBuf,
+    children: Vec<Entry>,
+}
+
+/// Something in a directory, which becomes some entries.
+struct Item {
+    file_name: String,
+    title: String,
+    entries: Vec<Entry>,
+}
+
+#[instrument]
+pub(crate) fn gen_summary(dir: &Path, opts: &GenSummary) -> Result<()> {
+    let book = dir.join(&opts.book);
+    let path = book.join("SUMMARY.md");
+    if path.exists() && !opts.force {
+        bail!("{:?} already exists, use --force to replace it", path);
+    }
+    let summary = summary(&book, opts.order)?;
+    fs::write(&path, summary).wrap_err_with(|| format!("Failed to create {:?}", path))?;
+    info!("Wrote {}", path.display());
+    Ok(())
+}
+
+/// The `SUMMARY.md` for the book in `book`.
+#[instrument]
+fn summary(book: &Path, order: SummaryOrder) -> Result<String> {
+    let readme = book.join("README.md");
+    let title = if readme.is_file() {
+        heading(&readme)?
+    } else {
+        None
+    };
+    let dir_name = book
+        .canonicalize()
+        .wrap_err_with(|| format!("Couldn't open {:?}", book))?
+        .file_name()
+        .map_or_else(|| "Book".into(), |x| x.to_string_lossy().into_owned());
+
+    let mut out = format!(
+        "# {}\n\n",
+        escape(title.as_deref().unwrap_or(&title_for(&dir_name)))
+    );
+    if readme.is_file() {
+        out.push_str(&format!(
+            "[{}](README.md)\n\n",
+            escape(title.as_deref().unwrap_or("Introduction"))
+        ));
+    }
+    write_entries(&mut out, &walk(book, Path::new(""), order)?, 0);
+    Ok(out)
+}
+
+fn walk(book: &Path, rel: &Path, order: SummaryOrder) -> Result<Vec<Entry>> {
+    let dir = book.join(rel);
+    let mut items = Vec::new();
+
+    for entry in fs::read_dir(&dir).wrap_err_with(|| format!("Couldn't open {:?}", dir))? {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        if file_name.starts_with('.')
+            || file_name.starts_with('_')
+            || file_name == "SUMMARY.md"
+            || file_name == "README.md"
+        {
+            continue;
+        }
+
+        let path = rel.join(&file_name);
+        if entry.file_type()?.is_dir() {
+            let children = walk(book, &path, order)?;
+            let readme = path.join("README.md");
+            if book.join(&readme).is_file() {
+                let name = chapter_name(&book.join(&readme), &file_name)?;
+                items.push(Item {
+                    file_name,
+                    title: name.clone(),
+                    entries: vec![Entry {
+                        name,
+                        path: readme,
+                        children,
+                    }],
+                });
+            } else if !children.is_empty() {
+                items.push(Item {
+                    title: title_for(&file_name),
+                    file_name,
+                    entries: children,
+                });
+            }
+        } else if Path::new(&file_name).extension().is_some_and(|x| x == "md") {
+            let stem = file_name.trim_end_matches(".md");
+            let name = chapter_name(&book.join(&path), stem)?;
+            items.push(Item {
+                file_name,
+                title: name.clone(),
+                entries: vec![Entry {
+                    name,
+                    path,
+                    children: Vec::new(),
+                }],
+            });
+        }
+    }
+
+    items.sort_by(|a, b| compare(order, a, b));
+    Ok(items.into_iter().flat_map(|item| item.entries).collect())
+}
+
+fn compare(order: SummaryOrder, a: &Item, b: &Item) -> Ordering {
+    match order {
+        SummaryOrder::Name => a.file_name.cmp(&b.file_name),
+        SummaryOrder::Natural => natural(&a.file_name, &b.file_name),
+        SummaryOrder::Title => a.title.to_lowercase().cmp(&b.title.to_lowercase()),
+    }
+    // Always be stable, whatever order the filesystem gives.
+    .then_with(|| a.file_name.cmp(&b.file_name))
+}
+
+/// Compare with runs of digits as numbers, so `ch2` comes before `ch10`.
+fn natural(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (a_digit, b_digit) = (starts_with_digit(a), starts_with_digit(b));
+        let a_len = run_len(a, a_digit);
+        let b_len = run_len(b, b_digit);
+        let (a_run, b_run) = (&a[..a_len], &b[..b_len]);
+
+        let ord = if a_digit && b_digit {
+            let (a_num, b_num) = (a_run.trim_start_matches('0'), b_run.trim_start_matches('0'));
+            a_num.len().cmp(&b_num.len()).then_with(|| a_num.cmp(b_num))
+        } else {
+            a_run.cmp(b_run)
+        };
+        if ord != Ordering::Equal || (a.is_empty() && b.is_empty()) {
+            return ord;
+        }
+        a = &a[a_len..];
+        b = &b[b_len..];
+    }
+}
+
+fn starts_with_digit(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_digit())
+}
+
+fn run_len(s: &str, digits: bool) -> usize {
+    s.find(|c: char| c.is_ascii_digit() != digits)
+        .unwrap_or(s.len())
+}
+
+/// The chapters first heading, or a name from it's file name.
+pub(super) fn chapter_name(path: &Path, file_stem: &str) -> Result<String> {
+    Ok(heading(path)?.unwrap_or_else(|| title_for(file_stem)))
+}
+
+/// The text of the first `# Heading`.
+fn heading(path: &Path) -> Result<Option<String>> {
+    let (_, body) = front_matter::read(path)?;
+    let mut text: Option<String> = None;
+    for ev in Parser::new(&body) {
+        match (ev, &mut text) {
+            (Event::Start(Tag::Heading(1)), _) => text = Some(String::new()),
+            (Event::End(Tag::Heading(1)), Some(text)) => return Ok(Some(text.trim().to_owned())),
+            (Event::Text(t), Some(text)) | (Event::Code(t), Some(text)) => text.push_str(&t),
+            _ => {}
+        }
+    }
+    Ok(None)
+}
+
+fn write_entries(out: &mut String, entries: &[Entry], depth: usize) {
+    for entry in entries {
+        let chapter = Chapter {
+            name: entry.name.clone(),
+            location: Some(entry.path.clone()),
+        };
+        out.push_str(&"    ".repeat(depth));
+        out.push_str(&format!("- {}\n", chapter));
+        write_entries(out, &entry.children, depth + 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::prelude::*;
+
+    use super::*;
+    use crate::cli::summary::parse_summary;
+
+    fn book() -> assert_fs::TempDir {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let files = [
+            ("README.md", "# My Book\n"),
+            ("ch10-end.md", "# The End\n"),
+            (
+                "ch2-middle.md",
+                "+++\ntitle = \"Ignored\"\n+++\n# `Middle` [bits]\n",
+            ),
+            ("ch1-start.md", "No heading\n"),
+            ("guide/README.md", "# Guide\n"),
+            ("guide/b.md", "# Zebra\n"),
+            ("guide/a.md", "# Aardvark\n"),
+            ("guide/deeper/README.md", "## Not a h1\n"),
+            ("guide/deeper/x y.md", "# Spaced\n"),
+            ("loose/one.md", "# One\n"),
+            ("empty/notes.txt", "Not markdown"),
+            ("_out/html.md", "# Output\n"),
+            (".hidden.md", "# Hidden\n"),
+        ];
+        for (path, contents) in &files {
+            temp.child(path).write_str(contents).unwrap();
+        }
+        temp
+    }
+
+    #[test]
+    fn natural_order() {
+        let temp = book();
+        let out = summary(temp.path(), SummaryOrder::Natural).unwrap();
+        insta::assert_snapshot!(out);
+
+        // And it parses to what we wanted.
+        let parsed = parse_summary(&out).unwrap();
+        assert_eq!(parsed.title, "My Book");
+        let names: Vec<_> = parsed
+            .numbered_chapters
+            .iter()
+            .map(|x| x.chapter.name.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            ["Ch1 Start", "Middle [bits]", "The End", "Guide", "One"]
+        );
+        let deeper = &parsed.numbered_chapters[3].nested_items[2];
+        assert_eq!(deeper.chapter.name, "Deeper");
+        assert_eq!(
+            deeper.nested_items[0].chapter.location,
+            Some("guide/deeper/x y.md".into())
+        );
+    }
+
+    #[test]
+    fn other_orders() {
+        let temp = book();
+        let by_name = summary(temp.path(), SummaryOrder::Name).unwrap();
+        let by_title = summary(temp.path(), SummaryOrder::Title).unwrap();
+        let firsts = |s: &str| {
+            s.lines()
+                .filter(|l| l.starts_with("- "))
+                .map(str::to_owned)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            firsts(&by_name),
+            [
+                "- [Ch1 Start](ch1-start.md)",
+                "- [The End](ch10-end.md)",
+                "- [Middle \\[bits\\]](ch2-middle.md)",
+                "- [Guide](guide/README.md)",
+                "- [One](loose/one.md)",
+            ]
+        );
+        assert_eq!(
+            firsts(&by_title),
+            [
+                "- [Ch1 Start](ch1-start.md)",
+                "- [Guide](guide/README.md)",
+                // `loose` has no README, so it goes by it's directory name.
+                "- [One](loose/one.md)",
+                "- [Middle \\[bits\\]](ch2-middle.md)",
+                "- [The End](ch10-end.md)",
+            ]
+        );
+    }
+
+    #[test]
+    fn natural_compare() {
+        assert_eq!(natural("ch2", "ch10"), Ordering::Less);
+        assert_eq!(natural("ch02", "ch2"), Ordering::Equal);
+        assert_eq!(natural("a", "b"), Ordering::Less);
+        assert_eq!(natural("ch1-b", "ch1-a"), Ordering::Greater);
+        assert_eq!(natural("ch1", "ch1-a"), Ordering::Less);
+    }
+
+    #[test]
+    fn doesnt_overwrite() {
+        let temp = book();
+        temp.child("SUMMARY.md").write_str("Mine").unwrap();
+        let mut opts = GenSummary {
+            book: ".".into(),
+            order: SummaryOrder::Natural,
+            force: false,
+        };
+        assert!(gen_summary(temp.path(), &opts).is_err());
+        temp.child("SUMMARY.md").assert("Mine");
+
+        opts.force = true;
+        gen_summary(temp.path(), &opts).unwrap();
+        temp.child("SUMMARY.md")
+            .assert(summary(temp.path(), SummaryOrder::Natural).unwrap());
+    }
+}
@@ -1,5 +1,5 @@
 // SPDX-License-Identifier: GPL-3.0-only
-use clap::Clap;
+use clap::{ArgEnum, Clap, Subcommand};
 use eyre::Result;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
@@ -9,12 +9,159 @@ pub(crate) mod config;
 pub(crate) mod summary;
 
 mod create_missing;
+pub(crate) mod gen_summary;
+pub(crate) mod move_chapter;
+pub(crate) mod scaffold;
+pub(crate) mod summary_edit;
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub(crate) struct Args {
     pub(crate) dir: PathBuf,
     pub(crate) create: bool,
     pub(crate) templates: Option<PathBuf>,
+    pub(crate) command: Command,
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Command {
+    /// Render the site (the default)
+    #[default]
+    Build,
+    /// Print everything xmark knows about the site, for other tools to use
+    Dump(Dump),
+    /// Start a new site in the directory
+    Init(Init),
+    /// Write a SUMMARY.md for a directory of markdown files
+    GenSummary(GenSummary),
+    /// Check the site for problems, without rendering it
+    Check,
+    /// Change a book's SUMMARY.md, keeping it's formatting
+    Summary(EditSummary),
+    /// Move a chapter's file, and fix the links to it
+    Mv(Mv),
+}
+
+#[derive(Clap, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Dump {
+    #[clap(long, arg_enum, default_value = "json")]
+    pub(crate) format: DumpFormat,
+}
+
+#[derive(Clap, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Init {
+    /// Directory of a book to create, can be given more than once
+    #[clap(long = "book")]
+    pub(crate) books: Vec<String>,
+    /// Don't ask anything, just use the defaults
+    #[clap(short, long)]
+    pub(crate) yes: bool,
+}
+
+#[derive(Clap, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct GenSummary {
+    /// The book's directory
+    pub(crate) book: PathBuf,
+    /// How to order the chapters in each directory
+    #[clap(long, arg_enum, default_value = "natural")]
+    pub(crate) order: SummaryOrder,
+    /// Replace an existing SUMMARY.md
+    #[clap(long)]
+    pub(crate) force: bool,
+}
+
+/// Paths are relative to the site, like in the xmark.toml's `[redirects]`.
+#[derive(Clap, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Mv {
+    /// The chapter's file
+    pub(crate) from: PathBuf,
+    /// Where to put it, in the same book
+    pub(crate) to: PathBuf,
+    /// Redirect the old page to the new one
+    #[clap(long)]
+    pub(crate) redirect: bool,
+}
+
+#[derive(Clap, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct EditSummary {
+    /// The book's directory
+    pub(crate) book: PathBuf,
+    #[clap(subcommand)]
+    pub(crate) edit: SummaryEdit,
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum SummaryEdit {
+    /// Add a chapter, creating it's file if needed
+    Add(AddChapter),
+    /// Move a chapter, and everything nested in it
+    Mv(MoveChapter),
+    /// Remove a chapter from the summary, but not it's file
+    Rm(RemoveChapter),
+    /// Change a chapter's name
+    Rename(RenameChapter),
+}
+
+/// Where to put a chapter. Chapters are given by their path in the book, or
+/// their name. Without either, it goes at the end of the numbered chapters.
+#[derive(Clap, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Placement {
+    /// Put it after this chapter, and anything nested in it
+    #[clap(long, conflicts_with = "under")]
+    pub(crate) after: Option<String>,
+    /// Nest it in this chapter, after anything already there
+    #[clap(long)]
+    pub(crate) under: Option<String>,
+}
+
+#[derive(Clap, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct AddChapter {
+    /// The chapter's file, relative to the book
+    pub(crate) path: PathBuf,
+    /// Defaults to the file's first heading, or it's name
+    #[clap(long)]
+    pub(crate) name: Option<String>,
+    #[clap(flatten)]
+    pub(crate) placement: Placement,
+}
+
+#[derive(Clap, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct MoveChapter {
+    pub(crate) chapter: String,
+    #[clap(flatten)]
+    pub(crate) placement: Placement,
+}
+
+#[derive(Clap, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct RemoveChapter {
+    pub(crate) chapter: String,
+    /// Remove the chapters nested in it too
+    #[clap(short, long)]
+    pub(crate) recursive: bool,
+}
+
+#[derive(Clap, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct RenameChapter {
+    pub(crate) chapter: String,
+    pub(crate) name: String,
+}
+
+#[derive(ArgEnum, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum SummaryOrder {
+    /// By file name
+    Name,
+    /// By file name, with numbers compared as numbers, so `ch2` is before `ch10`
+    Natural,
+    /// By chapter name
+    Title,
+}
+
+#[derive(ArgEnum, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum DumpFormat {
+    Json,
 }
 
 impl Args {
@@ -29,7 +176,12 @@ impl Args {
             mut dir,
             create,
             mut templates,
+            command,
         } = inner;
+        // A new site can go in a new directory.
+        if let Some(Command::Init(_)) = command {
+            std::fs::create_dir_all(&dir)?;
+        }
         dir = dir.canonicalize()?;
         templates = match templates {
             Some(p) => Some(p.canonicalize()?),
@@ -39,6 +191,7 @@ impl Args {
             dir,
             create,
             templates,
+            command: command.unwrap_or_default(),
         })
     }
 }
@@ -73,6 +226,8 @@ struct ArgsInner {
     pub(crate) create: bool,
     #[clap(short, long)]
     pub(crate) templates: Option<PathBuf>,
+    #[clap(subcommand)]
+    pub(crate) command: Option<Command>,
 }
 
 #[cfg(test)]
@@ -88,6 +243,7 @@ mod tests {
             dir: ".".into(),
             create: true,
             templates: None,
+            command: None,
         };
         let args = Args::parse_from(args).unwrap();
         // Yaml uses ~ for null
@@ -103,6 +259,7 @@ mod tests {
             create: true,
             // This needs to exist, as `.canonicalize` will read, in case it's a symblink
             templates: Some("./www".into()),
+            command: None,
         };
         let args = Args::parse_from(args).unwrap();
         assert_yaml_snapshot!(args, {
@@ -118,6 +275,7 @@ mod tests {
             dir: "/usr".into(),
             create: false,
             templates: None,
+            command: None,
         };
         let args = Args::parse_from(args).unwrap();
         assert_yaml_snapshot!(args, {
@@ -0,0 +1,321 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! `xmark mv`: move a chapter's file, and fix everything that points at it.
+//!
+//! That's it's entry in the `SUMMARY.md`, and relative links to it from the
+//! chapters of every book. The chapter's own links are fixed too, as they're
+//! relative to where it was. Optionally, the old path is added to the
+//! `[redirects]` in the `xmark.toml`, so old links to the site keep working.
+use std::collections::BTreeSet;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use eyre::{bail, eyre, Result, WrapErr};
+use pulldown_cmark::{Event, Options, Parser, Tag};
+use tracing::{info, instrument, warn};
+
+use super::summary::destination;
+use super::summary_edit::SummaryEditor;
+use super::{config, Args, Mv};
+use crate::content::{front_matter, links};
+
+#[instrument(skip(args))]
+pub(crate) fn mv(args: &Args, opts: &Mv) -> Result<()> {
+    let conf = config::load(args).context("Failed to load config")?;
+    let from = links::normalize(&args.dir.join(&opts.from));
+    let to = links::normalize(&args.dir.join(&opts.to));
+
+    let book = conf
+        .books
+        .iter()
+        .find(|b| chapters(b).any(|c| c == from))
+        .ok_or_else(|| eyre!("{:?} isn't a chapter in any book", opts.from))?;
+    let location = links::normalize(&book.location);
+    let rel_to = to.strip_prefix(&location).map_err(|_| {
+        eyre!(
+            "{:?} isn't in {:?}, chapters can only be moved within their book",
+            opts.to,
+            book.location
+        )
+    })?;
+    if to.exists() {
+        bail!("{:?} already exists", to);
+    }
+
+    // Work everything out before changing anything.
+    let summary_path = book.location.join("SUMMARY.md");
+    let src = fs::read_to_string(&summary_path)
+        .wrap_err_with(|| format!("Couldn't open {:?}", summary_path))?;
+    let mut editor =
+        SummaryEditor::new(&src).wrap_err_with(|| format!("Couldn't parse {:?}", summary_path))?;
+    let i = editor.find(&links::href(from.strip_prefix(&location)?))?;
+    editor.relocate(i, rel_to)?;
+
+    let mut rewritten = Vec::new();
+    let files: BTreeSet<_> = conf.books.iter().flat_map(chapters).collect();
+    for file in files.iter().filter(|f| f.is_file()) {
+        let new = if *file == from { &to } else { file };
+        let src = fs::read_to_string(file).wrap_err_with(|| format!("Couldn't open {:?}", file))?;
+        let out = rewrite(&src, file, new, (&from, &to));
+        if *file == from || out != src {
+            rewritten.push((new.clone(), out));
+        }
+    }
+
+    let xmark_toml = args.dir.join("xmark.toml");
+    let redirect = if opts.redirect {
+        let toml = fs::read_to_string(&xmark_toml)
+            .wrap_err_with(|| format!("Couldn't open {:?}", xmark_toml))?;
+        let (old, new) = (
+            links::href(from.strip_prefix(&args.dir)?),
+            links::href(to.strip_prefix(&args.dir)?),
+        );
+        Some(add_redirect(&toml, &old, &new)?)
+    } else {
+        None
+    };
+
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(&from, &to).wrap_err_with(|| format!("Couldn't move {:?} to {:?}", from, to))?;
+    info!("Moved {} to {}", from.display(), to.display());
+    for (path, src) in &rewritten {
+        fs::write(path, src).wrap_err_with(|| format!("Failed to create {:?}", path))?;
+        if *path != to {
+            info!("Updated links in {}", path.display());
+        }
+    }
+    fs::write(&summary_path, editor.source())
+        .wrap_err_with(|| format!("Failed to create {:?}", summary_path))?;
+    if let Some(toml) = redirect {
+        fs::write(&xmark_toml, toml)
+            .wrap_err_with(|| format!("Failed to create {:?}", xmark_toml))?;
+        info!("Added a redirect from {}", from.display());
+    }
+
+    for (old, target) in &conf.redirects {
+        if links::normalize(target) == from {
+            warn!(
+                "{:?} still redirects to {:?}, it should be changed to {:?}",
+                old, target, to
+            );
+        }
+    }
+    Ok(())
+}
+
+fn chapters(book: &config::Book) -> impl Iterator<Item = PathBuf> + '_ {
+    book.summary
+        .chapters()
+        .into_iter()
+        .filter_map(|c| c.location.as_deref().map(links::normalize))
+}
+
+/// Fix the links in the chapter `src`, which was at `old` and is now at
+/// `new`, for a chapter being `moved` from one place to another.
+fn rewrite(src: &str, old: &Path, new: &Path, moved: (&Path, &Path)) -> String {
+    let body = match front_matter::split(src) {
+        Ok((_, body)) => body,
+        Err(_) => src,
+    };
+    let skipped = src.len() - body.len();
+
+    let mut edits = Vec::new();
+    for (ev, range) in Parser::new_ext(body, Options::all()).into_offset_iter() {
+        let dest = match ev {
+            Event::Start(Tag::Link(_, dest, _)) | Event::Start(Tag::Image(_, dest, _)) => dest,
+            _ => continue,
+        };
+        let link = match links::resolve(old, &dest) {
+            Some(link) if !dest.starts_with('#') => link,
+            _ => continue,
+        };
+        let target = if link.path == moved.0 {
+            moved.1
+        } else if old.parent() == new.parent() {
+            continue;
+        } else {
+            &link.path
+        };
+
+        let mut href = links::href(&links::relative(new, target));
+        if dest.contains("%20") {
+            href = href.replace(' ', "%20");
+        }
+        if dest.starts_with("./") && !href.starts_with("../") {
+            href.insert_str(0, "./");
+        }
+        if let Some(fragment) = link.fragment {
+            href = format!("{}#{}", href, fragment);
+        }
+        if href == *dest {
+            continue;
+        }
+
+        match written(body, range, &dest) {
+            Some(at) => edits.push((skipped + at.start..skipped + at.end, destination(&href))),
+            None => warn!("Couldn't update the link to {:?} in {:?}", &*dest, old),
+        }
+    }
+
+    // Reference links share their definition.
+    edits.sort_by_key(|(at, _)| at.start);
+    edits.dedup_by_key(|(at, _)| at.start);
+    let mut out = src.to_owned();
+    for (at, href) in edits.into_iter().rev() {
+        out.replace_range(at, &href);
+    }
+    out
+}
+
+/// Where the destination of a link is written, either in it's `[text](here)`
+/// or a `[ref]: here` definition. Includes any `<>` around it.
+fn written(src: &str, link: Range<usize>, dest: &str) -> Option<Range<usize>> {
+    let text = &src[link.clone()];
+    let at = match text
+        .rfind("](")
+        .and_then(|at| text[at..].find(dest).map(|i| link.start + at + i))
+    {
+        Some(at) => at,
+        None => src.find(&format!("]: {}", dest))? + 3,
+    };
+
+    let (before, after) = (&src[..at], &src[at + dest.len()..]);
+    if before.ends_with('<') && after.starts_with('>') {
+        Some(at - 1..at + dest.len() + 1)
+    } else {
+        Some(at..at + dest.len())
+    }
+}
+
+/// Add `old = new` to the `[redirects]` in the `xmark.toml`.
+fn add_redirect(toml: &str, old: &str, new: &str) -> Result<String> {
+    let line = format!(
+        "{} = {}\n",
+        toml::Value::String(old.to_owned()),
+        toml::Value::String(new.to_owned())
+    );
+    let mut out = toml.to_owned();
+    let header = if out.starts_with("[redirects]\n") {
+        Some(0)
+    } else {
+        out.find("\n[redirects]\n").map(|at| at + 1)
+    };
+    match header {
+        // Right after the header, so it's in the table.
+        Some(at) => out.insert_str(at + "[redirects]\n".len(), &line),
+        None => {
+            if !out.is_empty() && !out.ends_with('\n') {
+                out.push('\n');
+            }
+            out.push_str("\n[redirects]\n");
+            out.push_str(&line);
+        }
+    }
+
+    let added = toml::from_str::<toml::Value>(&out)
+        .ok()
+        .and_then(|v| Some(v.get("redirects")?.get(old)?.as_str()? == new));
+    if added != Some(true) {
+        bail!(
+            "Couldn't add the redirect to the xmark.toml, put `{}` in it's [redirects] by hand",
+            line.trim()
+        );
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn moves_and_fixes_links() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let files = [
+            ("xmark.toml", "books = ['b', 'c']\n"),
+            (
+                "b/SUMMARY.md",
+                "# B\n\n- [A](./a.md)\n- [Old](./sub/old.md)\n    - [Next](sub/next.md)\n",
+            ),
+            (
+                "b/a.md",
+                "# A\n\nSee [old](./sub/old.md#top), [again](sub/old.md) and [ref][r].\n\n\
+                 [r]: sub/old.md\n\n[Not this](sub/next.md)\n",
+            ),
+            (
+                "b/sub/old.md",
+                "+++\ntitle = 'Old'\n+++\n# Top\n\n[Next](next.md), [A](../a.md), \
+                 ![pic](<my pic.png>), [self](old.md#top), [here](#top), \
+                 [web](https://example.com/old.md)\n",
+            ),
+            ("b/sub/next.md", "# Next\n\n[Back](old.md)\n"),
+            ("c/SUMMARY.md", "# C\n\n- [C](c.md)\n"),
+            ("c/c.md", "# C\n\n[Other book](../b/sub/old.md)\n"),
+        ];
+        for (path, contents) in &files {
+            temp.child(path).write_str(contents).unwrap();
+        }
+
+        let args = Args {
+            dir: temp.path().to_owned(),
+            ..Default::default()
+        };
+        let opts = Mv {
+            from: "b/sub/old.md".into(),
+            to: "b/new dir/new.md".into(),
+            redirect: true,
+        };
+        mv(&args, &opts).unwrap();
+
+        assert!(!temp.child("b/sub/old.md").path().exists());
+        temp.child("b/SUMMARY.md").assert(
+            "# B\n\n- [A](./a.md)\n- [Old](<./new dir/new.md>)\n    - [Next](sub/next.md)\n",
+        );
+        temp.child("b/a.md").assert(
+            "# A\n\nSee [old](<./new dir/new.md#top>), [again](<new dir/new.md>) and [ref][r].\n\n\
+             [r]: <new dir/new.md>\n\n[Not this](sub/next.md)\n",
+        );
+        temp.child("b/new dir/new.md").assert(
+            "+++\ntitle = 'Old'\n+++\n# Top\n\n[Next](../sub/next.md), [A](../a.md), \
+             ![pic](<../sub/my pic.png>), [self](new.md#top), [here](#top), \
+             [web](https://example.com/old.md)\n",
+        );
+        temp.child("b/sub/next.md")
+            .assert("# Next\n\n[Back](<../new dir/new.md>)\n");
+        temp.child("c/c.md")
+            .assert("# C\n\n[Other book](<../b/new dir/new.md>)\n");
+        temp.child("xmark.toml")
+            .assert("books = ['b', 'c']\n\n[redirects]\n\"b/sub/old.md\" = \"b/new dir/new.md\"\n");
+
+        // It's been moved, and can't leave the book.
+        assert!(mv(&args, &opts).is_err());
+        let opts = Mv {
+            from: "c/c.md".into(),
+            to: "b/c.md".into(),
+            redirect: false,
+        };
+        assert!(mv(&args, &opts).is_err());
+    }
+
+    #[test]
+    fn redirects_table() {
+        assert_eq!(
+            add_redirect(
+                "books = []\n\n[redirects]\n'x.md' = 'y.md'\n",
+                "a.md",
+                "b.md"
+            )
+            .unwrap(),
+            "books = []\n\n[redirects]\n\"a.md\" = \"b.md\"\n'x.md' = 'y.md'\n"
+        );
+        assert_eq!(
+            add_redirect("[redirects]\n", "a.md", "b.md").unwrap(),
+            "[redirects]\n\"a.md\" = \"b.md\"\n"
+        );
+        assert!(add_redirect("redirects = { 'x.md' = 'y.md' }\n", "a.md", "b.md").is_err());
+    }
+}
@@ -0,0 +1,287 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! `xmark init`: start a new site.
+//!
+//! This writes the `xmark.toml`, and for each book a `SUMMARY.md` and a
+//! `README.md` to be it's first page. It never overwrites anything, except to
+//! add `_out/` to the `.gitignore`.
+use std::fs;
+use std::io::{BufRead, Write};
+use std::path::{Component, Path, PathBuf};
+
+use eyre::{bail, Result, WrapErr};
+use tracing::instrument;
+
+use super::Init;
+
+/// A book to make, and what it's called.
+#[derive(Debug, Clone, PartialEq)]
+struct NewBook {
+    dir: String,
+    title: String,
+}
+
+#[instrument(skip(input, output))]
+pub(crate) fn init(
+    dir: &Path,
+    opts: &Init,
+    mut input: impl BufRead,
+    mut output: impl Write,
+) -> Result<()> {
+    let books = if opts.yes {
+        let dirs = if opts.books.is_empty() {
+            vec!["book".to_owned()]
+        } else {
+            opts.books.clone()
+        };
+        dirs.into_iter()
+            .map(|dir| NewBook {
+                title: title_for(&dir),
+                dir,
+            })
+            .collect()
+    } else {
+        ask(opts, &mut input, &mut output)?
+    };
+
+    let files = files(&books)?;
+
+    let existing: Vec<_> = files
+        .iter()
+        .map(|(path, _)| dir.join(path))
+        .filter(|path| path.exists())
+        .collect();
+    if !existing.is_empty() {
+        bail!("Refusing to overwrite existing files: {:?}", existing);
+    }
+
+    for (path, contents) in &files {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(&path, contents).wrap_err_with(|| format!("Failed to create {:?}", path))?;
+        writeln!(output, "Created {}", path.display())?;
+    }
+
+    let gitignore = dir.join(".gitignore");
+    let ignored = match fs::read_to_string(&gitignore) {
+        Ok(s) => s,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).wrap_err_with(|| format!("Couldn't open {:?}", gitignore)),
+    };
+    if !ignored
+        .lines()
+        .any(|l| matches!(l.trim(), "_out" | "_out/" | "/_out" | "/_out/"))
+    {
+        let mut new = ignored;
+        if !new.is_empty() && !new.ends_with('\n') {
+            new.push('\n');
+        }
+        new.push_str("/_out/\n");
+        fs::write(&gitignore, new).wrap_err_with(|| format!("Failed to create {:?}", gitignore))?;
+        writeln!(output, "Added /_out/ to {}", gitignore.display())?;
+    }
+
+    Ok(())
+}
+
+/// Ask for the books, with the ones from the command line as defaults.
+fn ask(opts: &Init, input: &mut impl BufRead, output: &mut impl Write) -> Result<Vec<NewBook>> {
+    let default = if opts.books.is_empty() {
+        "book".to_owned()
+    } else {
+        opts.books.join(" ")
+    };
+    let answer = prompt(
+        input,
+        output,
+        "Book directories, separated by spaces",
+        &default,
+    )?;
+
+    answer
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| {
+            let title = prompt(
+                input,
+                output,
+                &format!("Title for {}", dir),
+                &title_for(dir),
+            )?;
+            Ok(NewBook {
+                dir: dir.to_owned(),
+                title,
+            })
+        })
+        .collect()
+}
+
+fn prompt(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    question: &str,
+    default: &str,
+) -> Result<String> {
+    write!(output, "{} [{}]: ", question, default)?;
+    output.flush()?;
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    let answer = answer.trim();
+    Ok(if answer.is_empty() { default } else { answer }.to_owned())
+}
+
+/// The files to write, relative to the site.
+fn files(books: &[NewBook]) -> Result<Vec<(PathBuf, String)>> {
+    if books.is_empty() {
+        bail!("A site needs at least one book");
+    }
+
+    let mut toml = "books = [\n".to_owned();
+    let mut files = Vec::new();
+    for (i, book) in books.iter().enumerate() {
+        let path = Path::new(&book.dir);
+        if !path.components().all(|c| matches!(c, Component::Normal(_))) {
+            bail!(
+                "Book directory {:?} must be a relative path, without `..`",
+                book.dir
+            );
+        }
+        if books[..i].iter().any(|b| b.dir == book.dir) {
+            bail!("Book {:?} is listed twice", book.dir);
+        }
+
+        toml.push_str(&format!("    {},\n", toml::Value::from(book.dir.as_str())));
+        files.push((
+            path.join("SUMMARY.md"),
+            format!("# {}\n\n[Introduction](README.md)\n", book.title),
+        ));
+        files.push((
+            path.join("README.md"),
+            format!("# {}\n\nThis is the first page.\n", book.title),
+        ));
+    }
+    toml.push_str("]\n");
+    files.insert(0, ("xmark.toml".into(), toml));
+
+    Ok(files)
+}
+
+/// `user-guide` to `User Guide`.
+pub(super) fn title_for(dir: &str) -> String {
+    let name = Path::new(dir)
+        .file_name()
+        .map_or_else(|| dir.into(), |x| x.to_string_lossy());
+    name.split(|c: char| c == '-' || c == '_' || c.is_whitespace())
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use assert_fs::prelude::*;
+
+    use super::*;
+    use crate::cli::{self, config};
+    use crate::content::{Content, Dirs};
+
+    fn init_in(temp: &assert_fs::TempDir, opts: &Init, input: &str) -> Result<String> {
+        let mut output = Vec::new();
+        init(temp.path(), opts, Cursor::new(input), &mut output)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn non_interactive() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let opts = Init {
+            books: vec!["user-guide".into(), "api".into()],
+            yes: true,
+        };
+        init_in(&temp, &opts, "").unwrap();
+
+        temp.child("xmark.toml")
+            .assert("books = [\n    \"user-guide\",\n    \"api\",\n]\n");
+        temp.child("user-guide/SUMMARY.md")
+            .assert("# User Guide\n\n[Introduction](README.md)\n");
+        temp.child("api/README.md")
+            .assert("# Api\n\nThis is the first page.\n");
+        temp.child(".gitignore").assert("/_out/\n");
+
+        // And it's a site xmark can read.
+        let args = cli::Args {
+            dir: temp.path().to_owned(),
+            ..Default::default()
+        };
+        let conf = config::load(&args).unwrap();
+        let content = Content::new(&conf, &Dirs::new(&conf, &args)).unwrap();
+        assert_eq!(content.0[0].title, "User Guide");
+        assert_eq!(content.0[1].pages[0].name, "Introduction");
+    }
+
+    #[test]
+    fn interactive() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let opts = Init {
+            books: vec![],
+            yes: false,
+        };
+        let output = init_in(&temp, &opts, "guide, cookbook\n\nThe Cookbook\n").unwrap();
+
+        assert!(output.starts_with(
+            "Book directories, separated by spaces [book]: \
+             Title for guide [Guide]: Title for cookbook [Cookbook]: Created "
+        ));
+        temp.child("guide/SUMMARY.md")
+            .assert("# Guide\n\n[Introduction](README.md)\n");
+        temp.child("cookbook/SUMMARY.md")
+            .assert("# The Cookbook\n\n[Introduction](README.md)\n");
+    }
+
+    #[test]
+    fn doesnt_overwrite() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        temp.child("book/README.md").write_str("Mine").unwrap();
+        temp.child(".gitignore").write_str("target").unwrap();
+        let opts = Init {
+            books: vec![],
+            yes: true,
+        };
+
+        assert!(init_in(&temp, &opts, "").is_err());
+        temp.child("book/README.md").assert("Mine");
+        assert!(!temp.child("xmark.toml").path().exists());
+        temp.child(".gitignore").assert("target");
+
+        fs::remove_file(temp.child("book/README.md").path()).unwrap();
+        init_in(&temp, &opts, "").unwrap();
+        temp.child(".gitignore").assert("target\n/_out/\n");
+    }
+
+    #[test]
+    fn bad_books() {
+        assert!(files(&[]).is_err());
+        let book = |dir: &str| NewBook {
+            dir: dir.into(),
+            title: "T".into(),
+        };
+        assert!(files(&[book("../up")]).is_err());
+        assert!(files(&[book("/abs")]).is_err());
+        assert!(files(&[book("a"), book("a")]).is_err());
+    }
+
+    #[test]
+    fn titles() {
+        assert_eq!(title_for("user-guide"), "User Guide");
+        assert_eq!(title_for("docs/api_reference"), "Api Reference");
+        assert_eq!(title_for("book"), "Book");
+    }
+}
@@ -1,13 +1,16 @@
 // SPDX-License-Identifier: GPL-3.0-only
-use eyre::{bail, eyre, Error, Result, WrapErr};
+use eyre::{eyre, Result, WrapErr};
 use memchr::{self, Memchr};
 use pulldown_cmark::{self, Event, Tag};
 use serde::{Deserialize, Serialize};
 use std::fmt::{self, Display, Formatter};
 use std::iter::FromIterator;
-use std::ops::{Deref, DerefMut};
+use std::mem;
+use std::ops::{Deref, DerefMut, Range};
 use std::path::PathBuf;
-use tracing::{debug, instrument, trace, warn};
+use tracing::{debug, instrument, trace};
+
+use crate::content::links;
 
 /// Parse the text from a `SUMMARY.md` file into a sort of "recipe" to be
 /// used when loading a book from disk.
@@ -70,6 +73,79 @@ pub(crate) struct Summary {
     pub(crate) suffix_chapters: Vec<Chapter>,
 }
 
+impl Summary {
+    /// Every chapter, in the order they're in the file.
+    pub(crate) fn chapters(&self) -> Vec<&Chapter> {
+        fn walk<'a>(link: &'a Link, out: &mut Vec<&'a Chapter>) {
+            out.push(&link.chapter);
+            for i in &link.nested_items {
+                walk(i, out);
+            }
+        }
+
+        let mut out: Vec<_> = self.prefix_chapters.iter().collect();
+        for link in &self.numbered_chapters {
+            walk(link, &mut out);
+        }
+        out.extend(&self.suffix_chapters);
+        out
+    }
+
+    /// The line each of [`Self::chapters`] is on in `src`, the `SUMMARY.md`
+    /// this was parsed from.
+    pub(crate) fn chapter_lines(&self, src: &str) -> Option<Vec<usize>> {
+        let mut in_heading = false;
+        let mut lines = Vec::new();
+        for (ev, range) in pulldown_cmark::Parser::new(src).into_offset_iter() {
+            match ev {
+                Event::Start(Tag::Heading(_)) => in_heading = true,
+                Event::End(Tag::Heading(_)) => in_heading = false,
+                Event::Start(Tag::Link(..)) if !in_heading => {
+                    lines.push(src[..range.start].matches('\n').count() + 1)
+                }
+                _ => {}
+            }
+        }
+        // If they don't line up, we'd rather say nothing than the wrong line.
+        Some(lines).filter(|l| l.len() == self.chapters().len())
+    }
+}
+
+/// Write it back out as a `SUMMARY.md`.
+///
+/// This parses back to the same `Summary`, but anything that isn't in it,
+/// like part titles and comments, is lost.
+impl Display for Summary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        fn write_links(f: &mut Formatter<'_>, links: &[Link], depth: usize) -> fmt::Result {
+            for link in links {
+                writeln!(f, "{}- {}", "    ".repeat(depth), link.chapter)?;
+                write_links(f, &link.nested_items, depth + 1)?;
+            }
+            Ok(())
+        }
+
+        writeln!(f, "# {}", escape(&self.title))?;
+        if !self.prefix_chapters.is_empty() {
+            writeln!(f)?;
+            for chapter in &self.prefix_chapters {
+                writeln!(f, "{}", chapter)?;
+            }
+        }
+        if !self.numbered_chapters.is_empty() {
+            writeln!(f)?;
+            write_links(f, &self.numbered_chapters, 0)?;
+        }
+        if !self.suffix_chapters.is_empty() {
+            writeln!(f)?;
+            for chapter in &self.suffix_chapters {
+                writeln!(f, "{}", chapter)?;
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default, Hash)]
 pub(crate) struct Chapter {
     pub(crate) name: String,
@@ -77,6 +153,38 @@ pub(crate) struct Chapter {
     pub(crate) location: Option<PathBuf>,
 }
 
+/// As a link in a `SUMMARY.md`.
+impl Display for Chapter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let dest = match &self.location {
+            Some(path) => destination(&links::href(path)),
+            None => String::new(),
+        };
+        write!(f, "[{}]({})", escape(&self.name), dest)
+    }
+}
+
+/// Make a link's destination safe to put in `[...](here)`.
+pub(crate) fn destination(href: &str) -> String {
+    if href.contains(|c: char| c.is_whitespace() || c == '(' || c == ')') {
+        format!("<{}>", href)
+    } else {
+        href.to_owned()
+    }
+}
+
+/// Make a name safe to put in a link.
+pub(crate) fn escape(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    for c in name.chars() {
+        if matches!(c, '\\' | '[' | ']' | '<' | '>' | '*' | '_' | '`') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
 /// A struct representing an entry in the `SUMMARY.md`, possibly with nested
 /// entries.
 ///
@@ -96,6 +204,7 @@ impl Link {
         }
     }
 
+    #[allow(dead_code)]
     pub(crate) fn try_map<E>(&self, f: impl Fn(&Chapter) -> Result<(), E> + Copy) -> Result<(), E> {
         f(&self.chapter)?;
         for i in &self.nested_items {
@@ -130,7 +239,10 @@ impl Link {
 pub(crate) struct SummaryParser<'a> {
     src: &'a str,
     stream: pulldown_cmark::OffsetIter<'a>,
-    offset: usize,
+    /// Where the last event from `next_event` was.
+    span: Range<usize>,
+    /// Everything wrong so far, so we can report it all at once.
+    problems: Vec<Problem>,
 
     /// We can't actually put an event back into the `OffsetIter` stream, so instead we store it
     /// here until somebody calls `next_event` again.
@@ -182,25 +294,26 @@ impl<'a> SummaryParser<'a> {
         SummaryParser {
             src: text,
             stream: pulldown_parser,
-            offset: 0,
+            span: 0..0,
+            problems: Vec::new(),
             back: None,
         }
     }
 
     /// Get the current line and column to give the user more useful error
-    /// messages.
+    /// messages, both 1 based.
     fn current_location(&self) -> (usize, usize) {
-        let previous_text = self.src[..self.offset].as_bytes();
+        let previous_text = &self.src.as_bytes()[..self.span.start];
         let line = Memchr::new(b'\n', previous_text).count() + 1;
-        let start_of_line = memchr::memrchr(b'\n', previous_text).unwrap_or(0);
-        let col = self.src[start_of_line..self.offset].chars().count();
+        let start_of_line = memchr::memrchr(b'\n', previous_text).map_or(0, |i| i + 1);
+        let col = self.src[start_of_line..self.span.start].chars().count() + 1;
 
         (line, col)
     }
 
     /// Parse the text the `SummaryParser` was created with.
     fn parse(mut self) -> Result<Summary> {
-        let title = self.parse_title()?;
+        let title = self.parse_title();
 
         let prefix_chapters = self
             .parse_affix(true)
@@ -212,6 +325,10 @@ impl<'a> SummaryParser<'a> {
             .parse_affix(false)
             .with_context(|| "There was an error parsing the suffix chapters")?;
 
+        if !self.problems.is_empty() {
+            return Err(SummaryError(self.problems).into());
+        }
+
         Ok(Summary {
             title,
             prefix_chapters,
@@ -237,16 +354,24 @@ impl<'a> SummaryParser<'a> {
                         // of the numbered section.
                         self.back(ev);
                         break;
-                    } else {
-                        bail!(self.parse_error("Suffix chapters cannot be followed by a list"));
                     }
+                    let (what, tag) = match ev {
+                        Event::Start(tag @ Tag::List(..)) => ("a list", tag),
+                        Event::Start(tag) => ("a part title", tag),
+                        _ => unreachable!(),
+                    };
+                    self.problem(
+                        format!("Suffix chapters cannot be followed by {}", what),
+                        "Suffix chapters have to be at the end. Move this above them, \
+                         or make them list items",
+                    );
+                    self.skip_to_end(&tag);
                 }
                 Some(Event::Start(Tag::Link(_type, href, _title))) => {
-                    let link = self.parse_link(href.to_string())?;
-                    if !link.nested_items.is_empty() {
-                        bail!(self.parse_error("Cannot have nested chapted in prefix.postfix"));
-                    }
-                    assert!(link.section_number == None);
+                    let link = self.parse_link(href.to_string());
+                    // Affixes are never in a list, so can't be nested.
+                    assert!(link.nested_items.is_empty());
+                    assert!(link.section_number.is_none());
                     items.push(link.chapter);
                 }
                 Some(_) => {}
@@ -298,7 +423,7 @@ impl<'a> SummaryParser<'a> {
     }
 
     /// Finishes parsing a link once the `Event::Start(Tag::Link(..))` has been opened.
-    fn parse_link(&mut self, href: String) -> Result<Link> {
+    fn parse_link(&mut self, href: String) -> Link {
         let href = href.replace("%20", " ");
         let link_content = collect_events!(self.stream, end Tag::Link(..));
         let name = stringify_events(link_content);
@@ -309,11 +434,11 @@ impl<'a> SummaryParser<'a> {
             Some(PathBuf::from(href))
         };
 
-        Ok(Link {
+        Link {
             chapter: Chapter { name, location },
             section_number: None,
             nested_items: Vec::new(),
-        })
+        }
     }
 
     /// Parse the numbered chapters.
@@ -347,7 +472,7 @@ impl<'a> SummaryParser<'a> {
                 }
                 Some(ev @ Event::Start(Tag::List(..))) => {
                     self.back(ev);
-                    let mut bunch_of_items = self.parse_nested_numbered(&root_number)?;
+                    let mut bunch_of_items = self.parse_nested_numbered(root_number)?;
 
                     // if we've resumed after something like a rule the root sections
                     // will be numbered from 1. We need to manually go back and update
@@ -393,7 +518,7 @@ impl<'a> SummaryParser<'a> {
     fn next_event(&mut self) -> Option<Event<'a>> {
         let next = self.back.take().or_else(|| {
             self.stream.next().map(|(ev, range)| {
-                self.offset = range.start;
+                self.span = range;
                 ev
             })
         });
@@ -410,8 +535,9 @@ impl<'a> SummaryParser<'a> {
         loop {
             match self.next_event() {
                 Some(Event::Start(Tag::Item)) => {
-                    let item = self.parse_nested_item(parent, items.len())?;
-                    items.push(item);
+                    if let Some(item) = self.parse_nested_item(parent, items.len()) {
+                        items.push(item);
+                    }
                 }
                 Some(Event::Start(Tag::List(..))) => {
                     // Skip this tag after comment bacause it is not nested.
@@ -438,16 +564,18 @@ impl<'a> SummaryParser<'a> {
         Ok(items)
     }
 
+    /// Parse an item, once it's `Event::Start(Tag::Item)` has been opened. If
+    /// it's not a link, this records the problem and skips the whole item.
     fn parse_nested_item(
         &mut self,
         parent: &SectionNumber,
         num_existing_items: usize,
-    ) -> Result<Link> {
+    ) -> Option<Link> {
         loop {
             match self.next_event() {
                 Some(Event::Start(Tag::Paragraph)) => continue,
                 Some(Event::Start(Tag::Link(_type, href, _title))) => {
-                    let mut link = self.parse_link(href.to_string())?;
+                    let mut link = self.parse_link(href.to_string());
 
                     let mut number = parent.clone();
                     number.0.push(num_existing_items as u32 + 1);
@@ -464,42 +592,141 @@ impl<'a> SummaryParser<'a> {
 
                     link.section_number = Some(number);
 
-                    return Ok(link);
+                    return Some(link);
                 }
                 other => {
-                    warn!("Expected a start of a link, actually got {:?}", other);
-                    bail!(self.parse_error(
-                        "The link items for nested chapters must only contain a hyperlink"
-                    ));
+                    debug!("Expected a start of a link, actually got {:?}", other);
+                    self.problem(
+                        "The link items for nested chapters must only contain a hyperlink",
+                        "Each item should be just a link, like `- [Name](file.md)`, \
+                         or `- [Name]()` for a draft",
+                    );
+                    if !matches!(other, None | Some(Event::End(Tag::Item))) {
+                        self.skip_to_end(&Tag::Item);
+                    }
+                    return None;
                 }
             }
         }
     }
 
-    fn parse_error<D: Display>(&self, msg: D) -> Error {
-        let (line, col) = self.current_location();
-        eyre!(
-            "failed to parse SUMMARY.md line {}, column {}: {}",
+    /// Record a problem with the last event, to report once we've parsed
+    /// everything.
+    fn problem(&mut self, message: impl Into<String>, hint: &str) {
+        let (line, column) = self.current_location();
+        let start = self.span.start;
+        let line_start = self.src[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = self.src[start..]
+            .find('\n')
+            .map_or(self.src.len(), |i| start + i);
+        let end = self.span.end.clamp(start, line_end);
+
+        self.problems.push(Problem {
             line,
-            col,
-            msg
-        )
+            column,
+            source: self.src[line_start..line_end].trim_end().to_owned(),
+            len: self.src[start..end].trim_end().chars().count().max(1),
+            message: message.into(),
+            hint: hint.to_owned(),
+        });
+    }
+
+    /// Skip to the end of `tag`, which has just been started, including any
+    /// more of it nested inside.
+    fn skip_to_end(&mut self, tag: &Tag<'_>) {
+        let mut depth = 1;
+        while let Some(ev) = self.next_event() {
+            match ev {
+                Event::Start(t) if mem::discriminant(&t) == mem::discriminant(tag) => depth += 1,
+                Event::End(t) if mem::discriminant(&t) == mem::discriminant(tag) => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
     }
 
     /// Try to parse the title line.
-    fn parse_title(&mut self) -> Result<String> {
+    fn parse_title(&mut self) -> String {
         match self.next_event() {
             Some(Event::Start(Tag::Heading(1))) => {
                 debug!("Found a h1 in the SUMMARY");
 
                 let tags = collect_events!(self.stream, end Tag::Heading(1));
-                Ok(stringify_events(tags))
+                stringify_events(tags)
+            }
+            other => {
+                self.problem(
+                    "Could not parse title",
+                    "A SUMMARY.md starts with the book's title, like `# My Book`",
+                );
+                // Carry on, to find anything else that's wrong.
+                if let Some(ev) = other {
+                    self.back(ev);
+                }
+                String::new()
             }
-            _ => bail!(self.parse_error("Could not parse title")),
         }
     }
 }
 
+/// Something wrong in a `SUMMARY.md`, and where.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Problem {
+    /// 1 based.
+    pub(crate) line: usize,
+    /// 1 based, in chars.
+    pub(crate) column: usize,
+    /// The line it's on.
+    pub(crate) source: String,
+    /// How many chars of `source` it is.
+    pub(crate) len: usize,
+    pub(crate) message: String,
+    /// How to fix it.
+    pub(crate) hint: String,
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let gutter = " ".repeat(self.line.to_string().len());
+        // Keep tabs, so the caret lines up however they're shown.
+        let indent: String = self
+            .source
+            .chars()
+            .take(self.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        writeln!(f, "error: {}", self.message)?;
+        writeln!(f, "{}--> SUMMARY.md:{}:{}", gutter, self.line, self.column)?;
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", self.line, self.source)?;
+        writeln!(f, "{} | {}{}", gutter, indent, "^".repeat(self.len))?;
+        write!(f, "{} = hint: {}", gutter, self.hint)
+    }
+}
+
+/// Everything wrong with a `SUMMARY.md`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SummaryError(pub(crate) Vec<Problem>);
+
+impl Display for SummaryError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (i, problem) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, "\n\n")?;
+            }
+            write!(f, "{}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for SummaryError {}
+
 fn update_section_numbers(sections: &mut [Link], level: usize, by: u32) {
     for link in sections {
         if let Some(ref mut number) = link.section_number {
@@ -517,8 +744,7 @@ fn get_last_link(links: &mut [Link]) -> Result<(usize, &mut Link)> {
         .iter_mut()
         .enumerate()
         //.filter_map(|(i, item)| item.maybe_link_mut().map(|l| (i, l)))
-        .rev()
-        .next()
+        .next_back()
         .ok_or_else(||
             eyre!("Unable to get last link because the list of SummaryItems doesn't contain any Links")
             )
@@ -600,7 +826,7 @@ mod tests {
         let should_be = String::from("Summary");
 
         let mut parser = SummaryParser::new(src);
-        let got = parser.parse_title().unwrap();
+        let got = parser.parse_title();
 
         assert_eq!(got, should_be);
     }
@@ -611,7 +837,7 @@ mod tests {
         let should_be = String::from("My Awesome Summary");
 
         let mut parser = SummaryParser::new(src);
-        let got = parser.parse_title().unwrap();
+        let got = parser.parse_title();
 
         assert_eq!(got, should_be);
     }
@@ -636,12 +862,10 @@ mod tests {
             Chapter {
                 name: String::from("First"),
                 location: Some(PathBuf::from("./first.md")),
-                ..Default::default()
             },
             Chapter {
                 name: String::from("Second"),
                 location: Some(PathBuf::from("./second.md")),
-                ..Default::default()
             },
         ];
 
@@ -655,9 +879,27 @@ mod tests {
         let src = "[First](./first.md)\n- [Second](./second.md)\n";
         let mut parser = SummaryParser::new(src);
 
-        let got = parser.parse_affix(false);
+        parser.parse_affix(false).unwrap();
 
-        assert!(got.is_err());
+        assert_eq!(parser.problems.len(), 1);
+        assert_eq!(
+            parser.problems[0].message,
+            "Suffix chapters cannot be followed by a list"
+        );
+    }
+
+    #[test]
+    fn reports_every_problem() {
+        let src = "[Intro](intro.md)\n\n\
+                   - [One](one.md)\n  \
+                   - two.md\n\
+                   - [Three](three.md)\n\n\
+                   [End](end.md)\n\n\
+                   - [Too late](late.md)\n    - [Nested](nested.md)\n";
+        let err = parse_summary(src).unwrap_err();
+        let err = err.downcast_ref::<SummaryError>().unwrap();
+        assert_eq!(err.0.len(), 3);
+        insta::assert_snapshot!(err.to_string());
     }
 
     #[test]
@@ -667,7 +909,6 @@ mod tests {
             chapter: Chapter {
                 name: String::from("First"),
                 location: Some(PathBuf::from("./first.md")),
-                ..Default::default()
             },
             ..Default::default()
         };
@@ -680,7 +921,7 @@ mod tests {
             other => panic!("Unreachable, {:?}", other),
         };
 
-        let got = parser.parse_link(href).unwrap();
+        let got = parser.parse_link(href);
         assert_eq!(got, should_be);
     }
 
@@ -691,7 +932,6 @@ mod tests {
             chapter: Chapter {
                 name: String::from("First"),
                 location: Some(PathBuf::from("./first.md")),
-                ..Default::default()
             },
 
             section_number: Some(SectionNumber(vec![1])),
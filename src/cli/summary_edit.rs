@@ -0,0 +1,815 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! `xmark summary`: change a `SUMMARY.md`, without rewriting it.
+//!
+//! `Summary` doesn't keep part titles, comments or how things are indented,
+//! so rather than writing it back out, this edits the source around the links
+//! it was parsed from. Every edit is checked by parsing the result, and if
+//! that doesn't give the chapters we wanted, the whole file is written out
+//! from the `Summary` instead, losing the formatting.
+use std::fs;
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+
+use eyre::{bail, Result, WrapErr};
+use pulldown_cmark::{Event, Parser, Tag};
+use tracing::{info, instrument, warn};
+
+use super::gen_summary::chapter_name;
+use super::scaffold::title_for;
+use super::summary::{destination, escape, parse_summary, Chapter, Link, SectionNumber, Summary};
+use super::{EditSummary, Placement, SummaryEdit};
+use crate::content::links;
+
+#[instrument]
+pub(crate) fn edit(dir: &Path, opts: &EditSummary) -> Result<()> {
+    let book = dir.join(&opts.book);
+    let path = book.join("SUMMARY.md");
+    let src = fs::read_to_string(&path).wrap_err_with(|| format!("Couldn't open {:?}", path))?;
+    let mut editor =
+        SummaryEditor::new(&src).wrap_err_with(|| format!("Couldn't parse {:?}", path))?;
+
+    match &opts.edit {
+        SummaryEdit::Add(add) => {
+            let file = book.join(&add.path);
+            let stem = add.path.file_stem().unwrap_or_default().to_string_lossy();
+            let name = match &add.name {
+                Some(name) => name.clone(),
+                None if file.is_file() => chapter_name(&file, &stem)?,
+                None => title_for(&stem),
+            };
+            let at = editor.position(&add.placement)?;
+            editor.insert(
+                Chapter {
+                    name: name.clone(),
+                    location: Some(add.path.clone()),
+                },
+                at,
+            )?;
+
+            if !file.exists() {
+                if let Some(parent) = file.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(&file, format!("# {}\n", name))
+                    .wrap_err_with(|| format!("Failed to create {:?}", file))?;
+                info!("Created {}", file.display());
+            }
+        }
+        SummaryEdit::Mv(mv) => {
+            let i = editor.find(&mv.chapter)?;
+            let at = editor.position(&mv.placement)?;
+            editor.move_to(i, at)?;
+        }
+        SummaryEdit::Rm(rm) => {
+            let i = editor.find(&rm.chapter)?;
+            for chapter in editor.remove(i, rm.recursive)? {
+                info!("Removed {:?}", chapter.name);
+            }
+        }
+        SummaryEdit::Rename(rename) => {
+            let i = editor.find(&rename.chapter)?;
+            editor.rename(i, &rename.name)?;
+        }
+    }
+
+    fs::write(&path, editor.source()).wrap_err_with(|| format!("Failed to create {:?}", path))?;
+    info!("Wrote {}", path.display());
+    Ok(())
+}
+
+/// A chapter, flattened out of the tree.
+#[derive(Debug, Clone, PartialEq)]
+struct Entry {
+    chapter: Chapter,
+    /// How many lists it's in, so 0 for prefix and suffix chapters.
+    depth: usize,
+}
+
+/// Where an entry's link is in the source.
+#[derive(Debug, Clone)]
+struct Span {
+    /// The `[Name](path)`.
+    link: Range<usize>,
+    /// The lines it's on, with the newline.
+    lines: Range<usize>,
+}
+
+/// Where to put a chapter, by the index of another one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Position {
+    /// After it, and anything nested in it.
+    After(usize),
+    /// As it's last nested chapter.
+    Under(usize),
+    /// After the last numbered chapter.
+    End,
+}
+
+/// Where a position is in the source and the entries.
+#[derive(Debug)]
+struct Slot {
+    offset: usize,
+    index: usize,
+    depth: usize,
+    /// To put before the new lines, so they start a list.
+    lead: &'static str,
+    /// What goes before the link on it's line, like `  - `.
+    prefix: String,
+}
+
+/// A `SUMMARY.md`, and where each chapter is in it.
+///
+/// Chapters are refered to by their index in [`Summary::chapters`].
+#[derive(Debug)]
+pub(crate) struct SummaryEditor {
+    src: String,
+    title: String,
+    entries: Vec<Entry>,
+    spans: Vec<Span>,
+}
+
+impl SummaryEditor {
+    pub(crate) fn new(src: &str) -> Result<Self> {
+        let mut src = src.to_owned();
+        if !src.ends_with('\n') {
+            src.push('\n');
+        }
+        let summary = parse_summary(&src)?;
+        let entries = flatten(&summary);
+
+        // The same links as the parser, in the same order.
+        let mut spans = Vec::new();
+        let mut depths = Vec::new();
+        let (mut depth, mut in_heading) = (0, false);
+        for (ev, range) in Parser::new(&src).into_offset_iter() {
+            match ev {
+                Event::Start(Tag::List(_)) => depth += 1,
+                Event::End(Tag::List(_)) => depth -= 1,
+                Event::Start(Tag::Heading(_)) => in_heading = true,
+                Event::End(Tag::Heading(_)) => in_heading = false,
+                Event::Start(Tag::Link(..)) if !in_heading => {
+                    let start = src[..range.start].rfind('\n').map_or(0, |i| i + 1);
+                    let end = src[range.end..]
+                        .find('\n')
+                        .map_or(src.len(), |i| range.end + i + 1);
+                    spans.push(Span {
+                        link: range,
+                        lines: start..end,
+                    });
+                    depths.push(depth);
+                }
+                _ => {}
+            }
+        }
+        if depths != entries.iter().map(|e| e.depth).collect::<Vec<_>>() {
+            bail!("Couldn't tell where every chapter is, so it can't be edited");
+        }
+
+        Ok(Self {
+            src,
+            title: summary.title,
+            entries,
+            spans,
+        })
+    }
+
+    /// The new `SUMMARY.md`.
+    pub(crate) fn source(&self) -> &str {
+        &self.src
+    }
+
+    /// A chapter, by it's path or name.
+    pub(crate) fn find(&self, chapter: &str) -> Result<usize> {
+        let path = normalise(Path::new(chapter));
+        let matching = |f: &dyn Fn(&Chapter) -> bool| -> Vec<usize> {
+            (0..self.entries.len())
+                .filter(|&i| f(&self.entries[i].chapter))
+                .collect()
+        };
+        let mut found = matching(&|c| c.location.as_deref().map(normalise).as_ref() == Some(&path));
+        if found.is_empty() {
+            found = matching(&|c| c.name == chapter);
+        }
+
+        match found[..] {
+            [i] => Ok(i),
+            [] => bail!("There's no chapter {:?} in the summary", chapter),
+            _ => bail!("More than one chapter is {:?}", chapter),
+        }
+    }
+
+    pub(crate) fn position(&self, placement: &Placement) -> Result<Position> {
+        Ok(match (&placement.after, &placement.under) {
+            (Some(_), Some(_)) => bail!("A chapter can't be both after and under another"),
+            (Some(after), None) => Position::After(self.find(after)?),
+            (None, Some(under)) => Position::Under(self.find(under)?),
+            (None, None) => Position::End,
+        })
+    }
+
+    pub(crate) fn insert(&mut self, chapter: Chapter, at: Position) -> Result<()> {
+        let slot = self.slot(at)?;
+        let mut src = self.src.clone();
+        src.insert_str(
+            slot.offset,
+            &format!("{}{}{}\n", slot.lead, slot.prefix, chapter),
+        );
+
+        let mut entries = self.entries.clone();
+        entries.insert(
+            slot.index,
+            Entry {
+                chapter,
+                depth: slot.depth,
+            },
+        );
+        self.update(src, entries)
+    }
+
+    /// Move the chapter `i`, and everything nested in it.
+    pub(crate) fn move_to(&mut self, i: usize, at: Position) -> Result<()> {
+        let last = self.last_nested(i);
+        let anchor = match at {
+            Position::After(t) | Position::Under(t) => Some(t),
+            Position::End => self.last_numbered(),
+        };
+        if anchor.is_some_and(|t| (i..=last).contains(&t)) {
+            bail!(
+                "Can't move {:?} next to itself, or into something nested in it",
+                self.entries[i].chapter.name
+            );
+        }
+        let slot = self.slot(at)?;
+        if slot.depth == 0 && last > i {
+            bail!(
+                "{:?} has chapters nested in it, so can't be a prefix or suffix chapter",
+                self.entries[i].chapter.name
+            );
+        }
+
+        let block = format!("{}{}", slot.lead, self.reindent(i, last, &slot.prefix));
+        let removed = self.lines(i, last);
+        let offset = if slot.offset >= removed.end {
+            slot.offset - removed.len()
+        } else {
+            slot.offset.min(removed.start)
+        };
+        let mut src = self.src.clone();
+        src.replace_range(removed, "");
+        src.insert_str(offset, &block);
+
+        let mut entries = self.entries.clone();
+        let old_depth = entries[i].depth;
+        let moved: Vec<_> = entries
+            .drain(i..=last)
+            .map(|mut e| {
+                e.depth = e.depth + slot.depth - old_depth;
+                e
+            })
+            .collect();
+        let index = if slot.index > last {
+            slot.index - moved.len()
+        } else {
+            slot.index
+        };
+        entries.splice(index..index, moved);
+        self.update(src, entries)
+    }
+
+    /// Remove the chapter `i`, and returns what was removed.
+    pub(crate) fn remove(&mut self, i: usize, recursive: bool) -> Result<Vec<Chapter>> {
+        let last = self.last_nested(i);
+        if last > i && !recursive {
+            bail!(
+                "{:?} has chapters nested in it, use --recursive to remove them too",
+                self.entries[i].chapter.name
+            );
+        }
+
+        let mut src = self.src.clone();
+        src.replace_range(self.lines(i, last), "");
+        let mut entries = self.entries.clone();
+        let removed = entries.drain(i..=last).map(|e| e.chapter).collect();
+        self.update(src, entries)?;
+        Ok(removed)
+    }
+
+    pub(crate) fn rename(&mut self, i: usize, name: &str) -> Result<()> {
+        let mut entries = self.entries.clone();
+        entries[i].chapter.name = name.to_owned();
+
+        let span = self.spans[i].link.clone();
+        let link = &self.src[span.clone()];
+        // Keep the destination as it was written.
+        let new = match link.rfind("](") {
+            Some(at) if link.ends_with(')') => format!("[{}]{}", escape(name), &link[at + 1..]),
+            _ => entries[i].chapter.to_string(),
+        };
+        let mut src = self.src.clone();
+        src.replace_range(span, &new);
+        self.update(src, entries)
+    }
+
+    /// Point a chapter at a different file, relative to the book.
+    pub(crate) fn relocate(&mut self, i: usize, location: &Path) -> Result<()> {
+        let span = self.spans[i].link.clone();
+        let link = &self.src[span.clone()];
+        // Keep the name as it was written, and any `./`.
+        let at = link.rfind("](");
+        let dot = match at {
+            Some(at) if link[at + 2..].trim_start_matches('<').starts_with("./") => ".",
+            _ => "",
+        };
+        let location = Path::new(dot).join(location);
+        let mut entries = self.entries.clone();
+        entries[i].chapter.location = Some(location.clone());
+
+        let new = match at {
+            Some(at) => format!(
+                "{}({})",
+                &link[..at + 1],
+                destination(&links::href(&location))
+            ),
+            None => entries[i].chapter.to_string(),
+        };
+        let mut src = self.src.clone();
+        src.replace_range(span, &new);
+        self.update(src, entries)
+    }
+
+    /// Use the edited source, if it has the chapters we expect. Otherwise
+    /// write them out from scratch.
+    fn update(&mut self, src: String, entries: Vec<Entry>) -> Result<()> {
+        let parsed = parse_summary(&src).map(|s| flatten(&s));
+        if parsed.ok().as_ref() == Some(&entries) {
+            *self = Self::new(&src)?;
+        } else {
+            warn!("Couldn't edit the SUMMARY.md in place, so it's been rewritten without it's formatting");
+            *self = Self::new(&unflatten(&self.title, &entries)?.to_string())?;
+        }
+        Ok(())
+    }
+
+    fn slot(&self, at: Position) -> Result<Slot> {
+        let at = match at {
+            Position::End => match self.last_numbered() {
+                Some(t) => Position::After(t),
+                // Start the list.
+                None => {
+                    return Ok(Slot {
+                        offset: self.src.len(),
+                        index: self.entries.len(),
+                        depth: 1,
+                        lead: if self.src.ends_with("\n\n") { "" } else { "\n" },
+                        prefix: "- ".to_owned(),
+                    })
+                }
+            },
+            at => at,
+        };
+
+        let (t, depth) = match at {
+            Position::After(t) => (t, self.entries[t].depth),
+            Position::Under(t) if self.entries[t].depth == 0 => bail!(
+                "{:?} is a prefix or suffix chapter, so can't have chapters nested in it",
+                self.entries[t].chapter.name
+            ),
+            Position::Under(t) => (t, self.entries[t].depth + 1),
+            Position::End => unreachable!(),
+        };
+        let last = self.last_nested(t);
+        let prefix = if depth == 0 {
+            String::new()
+        } else if depth == self.entries[t].depth {
+            self.line_prefix(t).to_owned()
+        } else if last > t {
+            // Like the chapters already there.
+            self.line_prefix(t + 1).to_owned()
+        } else {
+            let parent = self.line_prefix(t);
+            let marker = parent.trim_start();
+            let step = self.indent_step().unwrap_or_else(|| marker.chars().count());
+            format!("{}{}{}", indent(parent), " ".repeat(step), marker)
+        };
+
+        Ok(Slot {
+            offset: self.spans[last].lines.end,
+            index: last + 1,
+            depth,
+            lead: "",
+            prefix,
+        })
+    }
+
+    /// The lines of `first..=last`, and a blank line if they're all that's
+    /// between two.
+    fn lines(&self, first: usize, last: usize) -> Range<usize> {
+        let (start, end) = (self.spans[first].lines.start, self.spans[last].lines.end);
+        let (before, after) = (&self.src[..start], &self.src[end..]);
+        if !before.ends_with("\n\n") {
+            start..end
+        } else if after.starts_with('\n') {
+            start..end + 1
+        } else if after.is_empty() {
+            start - 1..end
+        } else {
+            start..end
+        }
+    }
+
+    /// Indent `first..=last` to go after `prefix`, which replaces the text
+    /// before `first`'s link.
+    fn reindent(&self, first: usize, last: usize, prefix: &str) -> String {
+        let old = indent(self.line_prefix(first));
+        let new = indent(prefix);
+        let text = &self.src[self.spans[first].link.start..self.spans[last].lines.end];
+
+        let mut out = prefix.to_owned();
+        for (n, line) in text.split_inclusive('\n').enumerate() {
+            match line.strip_prefix(old) {
+                Some(rest) if n > 0 && !line.trim().is_empty() => {
+                    out.push_str(new);
+                    out.push_str(rest);
+                }
+                _ => out.push_str(line),
+            }
+        }
+        out
+    }
+
+    /// How much more nested chapters are indented than their parent, going
+    /// by the first one.
+    fn indent_step(&self) -> Option<usize> {
+        (0..self.entries.len()).find_map(|i| {
+            let depth = self.entries[i].depth;
+            let parent = (0..i).rev().find(|&p| self.entries[p].depth + 1 == depth)?;
+            let step = indent(self.line_prefix(i)).len() - indent(self.line_prefix(parent)).len();
+            Some(step).filter(|_| depth > 1)
+        })
+    }
+
+    /// The text before the link, on it's line.
+    fn line_prefix(&self, i: usize) -> &str {
+        &self.src[self.spans[i].lines.start..self.spans[i].link.start]
+    }
+
+    /// The last entry nested in `i`, or `i` if there's none.
+    fn last_nested(&self, i: usize) -> usize {
+        let depth = self.entries[i].depth;
+        if depth == 0 {
+            return i;
+        }
+        self.entries[i + 1..]
+            .iter()
+            .position(|e| e.depth <= depth)
+            .map_or(self.entries.len() - 1, |n| i + n)
+    }
+
+    fn last_numbered(&self) -> Option<usize> {
+        self.entries.iter().rposition(|e| e.depth == 1)
+    }
+}
+
+fn indent(s: &str) -> &str {
+    &s[..s.len() - s.trim_start().len()]
+}
+
+/// Without any `./`, so `./a.md` is `a.md`.
+fn normalise(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| *c != Component::CurDir)
+        .collect()
+}
+
+fn flatten(summary: &Summary) -> Vec<Entry> {
+    fn walk(link: &Link, depth: usize, out: &mut Vec<Entry>) {
+        out.push(Entry {
+            chapter: link.chapter.clone(),
+            depth,
+        });
+        for i in &link.nested_items {
+            walk(i, depth + 1, out);
+        }
+    }
+
+    let affix = |chapter: &Chapter| Entry {
+        chapter: chapter.clone(),
+        depth: 0,
+    };
+    let mut out: Vec<_> = summary.prefix_chapters.iter().map(affix).collect();
+    for link in &summary.numbered_chapters {
+        walk(link, 1, &mut out);
+    }
+    out.extend(summary.suffix_chapters.iter().map(affix));
+    out
+}
+
+/// The `Summary` with these chapters.
+fn unflatten(title: &str, entries: &[Entry]) -> Result<Summary> {
+    fn nest(entries: &[Entry], i: &mut usize, depth: usize, parent: &SectionNumber) -> Vec<Link> {
+        let mut links = Vec::new();
+        while entries.get(*i).is_some_and(|e| e.depth == depth) {
+            let mut number = parent.clone();
+            number.0.push(links.len() as u32 + 1);
+            let chapter = entries[*i].chapter.clone();
+            *i += 1;
+            let nested_items = nest(entries, i, depth + 1, &number);
+            links.push(Link {
+                chapter,
+                nested_items,
+                section_number: Some(number),
+            });
+        }
+        links
+    }
+
+    let start = entries
+        .iter()
+        .position(|e| e.depth > 0)
+        .unwrap_or(entries.len());
+    let end = entries
+        .iter()
+        .rposition(|e| e.depth > 0)
+        .map_or(start, |i| i + 1);
+    let mut i = start;
+    let numbered_chapters = nest(&entries[..end], &mut i, 1, &SectionNumber::default());
+    if i != end {
+        bail!("Numbered chapters can't have prefix or suffix chapters between them");
+    }
+
+    let chapters = |entries: &[Entry]| entries.iter().map(|e| e.chapter.clone()).collect();
+    Ok(Summary {
+        title: title.to_owned(),
+        prefix_chapters: chapters(&entries[..start]),
+        numbered_chapters,
+        suffix_chapters: chapters(&entries[end..]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::prelude::*;
+
+    use super::*;
+    use crate::cli::{AddChapter, RenameChapter};
+
+    const SRC: &str = "# My Book
+
+[Intro](intro.md)
+
+# Part One
+
+* [One](one.md)
+    * [One A](one/a.md)
+    * [One B](one/b.md)
+        * [Deep](one/deep.md)
+* [Two](<two words.md> \"Title\")
+
+<!-- Comments stay -->
+
+# Part Two
+
+* [Three](three.md)
+* [Draft]()
+
+[Credits](credits.md)
+";
+
+    fn editor() -> SummaryEditor {
+        SummaryEditor::new(SRC).unwrap()
+    }
+
+    fn after(src: &str, from: &str, to: &str) -> String {
+        src.replacen(from, to, 1)
+    }
+
+    fn chapter(name: &str, path: &str) -> Chapter {
+        Chapter {
+            name: name.into(),
+            location: Some(path.into()),
+        }
+    }
+
+    #[test]
+    fn find() {
+        let ed = editor();
+        assert_eq!(ed.find("intro.md").unwrap(), 0);
+        assert_eq!(ed.find("./one/a.md").unwrap(), 2);
+        assert_eq!(ed.find("two words.md").unwrap(), 5);
+        assert_eq!(ed.find("Draft").unwrap(), 7);
+        assert!(ed.find("nope.md").is_err());
+    }
+
+    #[test]
+    fn insert() {
+        let mut ed = editor();
+        ed.insert(chapter("Four", "four.md"), Position::End)
+            .unwrap();
+        assert_eq!(
+            ed.source(),
+            after(SRC, "* [Draft]()\n", "* [Draft]()\n* [Four](four.md)\n")
+        );
+
+        let mut ed = editor();
+        ed.insert(chapter("After One", "x.md"), Position::After(1))
+            .unwrap();
+        assert_eq!(
+            ed.source(),
+            after(SRC, "* [Two]", "* [After One](x.md)\n* [Two]")
+        );
+
+        // Without any nested chapters, it goes by how others are indented.
+        let mut ed = editor();
+        ed.insert(chapter("Five [5]", "five.md"), Position::Under(6))
+            .unwrap();
+        assert_eq!(
+            ed.source(),
+            after(
+                SRC,
+                "* [Three](three.md)\n",
+                "* [Three](three.md)\n    * [Five \\[5\\]](five.md)\n"
+            )
+        );
+
+        let mut ed = editor();
+        ed.insert(chapter("Preface", "preface.md"), Position::After(0))
+            .unwrap();
+        assert_eq!(
+            ed.source(),
+            after(SRC, "(intro.md)\n", "(intro.md)\n[Preface](preface.md)\n")
+        );
+        assert!(ed.insert(chapter("X", "x.md"), Position::Under(0)).is_err());
+    }
+
+    #[test]
+    fn start_a_list() {
+        let mut ed = SummaryEditor::new("# Book\n\n[Intro](intro.md)").unwrap();
+        ed.insert(chapter("One", "one.md"), Position::End).unwrap();
+        assert_eq!(
+            ed.source(),
+            "# Book\n\n[Intro](intro.md)\n\n- [One](one.md)\n"
+        );
+        ed.insert(chapter("Nested", "nested.md"), Position::Under(1))
+            .unwrap();
+        assert_eq!(
+            ed.source(),
+            "# Book\n\n[Intro](intro.md)\n\n- [One](one.md)\n  - [Nested](nested.md)\n"
+        );
+    }
+
+    #[test]
+    fn move_to() {
+        // Up a level, with it's children.
+        let mut ed = editor();
+        ed.move_to(3, Position::After(5)).unwrap();
+        assert_eq!(
+            ed.source(),
+            SRC.replace(
+                "    * [One B](one/b.md)\n        * [Deep](one/deep.md)\n",
+                ""
+            )
+            .replace(
+                "\"Title\")\n",
+                "\"Title\")\n* [One B](one/b.md)\n    * [Deep](one/deep.md)\n"
+            )
+        );
+
+        // Into another part, and back.
+        let mut ed = editor();
+        ed.move_to(2, Position::Under(7)).unwrap();
+        assert_eq!(
+            ed.source(),
+            SRC.replace("    * [One A](one/a.md)\n", "")
+                .replace("* [Draft]()\n", "* [Draft]()\n    * [One A](one/a.md)\n")
+        );
+        ed.move_to(ed.find("One A").unwrap(), Position::Under(1))
+            .unwrap();
+        assert_eq!(
+            ed.source(),
+            SRC.replace("    * [One A](one/a.md)\n", "").replace(
+                "        * [Deep](one/deep.md)\n",
+                "        * [Deep](one/deep.md)\n    * [One A](one/a.md)\n"
+            )
+        );
+
+        // A suffix chapter becoming numbered.
+        let mut ed = editor();
+        ed.move_to(8, Position::After(6)).unwrap();
+        assert_eq!(
+            ed.source(),
+            SRC.replace("\n[Credits](credits.md)\n", "")
+                .replace("(three.md)\n", "(three.md)\n* [Credits](credits.md)\n")
+        );
+
+        let mut ed = editor();
+        assert!(ed.move_to(1, Position::Under(4)).is_err());
+        assert!(ed.move_to(1, Position::After(1)).is_err());
+        assert!(ed.move_to(1, Position::After(8)).is_err());
+        assert_eq!(ed.source(), SRC);
+    }
+
+    #[test]
+    fn remove() {
+        let mut ed = editor();
+        assert!(ed.remove(1, false).is_err());
+        let removed = ed.remove(3, true).unwrap();
+        assert_eq!(
+            removed,
+            vec![chapter("One B", "one/b.md"), chapter("Deep", "one/deep.md")]
+        );
+        assert_eq!(
+            ed.source(),
+            SRC.replace(
+                "    * [One B](one/b.md)\n        * [Deep](one/deep.md)\n",
+                ""
+            )
+        );
+    }
+
+    #[test]
+    fn rename() {
+        let mut ed = editor();
+        ed.rename(5, "Two *2*").unwrap();
+        assert_eq!(ed.source(), after(SRC, "[Two](", "[Two \\*2\\*]("));
+        assert_eq!(ed.entries[5].chapter.name, "Two *2*");
+    }
+
+    #[test]
+    fn relocate() {
+        let mut ed = editor();
+        ed.relocate(5, Path::new("two/index.md")).unwrap();
+        assert_eq!(
+            ed.source(),
+            after(SRC, "(<two words.md> \"Title\")", "(two/index.md)")
+        );
+
+        let mut ed = SummaryEditor::new("# B\n\n- [A \\[1\\]](./a.md)\n").unwrap();
+        ed.relocate(0, Path::new("sub/a b.md")).unwrap();
+        assert_eq!(ed.source(), "# B\n\n- [A \\[1\\]](<./sub/a b.md>)\n");
+    }
+
+    #[test]
+    fn falls_back_to_rewriting() {
+        // Both on one line, so there's no way to put something between them.
+        let mut ed = SummaryEditor::new("# B\n\n[A](a.md) [C](c.md)\n\n- [D](d.md)\n").unwrap();
+        ed.insert(chapter("B", "b.md"), Position::After(0)).unwrap();
+        assert_eq!(
+            ed.source(),
+            "# B\n\n[A](a.md)\n[B](b.md)\n[C](c.md)\n\n- [D](d.md)\n"
+        );
+    }
+
+    #[test]
+    fn summary_round_trips() {
+        let summary = parse_summary(SRC).unwrap();
+        let entries = flatten(&summary);
+        assert_eq!(unflatten(&summary.title, &entries).unwrap(), summary);
+        assert_eq!(parse_summary(&summary.to_string()).unwrap(), summary);
+    }
+
+    #[test]
+    fn add_command() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        temp.child("b/SUMMARY.md").write_str(SRC).unwrap();
+        temp.child("b/existing.md")
+            .write_str("# From the file\n")
+            .unwrap();
+
+        let add = |path: &str, name: Option<&str>| {
+            let opts = EditSummary {
+                book: "b".into(),
+                edit: SummaryEdit::Add(AddChapter {
+                    path: path.into(),
+                    name: name.map(Into::into),
+                    placement: Placement {
+                        after: Some("Two".into()),
+                        under: None,
+                    },
+                }),
+            };
+            edit(temp.path(), &opts).unwrap();
+        };
+        add("existing.md", None);
+        add("new/file-name.md", None);
+        add("named.md", Some("Given"));
+
+        temp.child("b/SUMMARY.md").assert(after(
+            SRC,
+            "\"Title\")\n",
+            "\"Title\")\n* [Given](named.md)\n* [File Name](new/file-name.md)\n\
+             * [From the file](existing.md)\n",
+        ));
+        temp.child("b/existing.md").assert("# From the file\n");
+        temp.child("b/new/file-name.md").assert("# File Name\n");
+        temp.child("b/named.md").assert("# Given\n");
+
+        let opts = EditSummary {
+            book: "b".into(),
+            edit: SummaryEdit::Rename(RenameChapter {
+                chapter: "nope.md".into(),
+                name: "X".into(),
+            }),
+        };
+        assert!(edit(temp.path(), &opts).is_err());
+    }
+}
@@ -1,22 +1,36 @@
 // SPDX-License-Identifier: GPL-3.0-only
+use std::collections::{BTreeMap, HashMap};
 use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
 use std::path::{Path, PathBuf};
 
-use eyre::Result;
+use eyre::{bail, eyre, Result};
 use tracing::instrument;
 
-use crate::cli::config::GlobalConf;
+use crate::cli::config::{GlobalConf, UrlStyle};
 use crate::cli::{self, config, summary};
 
-use super::{Book, Content, Link, Page};
+use super::{front_matter, links, Book, Content, Link, Page, Redirect};
 
 // Oh dear god the allocations
 // TODO: Where should this be.
 #[derive(Debug, Clone, Default)]
 pub(crate) struct Dirs {
+    /// Where the html goes.
     pub(crate) out_dir: PathBuf,
     pub(crate) base_dir: PathBuf,
+    /// The path the site is served from, always starting and ending in a `/`.
     pub(crate) base_url: String,
+    /// The scheme and host from the `site-url`, for when we need absolute
+    /// urls.
+    pub(crate) origin: Option<String>,
+    pub(crate) url_style: UrlStyle,
+    /// Whether links between pages are relative to the page they're in.
+    pub(crate) relative_links: bool,
+    /// Whether the site will be opened from disk, so links have to point at
+    /// files.
+    pub(crate) offline: bool,
 }
 
 impl Dirs {
@@ -24,26 +38,164 @@ impl Dirs {
     pub(crate) fn new(conf: &GlobalConf, args: &cli::Args) -> Self {
         Self {
             base_dir: args.dir.clone(),
-            out_dir: args.dir.join("_out").join("html"),
-            base_url: conf
-                .html
-                .site_url
-                .to_owned()
-                .unwrap_or_else(|| "/".to_owned()),
+            out_dir: conf.build.out_dir.join("html"),
+            base_url: conf.html.site_url.path.clone(),
+            origin: conf.html.site_url.origin.clone(),
+            url_style: conf.build.url_style,
+            relative_links: conf.html.relative_links || conf.html.offline,
+            offline: conf.html.offline,
         }
     }
+
+    /// The link to a file in the output.
+    pub(crate) fn href(&self, output: &Path) -> Result<String> {
+        Ok(format!(
+            "{}{}",
+            self.base_url,
+            links::href(output.strip_prefix(&self.out_dir)?)
+        ))
+    }
+
+    /// The url of a page, so without the `index.html` if the host serves it
+    /// for us, which it doesn't when offline.
+    ///
+    /// With relative links, directories keep their trailing `/`, so they can
+    /// be told apart from files in [`Dirs::link`].
+    pub(crate) fn url(&self, output: &Path) -> Result<String> {
+        let mut url = self.href(output)?;
+        if self.url_style == UrlStyle::Pretty
+            && !self.offline
+            && output.file_name() == Some(OsStr::new("index.html"))
+        {
+            url.truncate(url.len() - "index.html".len());
+            if !self.relative_links && url != self.base_url {
+                url.pop();
+            }
+        }
+        Ok(url)
+    }
+
+    /// The link to `url` from the page written to `from`, which is relative if
+    /// the config asks for it.
+    pub(crate) fn link(&self, from: &Path, url: &str) -> Result<String> {
+        let target = match url.strip_prefix(&self.base_url) {
+            Some(target) if self.relative_links => target,
+            _ => return Ok(url.to_owned()),
+        };
+        let from = links::href(from.strip_prefix(&self.out_dir)?);
+
+        let mut here: Vec<_> = from.split('/').collect();
+        here.pop();
+        let mut there: Vec<_> = target.split('/').collect();
+        let file = there.pop().unwrap_or_default();
+        let common = here.iter().zip(&there).take_while(|(a, b)| a == b).count();
+
+        let mut link = "../".repeat(here.len() - common);
+        for dir in &there[common..] {
+            link.push_str(dir);
+            link.push('/');
+        }
+        link.push_str(file);
+        if link.is_empty() {
+            link.push_str("./");
+        }
+        Ok(link)
+    }
+}
+
+/// A chapter that can't be in a book, because of an earlier one.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Clash {
+    /// Of the later chapter, in the `SUMMARY.md`.
+    pub(crate) line: Option<usize>,
+    pub(crate) message: String,
+}
+
+impl fmt::Display for Clash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(line) = self.line {
+            write!(f, "line {}: ", line)?;
+        }
+        write!(f, "{}", self.message)
+    }
 }
 
 impl Content {
     #[instrument]
     pub(crate) fn new(config: &config::GlobalConf, dirs: &Dirs) -> Result<Self> {
-        Ok(Self(
+        let mut content = Self(
             config
                 .books
                 .iter()
                 .map(|x| Book::new(x, dirs))
                 .collect::<Result<_>>()?,
-        ))
+        );
+        content.add_config_redirects(&config.redirects, dirs)?;
+        content.check_redirects(dirs)?;
+        Ok(content)
+    }
+
+    /// Add the `[redirects]` from the xmark.toml to the book they point into.
+    #[instrument]
+    fn add_config_redirects(
+        &mut self,
+        redirects: &BTreeMap<PathBuf, PathBuf>,
+        dirs: &Dirs,
+    ) -> Result<()> {
+        for (from, to) in redirects {
+            let (book, url) = self
+                .0
+                .iter()
+                .enumerate()
+                .find_map(|(i, book)| {
+                    let page = book.pages.iter().find(|p| &p.input == to)?;
+                    Some((i, page.url(dirs)))
+                })
+                .ok_or_else(|| eyre!("Redirect target {:?} is not a chapter", to))?;
+            let from = links::normalize(from);
+            if !from.starts_with(links::normalize(&dirs.base_dir)) {
+                bail!("The redirect from {:?} is outside of the site", from);
+            }
+            let file = output_loc(&from, dirs)?;
+            self.0[book].redirects.push((file, url?));
+        }
+        Ok(())
+    }
+
+    /// Make sure no redirect clobbers a page, or another redirect.
+    #[instrument]
+    fn check_redirects(&self, dirs: &Dirs) -> Result<()> {
+        let generated = self
+            .0
+            .iter()
+            .map(|b| generated_files(&b.location, dirs))
+            .collect::<Result<Vec<_>>>()?;
+        let mut outputs = HashMap::new();
+        for (file, what) in generated.iter().flatten() {
+            outputs.insert(file, what.to_string());
+        }
+        for page in self.0.iter().flat_map(|b| &b.pages) {
+            outputs.insert(&page.output, format!("the page for {:?}", page.input));
+        }
+
+        let mut errors = Vec::new();
+        for (file, url) in self.0.iter().flat_map(|b| &b.redirects) {
+            match outputs.get(file) {
+                Some(other) => errors.push(format!(
+                    "The redirect at {:?} to {} collides with {}",
+                    file, url, other
+                )),
+                None => {
+                    outputs.insert(file, format!("the redirect to {}", url));
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            bail!("Conflicting redirects:\n{}", errors.join("\n"))
+        }
     }
 }
 
@@ -51,22 +203,118 @@ impl Book {
     #[instrument]
 
     pub(crate) fn new(book: &config::Book, dirs: &Dirs) -> Result<Self> {
+        let clashes = Self::clashes(book, dirs)?;
+        if !clashes.is_empty() {
+            bail!(
+                "Chapters in {:?} clash:\n{}",
+                book.location.join("SUMMARY.md"),
+                clashes
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            );
+        }
+
         let title = book.summary.title.clone();
         let (pages, redirects) = Self::capture_pages(book, dirs)?;
 
         Ok(Self {
             title,
+            location: book.location.clone(),
             pages,
             redirects,
         })
     }
 
+    /// The name of the book's directory, for naming output files.
+    pub(crate) fn dir_name(&self) -> String {
+        self.location
+            .file_name()
+            .map_or_else(|| "book".into(), |x| x.to_string_lossy().into_owned())
+    }
+
+    /// Chapters that are in the summary twice, or that would be rendered to
+    /// the same place, like `foo.md` and `foo/README.md`.
+    #[instrument]
+    pub(crate) fn clashes(book: &config::Book, dirs: &Dirs) -> Result<Vec<Clash>> {
+        let chapters = book.summary.chapters();
+        // This is just for the message, so don't fail if it's gone.
+        let lines = fs::read_to_string(book.location.join("SUMMARY.md"))
+            .ok()
+            .and_then(|src| book.summary.chapter_lines(&src));
+        let line = |i: usize| lines.as_ref().map(|l| l[i]);
+        let rel = |path: &Path| path.strip_prefix(&book.location).unwrap_or(path).to_owned();
+
+        let generated: HashMap<_, _> = generated_files(&book.location, dirs)?.into_iter().collect();
+        let mut inputs: HashMap<_, usize> = HashMap::new();
+        let mut outputs: HashMap<_, usize> = HashMap::new();
+        let mut clashes = Vec::new();
+        for (i, chapter) in chapters.iter().enumerate() {
+            let input = match &chapter.location {
+                Some(input) => input,
+                None => continue,
+            };
+
+            if let Some(&first) = inputs.get(input) {
+                clashes.push(Clash {
+                    line: line(i),
+                    message: format!(
+                        "{:?} ({}) is already in the summary as {:?}{}",
+                        chapter.name,
+                        rel(input).display(),
+                        chapters[first].name,
+                        line(first).map_or_else(String::new, |l| format!(" (line {})", l)),
+                    ),
+                });
+                continue;
+            }
+            inputs.insert(input, i);
+
+            let output = output_loc(input, dirs)?;
+            if let Some(what) = generated.get(&output) {
+                clashes.push(Clash {
+                    line: line(i),
+                    message: format!(
+                        "{:?} ({}) would be written to {}, which is {}",
+                        chapter.name,
+                        rel(input).display(),
+                        output.strip_prefix(&dirs.out_dir)?.display(),
+                        what,
+                    ),
+                });
+                continue;
+            }
+            match outputs.get(&output) {
+                Some(&first) => clashes.push(Clash {
+                    line: line(i),
+                    message: format!(
+                        "{:?} ({}) and {:?} ({}{}) would both be written to {}",
+                        chapter.name,
+                        rel(input).display(),
+                        chapters[first].name,
+                        chapters[first]
+                            .location
+                            .as_deref()
+                            .map(rel)
+                            .unwrap_or_default()
+                            .display(),
+                        line(first).map_or_else(String::new, |l| format!(", line {}", l)),
+                        output.strip_prefix(&dirs.out_dir)?.display(),
+                    ),
+                }),
+                None => {
+                    outputs.insert(output, i);
+                }
+            }
+        }
+
+        Ok(clashes)
+    }
+
     //TODO: does this need to be seperate from Book::new
     #[instrument]
-    fn capture_pages(
-        book: &config::Book,
-        dirs: &Dirs,
-    ) -> Result<(Vec<Page>, Vec<(PathBuf, String)>)> {
+    fn capture_pages(book: &config::Book, dirs: &Dirs) -> Result<(Vec<Page>, Vec<Redirect>)> {
         use PageListParts::*;
 
         // We need to hold onto a bungh of stuff as we walk the tree, ands its
@@ -76,37 +324,29 @@ impl Book {
         // Create flat list
         let mut pages_parts = Vec::new();
         for i in &book.summary.prefix_chapters {
-            pages_parts.push(Chapter(i));
+            pages_parts.push(Chapter(i, None));
         }
         for i in &book.summary.numbered_chapters {
             Self::capture_raw_parts(i, &mut pages_parts);
         }
         for i in &book.summary.suffix_chapters {
-            pages_parts.push(Chapter(i));
+            pages_parts.push(Chapter(i, None));
         }
 
         let mut pages = Vec::with_capacity(pages_parts.len());
         let mut redirs = Vec::new();
 
+        let redir_to_index_out = output_loc(&book.location.join("README.md"), dirs)?;
         let mut heirachy = vec![Link {
             prity: book.summary.title.clone(),
-            link: Path::new(&dirs.base_url)
-                .join(book.location.strip_prefix(&dirs.base_dir)?)
-                .into_os_string()
-                .into_string()
-                .map_err(|x| eyre::eyre!("Invalid string {:?}", x))?,
+            link: dirs.url(&redir_to_index_out)?,
         }];
 
-        let redir_to_index_out = output_loc(
-            &book.location.join("README.md"),
-            &dirs.out_dir,
-            &dirs.base_dir,
-        )?;
         let mut needs_redir = true;
 
         for i in pages_parts {
             match i {
-                PageListParts::Chapter(chap) => {
+                PageListParts::Chapter(chap, section_number) => {
                     let input = match &chap.location {
                         // Skip over the drafts, as they only show up in big toc.
                         None => continue,
@@ -119,10 +359,11 @@ impl Book {
                     // This is quite wastefull in terms of allocs, but who cares
                     let heirachy = heirachy.clone();
 
-                    let output = output_loc(&input, &dirs.out_dir, &dirs.base_dir)?;
+                    let output = output_loc(&input, dirs)?;
                     if output == redir_to_index_out {
                         needs_redir = false;
                     }
+                    let (front_matter, _) = front_matter::read(&input)?;
                     let mut page = Page {
                         input,
                         name,
@@ -130,8 +371,25 @@ impl Book {
                         heirachy,
                         prev: None,
                         next: None,
+                        section_number: section_number.cloned(),
+                        front_matter,
                     };
                     page.heirachy.push(page.heirachy_element(dirs)?);
+                    for alias in &page.front_matter.aliases {
+                        let alias = links::normalize(&book.location.join(alias));
+                        if !alias.starts_with(links::normalize(&book.location)) {
+                            bail!(
+                                "The alias {:?} of {:?} is outside of the book",
+                                alias,
+                                page.input
+                            );
+                        }
+                        let from = output_loc(&alias, dirs)?;
+                        if from == redir_to_index_out {
+                            needs_redir = false;
+                        }
+                        redirs.push((from, page.url(dirs)?));
+                    }
                     pages.push(page)
                 }
                 PageListParts::StartSection => {
@@ -159,7 +417,7 @@ impl Book {
 
         if needs_redir {
             // TODO: What do we do if their are no pages.
-            if let Some(first_page) = pages.get(0) {
+            if let Some(first_page) = pages.first() {
                 redirs.push((redir_to_index_out, first_page.url(dirs)?))
             }
         }
@@ -171,7 +429,7 @@ impl Book {
     fn capture_raw_parts<'a>(link: &'a summary::Link, out: &mut Vec<PageListParts<'a>>) {
         use PageListParts::*;
 
-        out.push(Chapter(&link.chapter));
+        out.push(Chapter(&link.chapter, link.section_number.as_ref()));
         if !link.nested_items.is_empty() {
             out.push(StartSection);
             for i in &link.nested_items {
@@ -183,6 +441,11 @@ impl Book {
 }
 
 impl Page {
+    /// The title for the page, which the front matter can override.
+    pub(crate) fn title(&self) -> &str {
+        self.front_matter.title.as_deref().unwrap_or(&self.name)
+    }
+
     pub(crate) fn heirachy_element(&self, dirs: &Dirs) -> Result<Link> {
         Ok(Link {
             prity: self.name.clone(),
@@ -192,43 +455,54 @@ impl Page {
 
     #[instrument]
     pub(crate) fn url(&self, dirs: &Dirs) -> Result<String> {
-        let relative_pos = self.output.strip_prefix(&dirs.out_dir)?;
-        let mut url = Path::new(&dirs.base_url).join(relative_pos);
-        url.pop();
-        Ok(
-            url.into_os_string()
-                .into_string()
-                .map_err(|x| eyre::eyre!("Invalid string {:?}", x))?
-                .replace("/./", "/"), // Hack
-        )
+        dirs.url(&self.output)
     }
 }
 /// Fun helper type
 ///
+/// ```text
 /// 1. Foo
 /// 2. Bar
 /// 2.1. Baz
 /// 2.1.1 Quix
 /// 2.2 Spam
+/// ```
 ///
+/// ```text
 /// Chapter(Foo)
 /// Chapter(Bar)
 /// StartSection
 /// Chapter(Baz)
 /// StartSection
+/// ```
 #[derive(Debug)]
 enum PageListParts<'a> {
     //TODO: A better name
-    Chapter(&'a summary::Chapter),
+    Chapter(&'a summary::Chapter, Option<&'a summary::SectionNumber>),
     StartSection,
     EndSection,
 }
 
+/// The files the renderers write for the book at `location`, besides it's
+/// chapters, and what they are.
+pub(crate) fn generated_files(
+    location: &Path,
+    dirs: &Dirs,
+) -> Result<Vec<(PathBuf, &'static str)>> {
+    let dir = dirs.out_dir.join(location.strip_prefix(&dirs.base_dir)?);
+    Ok(vec![
+        (dir.join("print.html"), "the print page"),
+        (dir.join("feed.xml"), "the feed"),
+    ])
+}
+
 #[instrument]
-pub(crate) fn output_loc(input_loc: &Path, out_dir: &Path, base_dir: &Path) -> Result<PathBuf> {
-    let mut path = out_dir.join(input_loc.strip_prefix(base_dir)?);
+pub(crate) fn output_loc(input_loc: &Path, dirs: &Dirs) -> Result<PathBuf> {
+    let mut path = dirs.out_dir.join(input_loc.strip_prefix(&dirs.base_dir)?);
     if path.file_name() == Some(OsStr::new("README.md")) {
         path.set_file_name("index.html")
+    } else if dirs.url_style == UrlStyle::Flat {
+        path.set_extension("html");
     } else {
         path.set_extension("");
         path.push("index.html");
@@ -0,0 +1,93 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! A short plain text excerpt of a chapter, for when the front matter doesn't
+//! have a description.
+//!
+//! It's the first paragraph that has some text in it, without any formatting,
+//! and cut down to [`MAX_LEN`] characters.
+use pulldown_cmark::{Event, Options, Parser, Tag};
+
+use crate::html_render::expand_containers;
+
+/// Roughly what search engines show, so there's no point going longer.
+pub(crate) const MAX_LEN: usize = 160;
+
+/// The excerpt of the markdown `body`, if it has a paragraph.
+pub(crate) fn excerpt(body: &str) -> Option<String> {
+    let mut depth = 0;
+    let mut text = None;
+    // Alt text isn't something you'd read.
+    let mut in_image = false;
+    // Otherwise a leading `:::warning` is part of the first paragraph.
+    let body = expand_containers(body);
+    for ev in Parser::new_ext(&body, Options::all()) {
+        match ev {
+            // Lists, quotes and the like don't count, only top level.
+            Event::Start(Tag::Paragraph) if depth == 0 => text = Some(String::new()),
+            Event::End(Tag::Paragraph) if depth == 0 => match text.take() {
+                Some(text) if !text.trim().is_empty() => return Some(shorten(&text)),
+                _ => {}
+            },
+            Event::Start(Tag::Image(..)) if text.is_some() => in_image = true,
+            Event::End(Tag::Image(..)) if text.is_some() => in_image = false,
+            Event::Start(_) if text.is_none() => depth += 1,
+            Event::End(_) if text.is_none() => depth -= 1,
+            Event::Text(t) | Event::Code(t) if !in_image => {
+                if let Some(text) = &mut text {
+                    text.push_str(&t);
+                }
+            }
+            Event::SoftBreak | Event::HardBreak => {
+                if let Some(text) = &mut text {
+                    text.push(' ');
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Collapse the whitespace, and cut at a word if it's too long.
+fn shorten(text: &str) -> String {
+    let words: Vec<_> = text.split_whitespace().collect();
+    let mut out = String::new();
+    for word in words {
+        // Leaving room for the space, and the ellipsis.
+        if out.chars().count() + word.chars().count() + 2 > MAX_LEN {
+            out.push('…');
+            return out;
+        }
+        if !out.is_empty() {
+            out.push(' ');
+        }
+        out.push_str(word);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_paragraph() {
+        assert_eq!(
+            excerpt("# Title\n\nSome *nice*\n`code`  [here](x.md).\n\nMore.\n").as_deref(),
+            Some("Some nice code here.")
+        );
+        assert_eq!(
+            excerpt("# T\n\n- A list\n\n> A quote\n\n![A pic](pic.png)\n\nThe text.\n").as_deref(),
+            Some("The text.")
+        );
+        assert_eq!(excerpt("# Only a title\n\n```\ncode\n```\n"), None);
+        assert_eq!(
+            excerpt(":::warning\nMind the steps.\n:::\n\nAfter.\n").as_deref(),
+            Some("Mind the steps.")
+        );
+
+        let long = "word ".repeat(100);
+        let cut = excerpt(&long).unwrap();
+        assert!(cut.chars().count() <= MAX_LEN);
+        assert!(cut.ends_with("word…"));
+    }
+}
@@ -0,0 +1,234 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! Optional metadata at the top of a chapter.
+//!
+//! Either TOML, fenced with `+++`
+//!
+//! ```markdown
+//! +++
+//! title = "Getting Started"
+//! description = "How to install things"
+//! +++
+//!
+//! # Getting started
+//! ```
+//!
+//! or YAML, fenced with `---`.
+//!
+//! ```markdown
+//! ---
+//! title: Getting Started
+//! noindex: true
+//! ---
+//! ```
+use std::fs;
+use std::path::Path;
+
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use eyre::{bail, eyre, Result, WrapErr};
+use serde::{Deserialize, Deserializer, Serialize};
+use tracing::instrument;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct FrontMatter {
+    /// Overrides the name from the `SUMMARY.md` in the page `<title>`.
+    pub(crate) title: Option<String>,
+    pub(crate) description: Option<String>,
+    /// Ask search engines not to index this page.
+    pub(crate) noindex: bool,
+    /// Old locations of this chapter, relative to the book, which should
+    /// redirect here.
+    pub(crate) aliases: Vec<String>,
+    /// When it was written, for feeds. Either RFC 3339, or just the day.
+    #[serde(deserialize_with = "de_date")]
+    pub(crate) date: Option<String>,
+}
+
+impl FrontMatter {
+    /// The `date`, which has already been checked when it was read.
+    pub(crate) fn date(&self) -> Option<DateTime<FixedOffset>> {
+        self.date.as_deref().and_then(parse_date)
+    }
+}
+
+/// TOML has it's own dates, so `date = 2020-11-01` isn't a string.
+fn de_date<'de, D: Deserializer<'de>>(d: D) -> Result<Option<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Date {
+        Toml(toml::value::Datetime),
+        Text(String),
+    }
+    Ok(Option::<Date>::deserialize(d)?.map(|date| match date {
+        Date::Toml(date) => date.to_string(),
+        Date::Text(date) => date,
+    }))
+}
+
+/// Dates without a time are midnight, and without an offset are UTC.
+fn parse_date(date: &str) -> Option<DateTime<FixedOffset>> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(date) {
+        return Some(date);
+    }
+    let naive = NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S")
+        .or_else(|_| NaiveDate::parse_from_str(date, "%Y-%m-%d").map(|d| d.and_hms(0, 0, 0)))
+        .ok()?;
+    Some(Utc.from_utc_datetime(&naive).into())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Toml,
+    Yaml,
+}
+
+/// Read a chapter, and split it into it's front matter and the markdown body.
+#[instrument]
+pub(crate) fn read(path: &Path) -> Result<(FrontMatter, String)> {
+    let src = fs::read_to_string(path).wrap_err_with(|| format!("Couldn't open {:?}", path))?;
+    let (fm, body) = split(&src).wrap_err_with(|| format!("Invalid front matter in {:?}", path))?;
+    Ok((fm.unwrap_or_default(), body.to_owned()))
+}
+
+/// Split the front matter (if any) from the markdown body.
+pub(crate) fn split(src: &str) -> Result<(Option<FrontMatter>, &str)> {
+    let (format, fence) = match src.lines().next().map(str::trim_end) {
+        Some("+++") => (Format::Toml, "+++"),
+        Some("---") => (Format::Yaml, "---"),
+        _ => return Ok((None, src)),
+    };
+
+    let start = src.find('\n').map_or(src.len(), |x| x + 1);
+    let mut pos = start;
+    for line in src[start..].split_inclusive('\n') {
+        let end = pos + line.len();
+        let trimmed = line.trim_end();
+        if trimmed == fence || (format == Format::Yaml && trimmed == "...") {
+            let raw = &src[start..pos];
+            let fm: FrontMatter = match format {
+                Format::Toml => toml::from_str(raw)?,
+                // serde_yaml doesn't like empty documents.
+                Format::Yaml if raw.trim().is_empty() => FrontMatter::default(),
+                Format::Yaml => match serde_yaml::from_str(raw)? {
+                    yaml @ serde_yaml::Value::Mapping(_) => serde_yaml::from_value(yaml)?,
+                    // Just text between two thematic breaks.
+                    _ => return Ok((None, src)),
+                },
+            };
+            if let Some(date) = &fm.date {
+                parse_date(date).ok_or_else(|| eyre!("Invalid date {:?}", date))?;
+            }
+            return Ok((Some(fm), &src[end..]));
+        }
+        pos = end;
+    }
+
+    match format {
+        Format::Toml => bail!("No closing `+++`"),
+        // A leading `---` without a match is just a thematic break.
+        Format::Yaml => Ok((None, src)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn none() {
+        let src = "# Hello\n\n+++\n";
+        assert_eq!(split(src).unwrap(), (None, src));
+    }
+
+    #[test]
+    fn toml() {
+        let src = "+++\ntitle = \"Hi\"\nnoindex = true\naliases = [\"old.md\"]\n+++\n# Hello\n";
+        let (fm, body) = split(src).unwrap();
+        assert_eq!(
+            fm,
+            Some(FrontMatter {
+                title: Some("Hi".into()),
+                noindex: true,
+                aliases: vec!["old.md".into()],
+                ..Default::default()
+            })
+        );
+        assert_eq!(body, "# Hello\n");
+    }
+
+    #[test]
+    fn yaml() {
+        let src = "---\ndescription: A page\n---\n# Hello\n";
+        let (fm, body) = split(src).unwrap();
+        assert_eq!(
+            fm,
+            Some(FrontMatter {
+                description: Some("A page".into()),
+                ..Default::default()
+            })
+        );
+        assert_eq!(body, "# Hello\n");
+
+        let (fm, body) = split("---\n---\n").unwrap();
+        assert_eq!(fm, Some(Default::default()));
+        assert_eq!(body, "");
+    }
+
+    #[test]
+    fn dates() {
+        let date = |src: &str| split(src).unwrap().0.unwrap().date().unwrap().to_rfc3339();
+        assert_eq!(
+            date("+++\ndate = 2020-11-01\n+++\n"),
+            "2020-11-01T00:00:00+00:00"
+        );
+        assert_eq!(
+            date("+++\ndate = 2020-11-01T12:30:00+01:00\n+++\n"),
+            "2020-11-01T12:30:00+01:00"
+        );
+        assert_eq!(
+            date("+++\ndate = 2020-11-01T12:30:00\n+++\n"),
+            "2020-11-01T12:30:00+00:00"
+        );
+        assert_eq!(
+            date("---\ndate: 2020-11-01\n---\n"),
+            "2020-11-01T00:00:00+00:00"
+        );
+        assert_eq!(
+            date("---\ndate: '2020-11-01T12:30:00Z'\n---\n"),
+            "2020-11-01T12:30:00+00:00"
+        );
+        assert!(split("---\ndate: last tuesday\n---\n").is_err());
+    }
+
+    #[test]
+    fn thematic_break() {
+        let src = "---\n# Hello\n";
+        assert_eq!(split(src).unwrap(), (None, src));
+    }
+
+    #[test]
+    fn two_thematic_breaks() {
+        let src = "---\n\nIntro text.\n\n---\n\nMore.\n";
+        assert_eq!(split(src).unwrap(), (None, src));
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(split("+++\ntitle = \n+++\n").is_err());
+        assert!(split("+++\ntitle = \"x\"\n").is_err());
+        assert!(split("---\ntitle: [\n---\n").is_err());
+        assert!(split("+++\nnot-a-field = 1\n+++\n").is_err());
+    }
+
+    #[test]
+    fn error_has_file_name() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let file = temp.child("bad.md");
+        file.write_str("+++\ntitle = 1\n+++\n").unwrap();
+
+        let err = read(file.path()).unwrap_err();
+        assert!(format!("{}", err).contains("bad.md"), "{}", err);
+    }
+}
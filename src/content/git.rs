@@ -0,0 +1,116 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! What the local git history knows about a chapter.
+//!
+//! This just runs `git`, so it never touches the network, and everything is
+//! an `Option`, as the book might not be in a repo, or git might not be
+//! installed.
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use chrono::{DateTime, FixedOffset, Utc};
+use tracing::instrument;
+
+/// The top of the repo that `dir` is in.
+#[instrument]
+pub(crate) fn root(dir: &Path) -> Option<PathBuf> {
+    let out = Command::new("git")
+        .args(["rev-parse", "--show-toplevel"])
+        .current_dir(dir)
+        .output()
+        .ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    Some(String::from_utf8(out.stdout).ok()?.trim_end().into())
+}
+
+/// When `path` was last committed.
+#[instrument]
+pub(crate) fn last_commit(path: &Path) -> Option<DateTime<FixedOffset>> {
+    let out = Command::new("git")
+        .args(["log", "-1", "--format=%cI", "--"])
+        .arg(path.file_name()?)
+        .current_dir(path.parent()?)
+        .output()
+        .ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    DateTime::parse_from_rfc3339(String::from_utf8(out.stdout).ok()?.trim()).ok()
+}
+
+/// When each chapter was last committed, for the ones that have been.
+pub(crate) type Commits = HashMap<PathBuf, DateTime<FixedOffset>>;
+
+/// The [`last_commit`] of each of `paths`, so everything that needs them
+/// doesn't run git again.
+pub(crate) fn last_commits<'a>(paths: impl IntoIterator<Item = &'a Path>) -> Commits {
+    paths
+        .into_iter()
+        .filter_map(|path| Some((path.to_owned(), last_commit(path)?)))
+        .collect()
+}
+
+/// When `path` was last changed, going by it's commit in `commits`, or
+/// failing that the file itself.
+pub(crate) fn last_modified(path: &Path, commits: &Commits) -> Option<DateTime<FixedOffset>> {
+    commits.get(path).copied().or_else(|| {
+        let modified = fs::metadata(path).ok()?.modified().ok()?;
+        Some(DateTime::<Utc>::from(modified).into())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::prelude::*;
+
+    use super::*;
+
+    fn git(dir: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .args(["-c", "user.name=X", "-c", "user.email=x@example.com"])
+            .args(args)
+            .env("GIT_COMMITTER_DATE", "2020-11-01T12:00:00+01:00")
+            .current_dir(dir)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    #[test]
+    fn commit_times() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        temp.child("b/one.md").write_str("# One\n").unwrap();
+        temp.child("b/two.md").write_str("# Two\n").unwrap();
+        git(temp.path(), &["init", "-q"]);
+        git(temp.path(), &["add", "b/one.md"]);
+        git(temp.path(), &["commit", "-q", "-m", "One"]);
+
+        let one = temp.child("b/one.md");
+        assert_eq!(
+            last_commit(one.path()).unwrap().to_rfc3339(),
+            "2020-11-01T12:00:00+01:00"
+        );
+        let two = temp.child("b/two.md");
+        let commits = last_commits(vec![one.path(), two.path()]);
+        assert_eq!(commits.len(), 1);
+        assert_eq!(last_modified(one.path(), &commits), last_commit(one.path()));
+        assert_eq!(
+            root(&temp.path().join("b")),
+            Some(temp.path().canonicalize().unwrap())
+        );
+
+        // Not committed, so it's the file's time.
+        assert_eq!(last_commit(two.path()), None);
+        assert!(
+            last_modified(two.path(), &commits).unwrap()
+                > last_modified(one.path(), &commits).unwrap()
+        );
+        assert_eq!(
+            last_modified(&temp.path().join("b/three.md"), &commits),
+            None
+        );
+    }
+}
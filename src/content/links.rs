@@ -0,0 +1,152 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! Working out where the links in a chapter point.
+use std::path::{Component, Path, PathBuf};
+
+/// A link from a chapter to a file on disk.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct LocalLink<'a> {
+    /// The file the link points to. For `#foo` links, this is the chapter
+    /// itself.
+    pub(crate) path: PathBuf,
+    pub(crate) fragment: Option<&'a str>,
+}
+
+/// Resolve a link in the chapter at `from` to the file it points at.
+///
+/// Returns `None` for anything that isn't a relative link, eg `https://`,
+/// `mailto:` or `/absolute` links, as they aren't files we know about.
+pub(crate) fn resolve<'a>(from: &Path, href: &'a str) -> Option<LocalLink<'a>> {
+    if href.starts_with('/') || href.contains(':') {
+        return None;
+    }
+
+    let (path, fragment) = match href.split_once('#') {
+        Some((path, fragment)) => (path, Some(fragment)),
+        None => (href, None),
+    };
+
+    let path = if path.is_empty() {
+        from.to_owned()
+    } else {
+        let path = path.replace("%20", " ");
+        normalize(&from.parent().unwrap_or_else(|| Path::new("")).join(path))
+    };
+
+    Some(LocalLink { path, fragment })
+}
+
+/// Lexically remove `.` and `..`, without touching the file system.
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !out.pop() {
+                    out.push("..");
+                }
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// The link from the chapter at `from` to `to`. Both should be normalized.
+pub(crate) fn relative(from: &Path, to: &Path) -> PathBuf {
+    let dir: Vec<_> = from
+        .parent()
+        .unwrap_or_else(|| Path::new(""))
+        .components()
+        .collect();
+    let to: Vec<_> = to.components().collect();
+    let common = dir.iter().zip(&to).take_while(|(a, b)| a == b).count();
+
+    let mut out: PathBuf = dir[common..].iter().map(|_| Component::ParentDir).collect();
+    out.extend(&to[common..]);
+    out
+}
+
+/// A relative path as it'd be written in a link, with `/` even on windows.
+pub(crate) fn href(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Percent-encode a url path. The `/`s, and everything else that's allowed
+/// in a path, are left alone.
+pub(crate) fn percent_encode(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' => out.push(byte as char),
+            b'-' | b'.' | b'_' | b'~' | b'/' | b':' | b'@' => out.push(byte as char),
+            b'!' | b'$' | b'&' | b'\'' | b'(' | b')' | b'*' | b'+' | b',' | b';' | b'=' => {
+                out.push(byte as char)
+            }
+            _ => out.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link(path: &str, fragment: Option<&'static str>) -> Option<LocalLink<'static>> {
+        Some(LocalLink {
+            path: path.into(),
+            fragment,
+        })
+    }
+
+    #[test]
+    fn resolves() {
+        let from = Path::new("/b/cli/init.md");
+        assert_eq!(resolve(from, "build.md"), link("/b/cli/build.md", None));
+        assert_eq!(
+            resolve(from, "./../format/README.md#top"),
+            link("/b/format/README.md", Some("top"))
+        );
+        assert_eq!(
+            resolve(from, "#usage"),
+            link("/b/cli/init.md", Some("usage"))
+        );
+        assert_eq!(
+            resolve(from, "my%20file.md"),
+            link("/b/cli/my file.md", None)
+        );
+    }
+
+    #[test]
+    fn relative_links() {
+        let rel = |from: &str, to: &str| href(&relative(Path::new(from), Path::new(to)));
+        assert_eq!(rel("/b/cli/init.md", "/b/cli/build.md"), "build.md");
+        assert_eq!(
+            rel("/b/cli/init.md", "/b/format/x y.md"),
+            "../format/x y.md"
+        );
+        assert_eq!(rel("/b/index.md", "/b/cli/deep/a.md"), "cli/deep/a.md");
+        assert_eq!(rel("/b/cli/init.md", "/c/a.md"), "../../c/a.md");
+    }
+
+    #[test]
+    fn percent_encodes() {
+        assert_eq!(percent_encode("/b/a&b/one"), "/b/a&b/one");
+        assert_eq!(
+            percent_encode("/my file#1?/100%/café"),
+            "/my%20file%231%3F/100%25/caf%C3%A9"
+        );
+    }
+
+    #[test]
+    fn ignores_non_local() {
+        let from = Path::new("/b/cli/init.md");
+        assert_eq!(resolve(from, "https://example.com/x.md"), None);
+        assert_eq!(resolve(from, "mailto:me@example.com"), None);
+        assert_eq!(resolve(from, "/b/x.md"), None);
+    }
+}
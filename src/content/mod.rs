@@ -4,9 +4,20 @@ use serde::{Deserialize, Serialize};
 
 use std::path::PathBuf;
 
+use crate::cli::summary::SectionNumber;
+
 pub(crate) mod collect;
+pub(crate) mod excerpt;
+pub(crate) mod front_matter;
+pub(crate) mod git;
+pub(crate) mod links;
+pub(crate) mod orphans;
 pub(crate) mod pagetoc;
 pub(crate) use collect::Dirs;
+pub(crate) use front_matter::FrontMatter;
+
+/// A file to be written, and the url it redirects to.
+pub(crate) type Redirect = (PathBuf, String);
 
 /// The content in a suitable form.
 
@@ -16,9 +27,11 @@ pub(crate) struct Content(pub(crate) Vec<Book>);
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Default)]
 pub(crate) struct Book {
     pub(crate) title: String,
+    /// The directory with the `SUMMARY.md`.
+    pub(crate) location: PathBuf,
     pub(crate) pages: Vec<Page>,
     /// List of files to be written, and the url to redirect to.
-    pub(crate) redirects: Vec<(PathBuf, String)>,
+    pub(crate) redirects: Vec<Redirect>,
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
@@ -40,11 +53,14 @@ pub(crate) struct Page {
     /// [Quickstart](https://docs.github.com/en/free-pro-team@latest/github/getting-started-with-github/quickstart) /
     /// [Set up Git](https://docs.github.com/en/free-pro-team@latest/github/getting-started-with-github/set-up-git)
     pub(crate) heirachy: Vec<Link>,
+    /// Numbered chapters only.
+    pub(crate) section_number: Option<SectionNumber>,
+    pub(crate) front_matter: FrontMatter,
 }
 
 //TODO: Should this be the same as pagetoc::Link.
-// This is relative to site root, so needs special care when we're serving
-// on a subdir. that is just relative to the page
+// This includes the site-url's path, so use `Dirs::link` to put it in a page,
+// which makes it relative if asked.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Rhc)]
 pub(crate) struct Link {
     pub(crate) prity: String,
@@ -57,16 +73,26 @@ mod tests {
 
     use assert_fs::prelude::*;
     use cli::Args;
+    use eyre::Result;
     use insta::{assert_yaml_snapshot, dynamic_redaction};
 
+    use crate::cli::config::UrlStyle;
     use crate::cli::{self, config};
 
+    use std::path::Path;
+
     use super::collect::output_loc;
     use super::*;
 
-    fn test_output_loc(md: &str, out: &str, base: &str, expected: &str) {
+    fn test_output_loc(md: &str, out: &str, base: &str, style: UrlStyle, expected: &str) {
+        let dirs = Dirs {
+            out_dir: out.into(),
+            base_dir: base.into(),
+            url_style: style,
+            ..Default::default()
+        };
         assert_eq!(
-            output_loc(md.as_ref(), out.as_ref(), base.as_ref())
+            output_loc(md.as_ref(), &dirs)
                 .unwrap()
                 .into_os_string()
                 .into_string()
@@ -77,33 +103,58 @@ mod tests {
 
     #[test]
     fn output_locs() {
+        use UrlStyle::*;
         test_output_loc(
             "/tmp/x/y.md",
             "/tmp/x/out",
             "/tmp/x/",
+            Pretty,
             "/tmp/x/out/y/index.html",
         );
         test_output_loc(
             "/tmp/x/z/README.md",
             "/tmp/x/out",
             "/tmp/x",
+            Pretty,
             "/tmp/x/out/z/index.html",
         );
         test_output_loc(
             "/tmp/x/z.md",
             "/tmp/x/out",
             "/tmp/x",
+            Pretty,
             "/tmp/x/out/z/index.html",
         );
         test_output_loc(
             "/tmp/zz/foo.md",
             "/xmark/",
             "/tmp/zz/",
+            Pretty,
             "/xmark/foo/index.html",
         );
+        test_output_loc(
+            "/tmp/x/z.md",
+            "/tmp/x/out",
+            "/tmp/x",
+            Flat,
+            "/tmp/x/out/z.html",
+        );
+        test_output_loc(
+            "/tmp/x/z/README.md",
+            "/tmp/x/out",
+            "/tmp/x",
+            Flat,
+            "/tmp/x/out/z/index.html",
+        );
     }
 
-    fn test_page_url(out_file: &str, base_url: &str, out_dir: &str, expected: &str) {
+    fn test_page_url(
+        out_file: &str,
+        base_url: &str,
+        out_dir: &str,
+        style: UrlStyle,
+        expected: &str,
+    ) {
         let page = Page {
             output: PathBuf::from(out_file),
             ..Default::default()
@@ -111,6 +162,7 @@ mod tests {
         let dirs = Dirs {
             base_url: base_url.to_owned(),
             out_dir: PathBuf::from(out_dir),
+            url_style: style,
             ..Default::default()
         };
         let url = page.url(&dirs).unwrap();
@@ -119,13 +171,58 @@ mod tests {
 
     #[test]
     fn urls() {
-        test_page_url("/out/x/y/index.html", "/", "/out", "/x/y");
+        use UrlStyle::*;
+        test_page_url("/out/x/y/index.html", "/", "/out", Pretty, "/x/y");
         test_page_url(
             "/usr/src/fx/_out/html/book3/cd/f/index.html",
             "/books/",
             "/usr/src/fx/_out/html",
+            Pretty,
             "/books/book3/cd/f",
-        )
+        );
+        test_page_url("/out/x/y.html", "/", "/out", Flat, "/x/y.html");
+        test_page_url("/out/x/index.html", "/", "/out", Flat, "/x/index.html");
+        test_page_url("/out/index.html", "/docs/v2/", "/out", Pretty, "/docs/v2/");
+        test_page_url("/out/./x/index.html", "/docs/", "/out", Pretty, "/docs/x");
+    }
+
+    #[test]
+    fn relative_page_links() {
+        let dirs = Dirs {
+            base_url: "/docs/".to_owned(),
+            out_dir: PathBuf::from("/out"),
+            relative_links: true,
+            ..Default::default()
+        };
+        let link = |from: &str, to: &str| {
+            let url = dirs.url(Path::new(to)).unwrap();
+            dirs.link(Path::new(from), &url).unwrap()
+        };
+        assert_eq!(link("/out/b/x/index.html", "/out/b/y/index.html"), "../y/");
+        assert_eq!(link("/out/b/x/index.html", "/out/b/x/index.html"), "./");
+        assert_eq!(link("/out/b/x/index.html", "/out/index.html"), "../../");
+        assert_eq!(link("/out/index.html", "/out/b/print.html"), "b/print.html");
+        assert_eq!(
+            link("/out/b/x/y/index.html", "/out/c/index.html"),
+            "../../../c/"
+        );
+        assert_eq!(
+            dirs.link(Path::new("/out/b/index.html"), "https://example.com/")
+                .unwrap(),
+            "https://example.com/"
+        );
+
+        // Without relative links, they're left alone.
+        let url = dirs.url(Path::new("/out/b/y/index.html")).unwrap();
+        assert_eq!(url, "/docs/b/y/");
+        let dirs = Dirs {
+            relative_links: false,
+            ..dirs.clone()
+        };
+        assert_eq!(
+            dirs.link(Path::new("/out/b/x/index.html"), &url).unwrap(),
+            url
+        );
     }
 
     #[test]
@@ -172,12 +269,169 @@ mod tests {
 
             {
                  ".**.input" => dynamic_redaction(redaction(tp.clone(), false)),
+                 ".*.location" => dynamic_redaction(redaction(tp.clone(), false)),
                  ".*.redirects[][]" => dynamic_redaction(redaction(tp.clone(), true)),
                  ".**.output" => dynamic_redaction(redaction(tp, false)),
             }
         );
     }
 
+    fn redirect_book(toml: &str, fm: &str) -> (assert_fs::TempDir, Result<Content>) {
+        let temp = assert_fs::TempDir::new().unwrap();
+        temp.child("xmark.toml").write_str(toml).unwrap();
+        temp.child("b/SUMMARY.md")
+            .write_str("# B\n\n- [One](one.md)\n- [Two](two.md)\n")
+            .unwrap();
+        temp.child("b/one.md").write_str("# One\n").unwrap();
+        temp.child("b/two.md").write_str(fm).unwrap();
+
+        let args = Args {
+            dir: temp.path().to_owned(),
+            ..Default::default()
+        };
+        let conf = config::load(&args).unwrap();
+        let dirs = Dirs::new(&conf, &args);
+        let content = Content::new(&conf, &dirs);
+        (temp, content)
+    }
+
+    #[test]
+    fn redirects() {
+        let (temp, content) = redirect_book(
+            "books = ['b']\n[redirects]\n'b/uno.md' = 'b/one.md'\n",
+            "+++\naliases = ['dos.md', 'old/two.md']\n+++\n",
+        );
+        let out = temp.path().join("_out/html/b");
+        let mut redirects = content.unwrap().0.remove(0).redirects;
+        redirects.sort();
+        assert_eq!(
+            redirects,
+            vec![
+                (out.join("dos/index.html"), "/b/two".to_owned()),
+                (out.join("index.html"), "/b/one".to_owned()),
+                (out.join("old/two/index.html"), "/b/two".to_owned()),
+                (out.join("uno/index.html"), "/b/one".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn redirect_collisions() {
+        let (_temp, content) = redirect_book("books = ['b']\n", "+++\naliases = ['one.md']\n+++\n");
+        let err = format!("{}", content.unwrap_err());
+        assert!(err.contains("collides with the page"), "{}", err);
+
+        let (_temp, content) = redirect_book(
+            "books = ['b']\n[redirects]\n'b/old.md' = 'b/one.md'\n",
+            "+++\naliases = ['old.md']\n+++\n",
+        );
+        let err = format!("{}", content.unwrap_err());
+        assert!(err.contains("collides with the redirect to /b/"), "{}", err);
+
+        let (_temp, content) = redirect_book(
+            "books = ['b']\n[redirects]\n'b/old.md' = 'b/nope.md'\n",
+            "# Two\n",
+        );
+        assert!(content.is_err());
+    }
+
+    #[test]
+    fn redirects_stay_inside() {
+        let (_temp, content) =
+            redirect_book("books = ['b']\n", "+++\naliases = ['../../../x.md']\n+++\n");
+        let err = format!("{}", content.unwrap_err());
+        assert!(err.contains("is outside of the book"), "{}", err);
+
+        let (_temp, content) = redirect_book(
+            "books = ['b']\n[redirects]\n'b/../../x.md' = 'b/one.md'\n",
+            "# Two\n",
+        );
+        let err = format!("{}", content.unwrap_err());
+        assert!(err.contains("is outside of the site"), "{}", err);
+
+        // Going up and back down is fine.
+        let (_temp, content) =
+            redirect_book("books = ['b']\n", "+++\naliases = ['old/../dos.md']\n+++\n");
+        assert_eq!(content.unwrap().0[0].redirects.len(), 2);
+    }
+
+    #[test]
+    fn chapter_clashes() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        temp.child("xmark.toml").write_str("books = ['b']").unwrap();
+        temp.child("b/SUMMARY.md")
+            .write_str(
+                "# B\n\n- [Foo](foo.md)\n    - [Bar](bar.md)\n- [Foo index](foo/README.md)\n\
+                 - [Bar again](./bar.md)\n",
+            )
+            .unwrap();
+        for file in &["foo.md", "bar.md", "foo/README.md"] {
+            temp.child("b").child(file).write_str("# Hi\n").unwrap();
+        }
+
+        let args = Args {
+            dir: temp.path().to_owned(),
+            ..Default::default()
+        };
+        let conf = config::load(&args).unwrap();
+        let err = Content::new(&conf, &Dirs::new(&conf, &args)).unwrap_err();
+        let err = format!("{}", err);
+        assert!(err.starts_with("Chapters in "), "{}", err);
+        assert!(
+            err.ends_with(
+                "clash:\n\
+                 line 5: \"Foo index\" (foo/README.md) and \"Foo\" (foo.md, line 3) \
+                 would both be written to b/foo/index.html\n\
+                 line 6: \"Bar again\" (bar.md) is already in the summary as \"Bar\" (line 4)"
+            ),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn generated_file_clashes() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        temp.child("xmark.toml")
+            .write_str("books = ['b']\n[build]\nurl-style = 'flat'\n")
+            .unwrap();
+        temp.child("b/SUMMARY.md")
+            .write_str("# B\n\n- [Print](print.md)\n- [Feed](feed.md)\n")
+            .unwrap();
+        for file in &["print.md", "feed.md"] {
+            temp.child("b").child(file).write_str("# Hi\n").unwrap();
+        }
+
+        let args = Args {
+            dir: temp.path().to_owned(),
+            ..Default::default()
+        };
+        let conf = config::load(&args).unwrap();
+        let err = Content::new(&conf, &Dirs::new(&conf, &args)).unwrap_err();
+        let err = format!("{}", err);
+        assert!(
+            err.ends_with(
+                "clash:\n\
+                 line 3: \"Print\" (print.md) would be written to b/print.html, \
+                 which is the print page"
+            ),
+            "{}",
+            err
+        );
+
+        // Nor can a redirect.
+        temp.child("b/SUMMARY.md")
+            .write_str("# B\n\n- [Feed](feed.md)\n")
+            .unwrap();
+        temp.child("b/feed.md")
+            .write_str("+++\naliases = ['print.md']\n+++\n# Hi\n")
+            .unwrap();
+        let conf = config::load(&args).unwrap();
+        let err = Content::new(&conf, &Dirs::new(&conf, &args)).unwrap_err();
+        let err = format!("{}", err);
+        assert!(err.contains("collides with the print page"), "{}", err);
+    }
+
     #[test]
     fn empty_conf() {
         let args = Default::default();
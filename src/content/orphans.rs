@@ -0,0 +1,231 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! Markdown files in a book that no chapter points at.
+//!
+//! It's easy to add a file and forget the summary entry, and then it's just
+//! quietly left out of the build.
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use eyre::{bail, Result, WrapErr};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use tracing::{instrument, warn};
+
+use crate::cli::config::{self, GlobalConf, OrphanLevel, OrphansConf};
+
+/// The orphans in `book`, in path order. Anything in `out_dir` was made by
+/// us, so isn't one.
+#[instrument]
+pub(crate) fn find(
+    book: &config::Book,
+    conf: &OrphansConf,
+    out_dir: &Path,
+) -> Result<Vec<PathBuf>> {
+    let ignore = globs(&conf.ignore)?;
+    let chapters: HashSet<_> = book
+        .summary
+        .chapters()
+        .into_iter()
+        .filter_map(|c| c.location.as_deref())
+        .collect();
+
+    let mut orphans = Vec::new();
+    walk(
+        &book.location,
+        &book.location,
+        out_dir,
+        &chapters,
+        &ignore,
+        &mut orphans,
+    )?;
+    Ok(orphans)
+}
+
+/// Warn about the orphans in every book, or fail if the config says to.
+#[instrument(skip(conf))]
+pub(crate) fn report(conf: &GlobalConf) -> Result<()> {
+    let mut orphans = Vec::new();
+    for book in &conf.books {
+        orphans.extend(find(book, &conf.orphans, &conf.build.out_dir)?);
+    }
+
+    match conf.orphans.level {
+        OrphanLevel::Warn => {
+            for path in &orphans {
+                warn!("{:?} isn't in the SUMMARY.md, so won't be rendered", path);
+            }
+        }
+        OrphanLevel::Error if !orphans.is_empty() => {
+            bail!(
+                "Found markdown files that aren't in a SUMMARY.md: {:?}",
+                orphans
+            )
+        }
+        OrphanLevel::Error => {}
+    }
+    Ok(())
+}
+
+fn walk(
+    book: &Path,
+    dir: &Path,
+    out_dir: &Path,
+    chapters: &HashSet<&Path>,
+    ignore: &GlobSet,
+    orphans: &mut Vec<PathBuf>,
+) -> Result<()> {
+    let mut paths = fs::read_dir(dir)
+        .wrap_err_with(|| format!("Couldn't open {:?}", dir))?
+        .map(|e| Ok(e?.path()))
+        .collect::<Result<Vec<_>>>()?;
+    paths.sort();
+
+    for path in paths {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        // Hidden, or the output dir.
+        if name.starts_with('.') || name.starts_with('_') || path == out_dir {
+            continue;
+        }
+        if ignore.is_match(path.strip_prefix(book)?) {
+            continue;
+        }
+
+        if path.is_dir() {
+            walk(book, &path, out_dir, chapters, ignore, orphans)?;
+        } else if path.extension().is_some_and(|x| x == "md")
+            && name != "SUMMARY.md"
+            && !chapters.contains(path.as_path())
+        {
+            orphans.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn globs(patterns: &[String]) -> Result<GlobSet> {
+    let mut set = GlobSetBuilder::new();
+    for pattern in patterns {
+        // Like a .gitignore, `*` stays in one directory, and a pattern without
+        // a `/` matches at any depth. A leading `/` means the top of the book.
+        let glob = match pattern.strip_prefix('/') {
+            Some(anchored) => anchored.to_owned(),
+            None if !pattern.contains('/') => format!("**/{}", pattern),
+            None => pattern.clone(),
+        };
+        let glob = GlobBuilder::new(&glob)
+            .literal_separator(true)
+            .build()
+            .wrap_err_with(|| format!("Invalid orphans ignore pattern {:?}", pattern))?;
+        set.add(glob);
+    }
+    Ok(set.build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::prelude::*;
+
+    use super::*;
+    use crate::cli;
+
+    #[test]
+    fn finds_orphans() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        temp.child("xmark.toml").write_str("books = ['b']").unwrap();
+        temp.child("b/SUMMARY.md")
+            .write_str("# B\n\n- [One](one.md)\n    - [Two](sub/two.md)\n- [Draft]()\n")
+            .unwrap();
+        let files = [
+            "b/one.md",
+            "b/sub/two.md",
+            "b/sub/three.md",
+            "b/forgot.md",
+            "b/notes.txt",
+            "b/drafts/wip.md",
+            "b/drafts/deeper/wip.md",
+            "b/partials/a.inc.md",
+            "b/top.inc.md",
+            "b/_out/html/x.md",
+            "b/.hidden.md",
+        ];
+        for path in &files {
+            temp.child(path).write_str("# Hi\n").unwrap();
+        }
+
+        let args = cli::Args {
+            dir: temp.path().to_owned(),
+            ..Default::default()
+        };
+        let mut conf = config::load(&args).unwrap();
+        let rel = |conf: &GlobalConf| -> Vec<_> {
+            find(&conf.books[0], &conf.orphans, &conf.build.out_dir)
+                .unwrap()
+                .into_iter()
+                .map(|x| x.strip_prefix(temp.path()).unwrap().to_owned())
+                .collect()
+        };
+
+        assert_eq!(
+            rel(&conf),
+            [
+                "b/drafts/deeper/wip.md",
+                "b/drafts/wip.md",
+                "b/forgot.md",
+                "b/partials/a.inc.md",
+                "b/sub/three.md",
+                "b/top.inc.md",
+            ]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>()
+        );
+        report(&conf).unwrap();
+
+        conf.orphans.ignore = vec!["drafts".into(), "*.inc.md".into()];
+        assert_eq!(
+            rel(&conf),
+            ["b/forgot.md", "b/sub/three.md"]
+                .iter()
+                .map(PathBuf::from)
+                .collect::<Vec<_>>()
+        );
+        conf.orphans.ignore = vec!["/*.inc.md".into(), "sub/*".into()];
+        assert_eq!(
+            rel(&conf),
+            [
+                "b/drafts/deeper/wip.md",
+                "b/drafts/wip.md",
+                "b/forgot.md",
+                "b/partials/a.inc.md",
+            ]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>()
+        );
+
+        conf.orphans.level = OrphanLevel::Error;
+        assert!(report(&conf).is_err());
+        conf.orphans.ignore = vec!["**/*.md".into()];
+        report(&conf).unwrap();
+
+        conf.orphans.ignore = vec!["[".into()];
+        assert!(find(&conf.books[0], &conf.orphans, &conf.build.out_dir).is_err());
+
+        // Wherever the output is.
+        conf.orphans.ignore = vec![];
+        conf.build.out_dir = temp.path().join("b/sub");
+        assert_eq!(
+            rel(&conf),
+            [
+                "b/drafts/deeper/wip.md",
+                "b/drafts/wip.md",
+                "b/forgot.md",
+                "b/partials/a.inc.md",
+                "b/top.inc.md",
+            ]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>()
+        );
+    }
+}
@@ -85,8 +85,13 @@
 //!)
 //!```
 
+use std::collections::HashMap;
+
+use pulldown_cmark::{Event, Options, Parser, Tag};
 use serde::{Deserialize, Serialize};
 
+use crate::html_render::{dedupe, expand_containers, slugify};
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
 pub(crate) struct PageToc(pub(crate) Vec<H2>);
 
@@ -106,3 +111,54 @@ pub(crate) struct H2 {
     pub(crate) this: Link,
     pub(crate) children: Vec<H3>,
 }
+
+impl PageToc {
+    /// The toc for a chapter's markdown (without front matter).
+    ///
+    /// The links are the same ids the html renderer gives the headings.
+    pub(crate) fn new(markdown: &str) -> Self {
+        let markdown = expand_containers(markdown);
+        let mut toc = Vec::new();
+        let mut seen = HashMap::new();
+        let mut current: Option<(u32, String)> = None;
+
+        for ev in Parser::new_ext(&markdown, Options::all()) {
+            match (ev, &mut current) {
+                (Event::Start(Tag::Heading(level)), _) => current = Some((level, String::new())),
+                (Event::Text(t), Some((_, text))) | (Event::Code(t), Some((_, text))) => {
+                    text.push_str(&t)
+                }
+                (Event::End(Tag::Heading(_)), _) => {
+                    let (level, text) = current.take().unwrap_or_default();
+                    let link = Link {
+                        link: dedupe(&mut seen, slugify(&text)),
+                        pritty: text,
+                    };
+                    match (level, toc.last_mut()) {
+                        (2, _) | (3, None) => toc.push(H2 {
+                            this: link,
+                            children: Vec::new(),
+                        }),
+                        (3, Some(H2 { children, .. })) => children.push(H3(link)),
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Self(toc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headings() {
+        let md = "# Control Flow\n\n### Orphan\n\n## If\n### If `else`\n### If else\n\n\
+                  #### Too deep\n\n## Loops\n";
+        insta::assert_yaml_snapshot!(PageToc::new(md));
+    }
+}
@@ -0,0 +1,157 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! `xmark dump`: the resolved site, for other tools to read instead of
+//! scraping the html.
+//!
+//! This is the config and content xmark builds from, plus each page's url,
+//! rendered html and heading tree.
+use std::io::Write;
+use std::path::Path;
+
+use eyre::Result;
+use serde::Serialize;
+use tracing::instrument;
+
+use crate::cli::config::GlobalConf;
+use crate::cli::summary::Summary;
+use crate::cli::{self, DumpFormat};
+use crate::content::pagetoc::PageToc;
+use crate::content::{self, front_matter, Content, Redirect};
+use crate::html_render::render_markdown;
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Site<'a> {
+    pub(crate) base_url: &'a str,
+    pub(crate) books: Vec<Book<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Book<'a> {
+    pub(crate) title: &'a str,
+    pub(crate) location: &'a Path,
+    pub(crate) summary: &'a Summary,
+    pub(crate) redirects: &'a [Redirect],
+    pub(crate) pages: Vec<Page<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Page<'a> {
+    #[serde(flatten)]
+    pub(crate) page: &'a content::Page,
+    /// The title, after front matter.
+    pub(crate) title: &'a str,
+    pub(crate) url: String,
+    pub(crate) html: String,
+    pub(crate) toc: PageToc,
+}
+
+impl<'a> Site<'a> {
+    #[instrument]
+    pub(crate) fn new(
+        conf: &'a GlobalConf,
+        content: &'a Content,
+        dirs: &'a content::Dirs,
+    ) -> Result<Self> {
+        // Content has the books in the same order as the config.
+        let books = conf
+            .books
+            .iter()
+            .zip(&content.0)
+            .map(|(conf, book)| {
+                Ok(Book {
+                    title: &book.title,
+                    location: &book.location,
+                    summary: &conf.summary,
+                    redirects: &book.redirects,
+                    pages: book
+                        .pages
+                        .iter()
+                        .map(|page| {
+                            let (_, body) = front_matter::read(&page.input)?;
+                            Ok(Page {
+                                page,
+                                title: page.title(),
+                                url: page.url(dirs)?,
+                                html: render_markdown(&body),
+                                toc: PageToc::new(&body),
+                            })
+                        })
+                        .collect::<Result<_>>()?,
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            base_url: &dirs.base_url,
+            books,
+        })
+    }
+}
+
+#[instrument(skip(out))]
+pub(crate) fn dump(
+    conf: &GlobalConf,
+    args: &cli::Args,
+    format: DumpFormat,
+    out: impl Write,
+) -> Result<()> {
+    let dirs = content::Dirs::new(conf, args);
+    let content = Content::new(conf, &dirs)?;
+    let site = Site::new(conf, &content, &dirs)?;
+    match format {
+        DumpFormat::Json => serde_json::to_writer_pretty(out, &site)?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::prelude::*;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::cli::config;
+
+    #[test]
+    fn json() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        temp.child("xmark.toml").write_str("books = ['b']").unwrap();
+        temp.child("b/SUMMARY.md")
+            .write_str("# Book B\n\n- [Start](start.md)\n    - [Next](next.md)\n")
+            .unwrap();
+        temp.child("b/start.md")
+            .write_str("+++\ntitle = \"Begin\"\n+++\n# Start\n\n## Setup\n### Linux\n")
+            .unwrap();
+        temp.child("b/next.md").write_str("# Next\n").unwrap();
+
+        let args = cli::Args {
+            dir: temp.path().to_owned(),
+            ..Default::default()
+        };
+        let conf = config::load(&args).unwrap();
+        let mut out = Vec::new();
+        dump(&conf, &args, DumpFormat::Json, &mut out).unwrap();
+        let site: Value = serde_json::from_slice(&out).unwrap();
+
+        assert_eq!(site["base_url"], "/");
+        let book = &site["books"][0];
+        assert_eq!(
+            book["summary"]["numbered_chapters"]
+                .as_array()
+                .unwrap()
+                .len(),
+            1
+        );
+        let start = &book["pages"][0];
+        assert_eq!(start["name"], "Start");
+        assert_eq!(start["title"], "Begin");
+        assert_eq!(start["url"], "/b/start");
+        assert_eq!(start["next"], book["pages"][1]["url"]);
+        assert_eq!(start["front_matter"]["title"], "Begin");
+        assert_eq!(
+            start["html"],
+            "<h1 id=\"start\">Start</h1>\n<h2 id=\"setup\">Setup</h2>\n<h3 id=\"linux\">Linux</h3>\n"
+        );
+        assert_eq!(start["toc"][0]["this"]["link"], "setup");
+        assert_eq!(start["toc"][0]["children"][0]["pritty"], "Linux");
+    }
+}
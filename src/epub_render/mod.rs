@@ -0,0 +1,412 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! An EPUB 3 per book, for e-readers.
+//!
+//! Each chapter becomes an XHTML file in the spine, in book order, and the
+//! navigation document follows the nesting in the `SUMMARY.md`. Images that
+//! chapters reference are copied in, as is a small stylesheet.
+//!
+//! Raw html in chapters is tidied up to be XHTML, but it still has to be
+//! balanced for the book to be valid.
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use std::fs::{self, File};
+use std::io::{Seek, Write};
+use std::path::{Path, PathBuf};
+
+use eyre::{Result, WrapErr};
+use pulldown_cmark::escape::escape_html;
+use ramhorns::{Content as Rhc, Template};
+use tracing::instrument;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::cli;
+use crate::cli::config::{EpubConf, GlobalConf};
+use crate::content::{self, front_matter, links, Book, Content};
+use crate::html_render::{render_xhtml, LinkDest};
+
+const CONTAINER: &str = include_str!("../../www/epub/container.xml");
+const CONTENT_OPF: &str = include_str!("../../www/epub/content.opf");
+const NAV: &str = include_str!("../../www/epub/nav.xhtml");
+const CHAPTER: &str = include_str!("../../www/epub/chapter.xhtml");
+const STYLE: &str = include_str!("../../www/epub/style.css");
+
+pub(crate) struct EpubRender<'a> {
+    content: Content,
+    conf: &'a EpubConf,
+    out_dir: PathBuf,
+}
+
+impl Debug for EpubRender<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EpubRender")
+            .field("out_dir", &self.out_dir)
+            .field("conf", &self.conf)
+            .finish()
+    }
+}
+
+#[derive(Rhc)]
+struct Package<'a> {
+    identifier: String,
+    title: &'a str,
+    language: &'a str,
+    modified: String,
+    chapters: Vec<Item>,
+    images: Vec<Item>,
+}
+
+#[derive(Rhc)]
+struct Item {
+    id: String,
+    href: String,
+    media_type: &'static str,
+}
+
+#[derive(Rhc)]
+struct Xhtml<'a> {
+    title: &'a str,
+    language: &'a str,
+    inner_html: String,
+    toc: String,
+}
+
+impl<'a> EpubRender<'a> {
+    #[instrument]
+    pub(crate) fn new(conf: &GlobalConf, args: &cli::Args, epub: &'a EpubConf) -> Result<Self> {
+        let dirs = content::Dirs::new(conf, args);
+        let content = Content::new(conf, &dirs)?;
+        Ok(Self {
+            content,
+            conf: epub,
+            out_dir: conf.build.out_dir.join("epub"),
+        })
+    }
+
+    #[instrument]
+    pub(crate) fn render(&self) -> Result<()> {
+        if self.out_dir.exists() {
+            fs::remove_dir_all(&self.out_dir)?;
+        }
+        fs::create_dir_all(&self.out_dir)?;
+
+        for book in &self.content.0 {
+            let path = self.out_dir.join(format!("{}.epub", book.dir_name()));
+            let file =
+                File::create(&path).wrap_err_with(|| format!("Failed to create {:?}", path))?;
+            self.write(book, file)?;
+        }
+
+        Ok(())
+    }
+
+    #[instrument(skip(out))]
+    fn write(&self, book: &Book, out: impl Write + Seek) -> Result<()> {
+        let language = self.conf.language.as_deref().unwrap_or("en");
+        let chapter_tpl = Template::new(CHAPTER)?;
+
+        let hrefs: HashMap<&Path, String> = book
+            .pages
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (p.input.as_path(), format!("ch{:03}.xhtml", i + 1)))
+            .collect();
+
+        let mut zip = ZipWriter::new(out);
+        let deflated = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+        // The mimetype has to be first, and uncompressed, so readers can
+        // sniff it.
+        zip.start_file(
+            "mimetype",
+            FileOptions::default().compression_method(CompressionMethod::Stored),
+        )?;
+        zip.write_all(b"application/epub+zip")?;
+
+        zip.start_file("META-INF/container.xml", deflated)?;
+        zip.write_all(CONTAINER.as_bytes())?;
+
+        // Images, by where they are on disk, and their href in the book.
+        let mut images = BTreeMap::new();
+        let mut chapters = Vec::new();
+        for page in &book.pages {
+            let href = &hrefs[page.input.as_path()];
+            let (_, body) = front_matter::read(&page.input)?;
+            let inner_html = render_xhtml(&body, |dest, image| {
+                let link = match links::resolve(&page.input, dest) {
+                    Some(link) => link,
+                    // Remote images would have to be declared, and readers
+                    // mostly don't load them anyway.
+                    None if image && !dest.starts_with("data:") => return LinkDest::Unlink,
+                    None => return LinkDest::Keep,
+                };
+                if let Some(target) = hrefs.get(link.path.as_path()) {
+                    return LinkDest::To(match link.fragment {
+                        Some(frag) => format!("{}#{}", target, frag),
+                        None => target.clone(),
+                    });
+                }
+                // Anything else isn't in the book, so there's nothing to link to.
+                let media_type = match image_type(&link.path) {
+                    Some(media_type) => media_type,
+                    None => return LinkDest::Unlink,
+                };
+                if !link.path.is_file() {
+                    return LinkDest::Unlink;
+                }
+                let ext = link.path.extension().unwrap_or_default();
+                let ext = ext.to_string_lossy().to_lowercase();
+                let next = images.len() + 1;
+                let image = images.entry(link.path).or_insert_with(|| Item {
+                    id: format!("img{:03}", next),
+                    href: format!("images/img{:03}.{}", next, ext),
+                    media_type,
+                });
+                LinkDest::To(image.href.clone())
+            });
+
+            zip.start_file(format!("OEBPS/{}", href), deflated)?;
+            chapter_tpl.render_to_writer(
+                &mut zip,
+                &Xhtml {
+                    title: page.title(),
+                    language,
+                    inner_html,
+                    toc: String::new(),
+                },
+            )?;
+
+            chapters.push(Item {
+                id: href.trim_end_matches(".xhtml").to_owned(),
+                href: href.clone(),
+                media_type: "application/xhtml+xml",
+            });
+        }
+
+        for (path, image) in &images {
+            zip.start_file(format!("OEBPS/{}", image.href), deflated)?;
+            zip.write_all(&fs::read(path).wrap_err_with(|| format!("Couldn't open {:?}", path))?)?;
+        }
+
+        zip.start_file("OEBPS/style.css", deflated)?;
+        zip.write_all(STYLE.as_bytes())?;
+
+        zip.start_file("OEBPS/nav.xhtml", deflated)?;
+        Template::new(NAV)?.render_to_writer(
+            &mut zip,
+            &Xhtml {
+                title: &book.title,
+                language,
+                inner_html: String::new(),
+                toc: nav_toc(book, &hrefs),
+            },
+        )?;
+
+        zip.start_file("OEBPS/content.opf", deflated)?;
+        Template::new(CONTENT_OPF)?.render_to_writer(
+            &mut zip,
+            &Package {
+                identifier: format!("urn:xmark:{}", book.dir_name()),
+                title: &book.title,
+                language,
+                modified: chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+                chapters,
+                images: images.into_values().collect(),
+            },
+        )?;
+
+        zip.finish()?;
+        Ok(())
+    }
+}
+
+/// The media types readers have to support, by extension.
+fn image_type(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?.to_str()?.to_lowercase();
+    Some(match ext.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        _ => return None,
+    })
+}
+
+/// The nested `<ol>` for the navigation document.
+fn nav_toc(book: &Book, hrefs: &HashMap<&Path, String>) -> String {
+    let mut out = String::from("<ol>");
+    let mut current_level = 0;
+
+    for (i, page) in book.pages.iter().enumerate() {
+        // The heirachy has the book, and the page itself. Each level can only
+        // be one deeper than the last, as every `<ol>` needs a parent.
+        let level = page.heirachy.len().saturating_sub(2);
+        let level = if i == 0 {
+            0
+        } else {
+            level.min(current_level + 1)
+        };
+
+        if i != 0 {
+            if level > current_level {
+                out.push_str("<ol>");
+            } else {
+                out.push_str("</li>");
+                for _ in level..current_level {
+                    out.push_str("</ol></li>");
+                }
+            }
+        }
+        current_level = level;
+
+        out.push_str("<li><a href=\"");
+        escape_html(&mut out, &hrefs[page.input.as_path()]).unwrap();
+        out.push_str("\">");
+        if let Some(number) = &page.section_number {
+            escape_html(&mut out, &number.to_string()).unwrap();
+            out.push(' ');
+        }
+        escape_html(&mut out, page.title()).unwrap();
+        out.push_str("</a>");
+    }
+
+    if !book.pages.is_empty() {
+        out.push_str("</li>");
+    }
+    for _ in 0..current_level {
+        out.push_str("</ol></li>");
+    }
+    out.push_str("</ol>");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use assert_fs::prelude::*;
+    use zip::ZipArchive;
+
+    use super::*;
+    use crate::cli::config;
+    use crate::content::{Link, Page};
+
+    fn read(zip: &mut ZipArchive<Cursor<Vec<u8>>>, name: &str) -> String {
+        let mut out = String::new();
+        zip.by_name(name).unwrap().read_to_string(&mut out).unwrap();
+        out
+    }
+
+    fn page(temp: &assert_fs::TempDir, path: &str, depth: usize, md: &str) -> Page {
+        temp.child(path).write_str(md).unwrap();
+        Page {
+            name: path.into(),
+            input: temp.path().join(path),
+            heirachy: vec![
+                Link {
+                    prity: "B".into(),
+                    link: "/".into()
+                };
+                depth + 2
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn chapters_and_images() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        temp.child("b/img/cat.PNG").write_binary(b"meow").unwrap();
+        let book = Book {
+            title: "B & Co".into(),
+            location: temp.path().join("b"),
+            pages: vec![
+                page(
+                    &temp,
+                    "b/a.md",
+                    0,
+                    "# A\n\n![cat](img/cat.PNG) [b](sub/b.md#x) [src](../main.rs)\n\n# ***\n",
+                ),
+                page(&temp, "b/sub/b.md", 1, "# B\n\n![cat](../img/cat.PNG)\n"),
+                page(
+                    &temp,
+                    "b/sub/c.md",
+                    2,
+                    "# C\n\n![gone](nope.png) ![web](https://example.com/x.png)\n",
+                ),
+                page(&temp, "b/d.md", 0, "# D\n"),
+            ],
+            ..Default::default()
+        };
+
+        let conf = EpubConf::default();
+        let render = EpubRender {
+            content: Content(vec![]),
+            conf: &conf,
+            out_dir: temp.path().into(),
+        };
+        let mut out = Cursor::new(Vec::new());
+        render.write(&book, &mut out).unwrap();
+        let mut zip = ZipArchive::new(out).unwrap();
+
+        let first = zip.by_index(0).unwrap();
+        assert_eq!(first.name(), "mimetype");
+        assert_eq!(first.compression(), CompressionMethod::Stored);
+        drop(first);
+
+        insta::assert_snapshot!(read(&mut zip, "OEBPS/nav.xhtml"));
+        let a = read(&mut zip, "OEBPS/ch001.xhtml");
+        assert!(a.contains(r#"<img src="images/img001.png" alt="cat" />"#));
+        assert!(a.contains(r#"<a href="ch002.xhtml#x">"#));
+        assert!(a.contains("</a> src</p>"));
+        assert!(a.contains(r#"<h1 id="heading">"#));
+        let b = read(&mut zip, "OEBPS/ch002.xhtml");
+        assert!(b.contains(r#"<img src="images/img001.png" alt="cat" />"#));
+        let c = read(&mut zip, "OEBPS/ch003.xhtml");
+        assert!(c.contains("<p>gone web</p>"));
+        assert_eq!(read(&mut zip, "OEBPS/images/img001.png"), "meow");
+        let style = read(&mut zip, "OEBPS/style.css");
+        assert!(style.contains(".admonition.warning"));
+        assert!(!style.contains("var("));
+
+        let opf = read(&mut zip, "OEBPS/content.opf");
+        assert!(opf.contains(r#"<dc:title>B &amp; Co</dc:title>"#));
+        assert!(
+            opf.contains(r#"<item id="img001" href="images/img001.png" media-type="image/png"/>"#)
+        );
+        assert_eq!(opf.matches("<itemref").count(), 4);
+    }
+
+    #[test]
+    fn dummy_e2e() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        temp.copy_from(
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("dummy-book"),
+            &["xmark.toml", "book-*/**"],
+        )
+        .unwrap();
+
+        let args = cli::Args {
+            dir: temp.path().to_owned(),
+            ..Default::default()
+        };
+        let conf = config::load(&args).unwrap();
+        let epub = EpubConf::default();
+        EpubRender::new(&conf, &args, &epub)
+            .unwrap()
+            .render()
+            .unwrap();
+
+        for book in &["book-1", "book-2", "book-3"] {
+            let out =
+                fs::read(temp.path().join("_out/epub").join(format!("{}.epub", book))).unwrap();
+            let mut zip = ZipArchive::new(Cursor::new(out)).unwrap();
+            assert!(read(&mut zip, "OEBPS/content.opf").contains("<dc:language>en</dc:language>"));
+        }
+
+        let book3 = fs::read(temp.path().join("_out/epub/book-3.epub")).unwrap();
+        let mut zip = ZipArchive::new(Cursor::new(book3)).unwrap();
+        insta::assert_snapshot!(read(&mut zip, "OEBPS/nav.xhtml"));
+    }
+}
@@ -0,0 +1,353 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! Admonitions, aka callouts.
+//!
+//! These come in two flavours. The GitHub style blockquote
+//!
+//! ```markdown
+//! > [!WARNING] Optional title
+//! > This is **very** important.
+//! ```
+//!
+//! And the fenced container style (from docusaurus, vuepress, and friends)
+//!
+//! ```markdown
+//! :::tip Optional title
+//! This is a *helpful* tip.
+//! :::
+//! ```
+//!
+//! Both render to the same html, a `div.admonition.<kind>`, which is styled by
+//! `css/admonitions.css`. The body is still normal markdown, and goes through
+//! the same parser as the rest of the page.
+use std::borrow::Cow;
+use std::collections::VecDeque;
+
+use pulldown_cmark::escape::escape_html;
+use pulldown_cmark::{CowStr, Event, Tag};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Kind {
+    Note,
+    Tip,
+    Important,
+    Warning,
+    Caution,
+}
+
+impl Kind {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name.to_ascii_lowercase().as_str() {
+            "note" | "info" => Kind::Note,
+            "tip" | "hint" => Kind::Tip,
+            "important" => Kind::Important,
+            "warning" => Kind::Warning,
+            "caution" | "danger" => Kind::Caution,
+            _ => return None,
+        })
+    }
+
+    fn class(self) -> &'static str {
+        match self {
+            Kind::Note => "note",
+            Kind::Tip => "tip",
+            Kind::Important => "important",
+            Kind::Warning => "warning",
+            Kind::Caution => "caution",
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            Kind::Note => "Note",
+            Kind::Tip => "Tip",
+            Kind::Important => "Important",
+            Kind::Warning => "Warning",
+            Kind::Caution => "Caution",
+        }
+    }
+
+    /// The FontAwesome 4 icon.
+    fn icon(self) -> &'static str {
+        match self {
+            Kind::Note => "fa-info-circle",
+            Kind::Tip => "fa-lightbulb-o",
+            Kind::Important => "fa-exclamation-circle",
+            Kind::Warning => "fa-exclamation-triangle",
+            Kind::Caution => "fa-ban",
+        }
+    }
+}
+
+/// Parse the `NOTE] title` / `note title` bit of an admonition header.
+fn parse_header(kind: &str, title: &str) -> Option<(Kind, Option<String>)> {
+    let kind = Kind::parse(kind.trim())?;
+    let title = title.trim();
+    let title = if title.is_empty() {
+        None
+    } else {
+        Some(title.to_owned())
+    };
+    Some((kind, title))
+}
+
+fn open_html(kind: Kind, title: Option<&str>) -> String {
+    let mut out = format!(
+        "<div class=\"admonition {}\">\n<p class=\"admonition-title\"><i class=\"fa {}\"></i> ",
+        kind.class(),
+        kind.icon()
+    );
+    escape_html(&mut out, title.unwrap_or_else(|| kind.title())).unwrap();
+    out.push_str("</p>\n");
+    out
+}
+
+const CLOSE_HTML: &str = "</div>\n";
+
+/// Turn `:::kind` fenced containers into html blocks.
+///
+/// This has to happen before the markdown is parsed, as otherwise the `:::`
+/// lines get glued onto the paragraphs around them. The blank lines around the
+/// `<div>`s make sure pulldown treats the body as markdown, not html.
+pub(crate) fn expand_containers(src: &str) -> Cow<'_, str> {
+    if !src.contains(":::") {
+        return Cow::Borrowed(src);
+    }
+
+    let mut out = String::with_capacity(src.len());
+    // Whether each open container is one of ours. Ones we don't know are left
+    // as text, but still need to eat their own closing `:::`.
+    let mut open: Vec<bool> = Vec::new();
+    // The fence of the code block we're in, if any.
+    let mut code_fence: Option<&str> = None;
+
+    for line in src.split_inclusive('\n') {
+        let trimmed = line.trim_start_matches(' ');
+        // Anything indented more than this is a code block, or in a list.
+        let indented = line.len() - trimmed.len() > 3;
+        let trimmed = trimmed.trim_end();
+
+        if let Some(fence) = code_fence {
+            if trimmed.starts_with(fence) && trimmed.trim_start_matches(&fence[..1]).is_empty() {
+                code_fence = None;
+            }
+            out.push_str(line);
+            continue;
+        }
+
+        if !indented {
+            if let Some(fence) = ["```", "~~~"].iter().find(|f| trimmed.starts_with(*f)) {
+                let len = trimmed.len() - trimmed.trim_start_matches(&fence[..1]).len();
+                code_fence = Some(&trimmed[..len]);
+                out.push_str(line);
+                continue;
+            }
+
+            if let Some(rest) = trimmed.strip_prefix(":::") {
+                let rest = rest.trim_start_matches(':');
+                if rest.trim().is_empty() {
+                    if let Some(true) = open.pop() {
+                        out.push('\n');
+                        out.push_str(CLOSE_HTML);
+                        out.push('\n');
+                        continue;
+                    }
+                    out.push_str(line);
+                    continue;
+                }
+                let (kind, title) = rest
+                    .trim_start()
+                    .split_once(' ')
+                    .unwrap_or((rest.trim(), ""));
+                if let Some((kind, title)) = parse_header(kind, title) {
+                    open.push(true);
+                    out.push('\n');
+                    out.push_str(&open_html(kind, title.as_deref()));
+                    out.push('\n');
+                    continue;
+                }
+                open.push(false);
+            }
+        }
+
+        out.push_str(line);
+    }
+
+    // Be forgiving about unclosed containers.
+    for _ in open.iter().filter(|&&ours| ours) {
+        out.push('\n');
+        out.push_str(CLOSE_HTML);
+    }
+
+    Cow::Owned(out)
+}
+
+/// Turns `> [!KIND]` blockquotes into admonitions.
+pub(crate) struct Admonitions<'a, I: Iterator<Item = Event<'a>>> {
+    inner: I,
+    pending: VecDeque<Event<'a>>,
+}
+
+impl<'a, I: Iterator<Item = Event<'a>>> Admonitions<'a, I> {
+    pub(crate) fn new(inner: I) -> Self {
+        Self {
+            inner,
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl<'a, I: Iterator<Item = Event<'a>>> Iterator for Admonitions<'a, I> {
+    type Item = Event<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(ev) = self.pending.pop_front() {
+            return Some(ev);
+        }
+
+        match self.inner.next()? {
+            Event::Start(Tag::BlockQuote) => {
+                // We can only tell if it's an admonition once we've seen the
+                // contents, so buffer the whole thing.
+                let mut quote = Vec::new();
+                let mut depth = 1;
+                for ev in &mut self.inner {
+                    match ev {
+                        Event::Start(Tag::BlockQuote) => depth += 1,
+                        Event::End(Tag::BlockQuote) => {
+                            depth -= 1;
+                            if depth == 0 {
+                                break;
+                            }
+                        }
+                        _ => {}
+                    }
+                    quote.push(ev);
+                }
+                self.pending.extend(expand_quote(quote));
+                self.pending.pop_front()
+            }
+            ev => Some(ev),
+        }
+    }
+}
+
+/// Takes the contents of a blockquote (without the start and end tags).
+fn expand_quote(quote: Vec<Event<'_>>) -> Vec<Event<'_>> {
+    let header = match quote_header(&quote) {
+        Some(h) => h,
+        None => {
+            let mut out = vec![Event::Start(Tag::BlockQuote)];
+            out.extend(Admonitions::new(quote.into_iter()));
+            out.push(Event::End(Tag::BlockQuote));
+            return out;
+        }
+    };
+    let (kind, title, header_len) = header;
+
+    let mut rest = quote.into_iter().skip(header_len).peekable();
+    let mut out = vec![Event::Html(CowStr::from(open_html(kind, title.as_deref())))];
+
+    if let Some(Event::SoftBreak) | Some(Event::HardBreak) = rest.peek() {
+        rest.next();
+    }
+    // Don't leave an empty paragraph where the header was.
+    if let Some(Event::End(Tag::Paragraph)) = rest.peek() {
+        rest.next();
+    } else {
+        out.push(Event::Start(Tag::Paragraph));
+    }
+
+    out.extend(Admonitions::new(rest));
+    out.push(Event::Html(CowStr::Borrowed(CLOSE_HTML)));
+    out
+}
+
+/// If the blockquote starts with `[!KIND] title`, get the kind, the title, and
+/// how many events it took up.
+fn quote_header(quote: &[Event<'_>]) -> Option<(Kind, Option<String>, usize)> {
+    if quote.first() != Some(&Event::Start(Tag::Paragraph)) {
+        return None;
+    }
+
+    // pulldown splits `[!NOTE]` into `[`, `!NOTE` and `]`.
+    let mut first_line = String::new();
+    let mut len = 1;
+    for ev in &quote[1..] {
+        match ev {
+            Event::Text(t) => first_line.push_str(t),
+            _ => break,
+        }
+        len += 1;
+    }
+
+    let (kind, title) = first_line
+        .trim_start()
+        .strip_prefix("[!")?
+        .split_once(']')?;
+    let (kind, title) = parse_header(kind, title)?;
+    Some((kind, title, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use insta::assert_snapshot;
+
+    use super::*;
+    use crate::html_render::tpl_types::render_markdown;
+
+    #[test]
+    fn kinds() {
+        assert_eq!(Kind::parse("NOTE"), Some(Kind::Note));
+        assert_eq!(Kind::parse("Warning"), Some(Kind::Warning));
+        assert_eq!(Kind::parse("danger"), Some(Kind::Caution));
+        assert_eq!(Kind::parse("nope"), None);
+    }
+
+    #[test]
+    fn blockquote() {
+        let src = "> [!NOTE]\n> Some *text*\n>\n> - a list\n";
+        assert_snapshot!(render_markdown(src));
+    }
+
+    #[test]
+    fn blockquote_title() {
+        let src = "> [!warning] Mind the gap & stuff\n> body\n";
+        assert_snapshot!(render_markdown(src));
+    }
+
+    #[test]
+    fn plain_blockquote() {
+        let src = "> [link]\n> > [!TIP]\n> > nested\n";
+        assert_snapshot!(render_markdown(src));
+    }
+
+    #[test]
+    fn container() {
+        let src = "Before\n:::tip\nA **tip**\n\n```\n:::\n```\n:::\nAfter\n";
+        assert_snapshot!(render_markdown(src));
+    }
+
+    #[test]
+    fn nested_container() {
+        let src = ":::caution Careful now\n> [!NOTE]\n> inner\n\n:::important\ndeeper\n";
+        assert_snapshot!(render_markdown(src));
+    }
+
+    #[test]
+    fn unknown_container_is_text() {
+        assert_eq!(
+            expand_containers(":::nope\nhi\n:::\n"),
+            ":::nope\nhi\n:::\n"
+        );
+    }
+
+    #[test]
+    fn unknown_container_inside() {
+        let src = ":::note\n:::details\nHidden.\n:::\nStill in the note.\n:::\nAfter.\n";
+        let html = render_markdown(src);
+        let inside = &html[..html.find("</div>").unwrap()];
+        assert!(inside.contains("Still in the note."));
+        assert!(html.ends_with("</div>\n<p>After.</p>\n"));
+        assert_eq!(html.matches("</div>").count(), 1);
+    }
+}
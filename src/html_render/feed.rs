@@ -0,0 +1,311 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! Atom feeds of the most recent chapters, for each book or the whole site.
+//!
+//! Chapters are dated by the `date` in their front matter, or else when they
+//! were last committed. Ones with neither are left out, as a file's own time
+//! is just when it was checked out. Like the sitemap, feeds need absolute
+//! urls, so there aren't any unless the `site-url` has a host.
+use std::cmp::Reverse;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, FixedOffset, SecondsFormat, Utc};
+use eyre::{Result, WrapErr};
+use pulldown_cmark::escape::escape_html;
+use tracing::{debug, instrument};
+
+use crate::cli::config::{FeedConf, FeedScope};
+use crate::content::collect::output_loc;
+use crate::content::{excerpt, front_matter, git, links, Content, Dirs, Page};
+
+/// A feed to write, and which books go in it.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Feed {
+    pub(crate) title: String,
+    pub(crate) output: PathBuf,
+    /// The url of what the feed is for.
+    home: String,
+    books: Vec<PathBuf>,
+    limit: usize,
+}
+
+impl Feed {
+    /// Whether the pages of the book at `location` should link to this feed.
+    pub(crate) fn has_book(&self, location: &Path) -> bool {
+        self.books.iter().any(|b| b == location)
+    }
+}
+
+#[instrument(skip(content))]
+pub(crate) fn feeds(content: &Content, dirs: &Dirs, conf: Option<&FeedConf>) -> Result<Vec<Feed>> {
+    let conf = match (conf, &dirs.origin) {
+        (Some(conf), Some(_)) => conf,
+        (Some(_), None) => {
+            debug!("The site-url has no host, so there's no feed");
+            return Ok(Vec::new());
+        }
+        (None, _) => return Ok(Vec::new()),
+    };
+
+    match conf.scope {
+        FeedScope::Book => content
+            .0
+            .iter()
+            .map(|book| {
+                let index = output_loc(&book.location.join("README.md"), dirs)?;
+                Ok(Feed {
+                    title: book.title.clone(),
+                    output: index.with_file_name("feed.xml"),
+                    home: dirs.url(&index)?,
+                    books: vec![book.location.clone()],
+                    limit: conf.limit,
+                })
+            })
+            .collect(),
+        FeedScope::Site => Ok(vec![Feed {
+            title: conf.title.clone().unwrap_or_else(|| {
+                let titles: Vec<_> = content.0.iter().map(|b| b.title.as_str()).collect();
+                titles.join(", ")
+            }),
+            output: dirs.out_dir.join("feed.xml"),
+            home: dirs.base_url.clone(),
+            books: content.0.iter().map(|b| b.location.clone()).collect(),
+            limit: conf.limit,
+        }]),
+    }
+}
+
+#[instrument(skip(content))]
+pub(crate) fn write(
+    feed: &Feed,
+    content: &Content,
+    dirs: &Dirs,
+    commits: &git::Commits,
+) -> Result<()> {
+    let origin = dirs.origin.as_deref().unwrap_or_default();
+
+    let mut entries = content
+        .0
+        .iter()
+        .filter(|b| feed.has_book(&b.location))
+        .flat_map(|b| &b.pages)
+        .filter_map(|page| Some((date(page, commits)?, page)))
+        .collect::<Vec<_>>();
+    // Newest first, and then in book order.
+    entries.sort_by_key(|(date, _)| Reverse(*date));
+    entries.truncate(feed.limit);
+
+    let updated = entries
+        .first()
+        .map_or_else(|| Utc::now().into(), |(date, _)| *date);
+    let home = format!("{}{}", origin, links::percent_encode(&feed.home));
+
+    let mut out = String::from(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+         <feed xmlns=\"http://www.w3.org/2005/Atom\">\n",
+    );
+    element(&mut out, 1, "title", &feed.title)?;
+    element(&mut out, 1, "id", &home)?;
+    link(&mut out, 1, None, &home)?;
+    link(
+        &mut out,
+        1,
+        Some("self"),
+        &format!(
+            "{}{}",
+            origin,
+            links::percent_encode(&dirs.href(&feed.output)?)
+        ),
+    )?;
+    element(&mut out, 1, "updated", &timestamp(&updated))?;
+    out.push_str("  <author>\n");
+    element(&mut out, 2, "name", &feed.title)?;
+    out.push_str("  </author>\n");
+
+    for (date, page) in entries {
+        let url = format!("{}{}", origin, links::percent_encode(&page.url(dirs)?));
+        let (_, body) = front_matter::read(&page.input)?;
+        out.push_str("  <entry>\n");
+        element(&mut out, 2, "title", page.title())?;
+        element(&mut out, 2, "id", &url)?;
+        link(&mut out, 2, None, &url)?;
+        element(&mut out, 2, "updated", &timestamp(&date))?;
+        let summary = page
+            .front_matter
+            .description
+            .clone()
+            .or_else(|| excerpt::excerpt(&body));
+        if let Some(summary) = summary {
+            element(&mut out, 2, "summary", &summary)?;
+        }
+        out.push_str("  </entry>\n");
+    }
+    out.push_str("</feed>\n");
+
+    if let Some(parent) = feed.output.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&feed.output, out).wrap_err_with(|| format!("Failed to create {:?}", feed.output))
+}
+
+fn date(page: &Page, commits: &git::Commits) -> Option<DateTime<FixedOffset>> {
+    page.front_matter
+        .date()
+        .or_else(|| commits.get(&page.input).copied())
+}
+
+fn timestamp(date: &DateTime<FixedOffset>) -> String {
+    date.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn element(out: &mut String, indent: usize, name: &str, text: &str) -> Result<()> {
+    out.push_str(&"  ".repeat(indent));
+    out.push_str(&format!("<{}>", name));
+    escape_html(&mut *out, text)?;
+    out.push_str(&format!("</{}>\n", name));
+    Ok(())
+}
+
+fn link(out: &mut String, indent: usize, rel: Option<&str>, href: &str) -> Result<()> {
+    out.push_str(&"  ".repeat(indent));
+    out.push_str("<link");
+    if let Some(rel) = rel {
+        out.push_str(&format!(" rel=\"{}\"", rel));
+    }
+    out.push_str(" href=\"");
+    escape_html(&mut *out, href)?;
+    out.push_str("\"/>\n");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::prelude::*;
+
+    use crate::cli::{self, config};
+    use crate::html_render::HTMLRender;
+
+    use super::*;
+
+    fn render(dir: &Path, toml: &str) {
+        render_files(
+            dir,
+            &[
+                ("xmark.toml", toml),
+                (
+                    "log/SUMMARY.md",
+                    "# Changelog\n\n- [Old](old.md)\n- [New](new.md)\n- [Undated](undated.md)\n",
+                ),
+                (
+                    "log/old.md",
+                    "+++\ndate = 2020-01-01\ndescription = 'Where it began'\n+++\n# Old\n\n\
+                     The first release.\n",
+                ),
+                (
+                    "log/new.md",
+                    "---\ndate: '2020-11-01T12:00:00+01:00'\n---\n# New\n\nFixed *all* the bugs & more.\n",
+                ),
+                ("log/undated.md", "# Undated\n\nRight now.\n"),
+                ("b/SUMMARY.md", "# B\n\n- [One](one.md)\n"),
+                ("b/one.md", "+++\ndate = 2019-01-01\n+++\n# One\n"),
+            ],
+        );
+    }
+
+    fn render_files(dir: &Path, files: &[(&str, &str)]) {
+        for (path, contents) in files {
+            fs::create_dir_all(dir.join(path).parent().unwrap()).unwrap();
+            fs::write(dir.join(path), contents).unwrap();
+        }
+
+        let args = cli::Args {
+            dir: dir.to_owned(),
+            ..Default::default()
+        };
+        let conf = config::load(&args).unwrap();
+        HTMLRender::new(&conf, &args).unwrap().render().unwrap();
+    }
+
+    fn titles(feed: &str) -> Vec<&str> {
+        feed.lines()
+            .filter_map(|l| l.trim().strip_prefix("<title>")?.strip_suffix("</title>"))
+            .collect()
+    }
+
+    #[test]
+    fn book_feeds() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        render(
+            temp.path(),
+            "books = ['log', 'b']\n[html]\nsite-url = 'https://host/docs'\n\
+             [html.feed]\nlimit = 2\n",
+        );
+
+        let feed = fs::read_to_string(temp.child("_out/html/log/feed.xml").path()).unwrap();
+        // The undated one isn't committed, so it's left out.
+        assert_eq!(titles(&feed), ["Changelog", "New", "Old"]);
+        assert!(feed.contains("<id>https://host/docs/log</id>"));
+        assert!(feed.contains("<link rel=\"self\" href=\"https://host/docs/log/feed.xml\"/>"));
+        assert!(feed.contains("<link href=\"https://host/docs/log/new\"/>"));
+        assert!(feed.contains("<updated>2020-11-01T12:00:00+01:00</updated>"));
+        assert!(feed.contains("<summary>Fixed all the bugs &amp; more.</summary>"));
+        assert!(feed.contains("<summary>Where it began</summary>"));
+        assert!(temp.child("_out/html/b/feed.xml").path().exists());
+
+        let page = fs::read_to_string(temp.child("_out/html/log/new/index.html").path()).unwrap();
+        assert!(page.contains(
+            "<link rel=\"alternate\" type=\"application/atom+xml\" title=\"Changelog\" \
+             href=\"/docs/log/feed.xml\">"
+        ));
+    }
+
+    #[test]
+    fn site_feed() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        render(
+            temp.path(),
+            "books = ['log', 'b']\n[html]\nsite-url = 'https://host'\n\
+             [html.feed]\nscope = 'site'\n",
+        );
+        let feed = fs::read_to_string(temp.child("_out/html/feed.xml").path()).unwrap();
+        assert_eq!(titles(&feed), ["Changelog, B", "New", "Old", "One"]);
+        assert!(!temp.child("_out/html/log/feed.xml").path().exists());
+
+        // No host, no feed.
+        render(
+            temp.path(),
+            "books = ['log', 'b']\n[html.feed]\nscope = 'site'\n",
+        );
+        assert!(!temp.child("_out/html/feed.xml").path().exists());
+        let page = fs::read_to_string(temp.child("_out/html/b/one/index.html").path()).unwrap();
+        assert!(!page.contains("application/atom+xml"));
+    }
+
+    #[test]
+    fn encoded_urls() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        render_files(
+            temp.path(),
+            &[
+                (
+                    "xmark.toml",
+                    "books = ['mes notes']\n[html]\nsite-url = 'https://host'\n[html.feed]\n",
+                ),
+                (
+                    "mes notes/SUMMARY.md",
+                    "# Notes\n\n- [Café](<café au lait.md>)\n",
+                ),
+                (
+                    "mes notes/café au lait.md",
+                    "+++\ndate = 2020-01-01\n+++\n# Café\n",
+                ),
+            ],
+        );
+
+        let feed = fs::read_to_string(temp.child("_out/html/mes notes/feed.xml").path()).unwrap();
+        assert!(feed.contains("<id>https://host/mes%20notes</id>"));
+        assert!(feed.contains("<link rel=\"self\" href=\"https://host/mes%20notes/feed.xml\"/>"));
+        assert!(feed.contains("<id>https://host/mes%20notes/caf%C3%A9%20au%20lait</id>"));
+        assert!(feed.contains("<link href=\"https://host/mes%20notes/caf%C3%A9%20au%20lait\"/>"));
+    }
+}
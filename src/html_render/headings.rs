@@ -0,0 +1,141 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! Give every heading an `id`, so it can be linked to.
+use std::collections::{HashMap, VecDeque};
+
+use pulldown_cmark::{CowStr, Event, Tag};
+
+/// Turn a heading into something usable as an id.
+///
+/// This matches what mdBook (and roughly what GitHub) does, so links to
+/// `#some-heading` keep working. Headings with nothing usable in them (like
+/// `# ***`) get `heading`, as an empty id isn't valid.
+pub(crate) fn slugify(text: &str) -> String {
+    let slug: String = text
+        .chars()
+        .filter_map(|ch| {
+            if ch.is_alphanumeric() || ch == '_' || ch == '-' {
+                Some(ch.to_ascii_lowercase())
+            } else if ch.is_whitespace() {
+                Some('-')
+            } else {
+                None
+            }
+        })
+        .collect();
+    if slug.is_empty() {
+        "heading".to_owned()
+    } else {
+        slug
+    }
+}
+
+/// Make `id` unique among the ids in `seen`, by adding `-1`, `-2`, etc.
+pub(crate) fn dedupe(seen: &mut HashMap<String, usize>, id: String) -> String {
+    let count = seen.entry(id.clone()).or_insert(0);
+    let id = if *count > 0 {
+        format!("{}-{}", id, count)
+    } else {
+        id
+    };
+    *count += 1;
+    id
+}
+
+pub(crate) struct HeadingIds<'a, 'p, I: Iterator<Item = Event<'a>>> {
+    inner: I,
+    /// Put in front of every id, so multiple chapters can share a page.
+    prefix: Option<&'p str>,
+    /// How many times we've seen each id, to keep them unique.
+    seen: HashMap<String, usize>,
+    pending: VecDeque<Event<'a>>,
+}
+
+impl<'a, 'p, I: Iterator<Item = Event<'a>>> HeadingIds<'a, 'p, I> {
+    pub(crate) fn new(inner: I, prefix: Option<&'p str>) -> Self {
+        Self {
+            inner,
+            prefix,
+            seen: HashMap::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn unique_id(&mut self, text: &str) -> String {
+        let id = dedupe(&mut self.seen, slugify(text));
+        match self.prefix {
+            Some(prefix) => format!("{}--{}", prefix, id),
+            None => id,
+        }
+    }
+}
+
+impl<'a, 'p, I: Iterator<Item = Event<'a>>> Iterator for HeadingIds<'a, 'p, I> {
+    type Item = Event<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(ev) = self.pending.pop_front() {
+            return Some(ev);
+        }
+
+        match self.inner.next()? {
+            Event::Start(Tag::Heading(level)) => {
+                let mut text = String::new();
+                for ev in &mut self.inner {
+                    match &ev {
+                        Event::End(Tag::Heading(_)) => break,
+                        Event::Text(t) | Event::Code(t) => text.push_str(t),
+                        _ => {}
+                    }
+                    self.pending.push_back(ev);
+                }
+                let id = self.unique_id(&text);
+                self.pending
+                    .push_back(Event::Html(CowStr::from(format!("</h{}>\n", level))));
+                Some(Event::Html(CowStr::from(format!(
+                    "<h{} id=\"{}\">",
+                    level, id
+                ))))
+            }
+            ev => Some(ev),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pulldown_cmark::{html, Parser};
+
+    use super::*;
+
+    fn render(src: &str, prefix: Option<&str>) -> String {
+        let mut out = String::new();
+        html::push_html(&mut out, HeadingIds::new(Parser::new(src), prefix));
+        out
+    }
+
+    #[test]
+    fn slugs() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(slugify("for .. in .."), "for--in-");
+        assert_eq!(slugify("snake_case & kebab-case"), "snake_case--kebab-case");
+        assert_eq!(slugify("!!!"), "heading");
+    }
+
+    #[test]
+    fn ids() {
+        assert_eq!(
+            render("# Hello *there*\n## `code`\n# Hello there\n", None),
+            "<h1 id=\"hello-there\">Hello <em>there</em></h1>\n\
+             <h2 id=\"code\"><code>code</code></h2>\n\
+             <h1 id=\"hello-there-1\">Hello there</h1>\n"
+        );
+    }
+
+    #[test]
+    fn prefixed_ids() {
+        assert_eq!(
+            render("## Usage\n", Some("cli-init")),
+            "<h2 id=\"cli-init--usage\">Usage</h2>\n"
+        );
+    }
+}
@@ -2,19 +2,29 @@
 use std::fmt::Debug;
 use std::fs;
 use std::io::Write;
+use std::path::PathBuf;
 
 use eyre::{Context, Result};
 use fs_extra::dir as fsx;
 use ramhorns::{Content as Rhc, Ramhorns};
-use tracing::instrument;
+use tracing::{instrument, warn};
 
 use crate::cli;
-use crate::cli::config::GlobalConf;
+use crate::cli::config::{GlobalConf, HtmlConf};
 
-use crate::content::{self, Book, Content, Page};
+use crate::content::{self, git, Book, Content, Page};
 
+mod admonition;
+mod feed;
+mod headings;
+mod print;
+mod sitemap;
 mod tpl_types;
+mod xhtml;
 
+pub(crate) use admonition::expand_containers;
+pub(crate) use headings::{dedupe, slugify};
+pub(crate) use tpl_types::{render_markdown, render_xhtml, LinkDest};
 
 /// Singleton
 pub(crate) struct HTMLRender<'a> {
@@ -23,6 +33,11 @@ pub(crate) struct HTMLRender<'a> {
     _args: &'a cli::Args,
     templates: Ramhorns,
     pub(crate) dirs: content::Dirs,
+    pub(crate) html: HtmlConf,
+    pub(crate) feeds: Vec<feed::Feed>,
+    /// What edit links are relative to, if there are any.
+    pub(crate) repo_root: Option<PathBuf>,
+    pub(crate) commits: git::Commits,
 }
 
 impl Debug for HTMLRender<'_> {
@@ -45,19 +60,41 @@ impl<'a> HTMLRender<'a> {
             fs::remove_dir_all(&dirs.out_dir)?;
         }
 
-        let parent = dirs.out_dir.parent().unwrap();
-
-        fs::create_dir_all(parent)?;
+        fs::create_dir_all(&dirs.out_dir)?;
 
         fsx::copy(
             concat!(env!("CARGO_MANIFEST_DIR"), "/www/static/"),
-            parent,
-            &Default::default(),
+            &dirs.out_dir,
+            &fsx::CopyOptions {
+                content_only: true,
+                ..Default::default()
+            },
         )?;
 
-        fs::rename(parent.join("static"), &dirs.out_dir)?;
-
         let content = content::Content::new(conf, &dirs)?;
+        let feeds = feed::feeds(&content, &dirs, conf.html.feed.as_ref())?;
+        let repo_root = match &conf.html.edit_url_template {
+            Some(_) => match git::root(&args.dir) {
+                Some(root) => Some(root),
+                None => {
+                    warn!(
+                        "{:?} isn't in a git repo, so edit links are relative to it",
+                        args.dir
+                    );
+                    Some(fs::canonicalize(&args.dir)?)
+                }
+            },
+            None => None,
+        };
+
+        // The pages, sitemap and feeds all want these.
+        let commits = git::last_commits(
+            content
+                .0
+                .iter()
+                .flat_map(|b| &b.pages)
+                .map(|p| p.input.as_path()),
+        );
 
         let templates =
             Ramhorns::from_folder(concat!(env!("CARGO_MANIFEST_DIR"), "/www/templates/"))?;
@@ -67,9 +104,13 @@ impl<'a> HTMLRender<'a> {
             _args: args,
             templates,
             dirs,
+            html: conf.html.clone(),
+            feeds,
+            repo_root,
+            commits,
         })
     }
-    
+
     #[instrument]
     pub(crate) fn render(&self) -> Result<()> {
         //TODO: Rayon
@@ -91,8 +132,24 @@ impl<'a> HTMLRender<'a> {
                 self.templates
                     .get("redirect.html")
                     .unwrap()
-                    .render_to_file(file, &Params { url })?;
+                    .render_to_file(
+                        file,
+                        &Params {
+                            url: &self.dirs.link(file, url)?,
+                        },
+                    )?;
             }
+
+            let print = print::PrintPage::new(book, &self.dirs, self.html.theme(&book.location))?;
+            self.templates
+                .get("print.html")
+                .unwrap()
+                .render_to_file(print::output(book, &self.dirs)?, &print)?;
+        }
+
+        sitemap::write(&self.content, &self.dirs, &self.html.robots, &self.commits)?;
+        for feed in &self.feeds {
+            feed::write(feed, &self.content, &self.dirs, &self.commits)?;
         }
 
         Ok(())
@@ -100,7 +157,7 @@ impl<'a> HTMLRender<'a> {
 
     #[instrument]
     pub(crate) fn render_page(&self, page: &Page, book: &Book) -> Result<String> {
-        let rp = tpl_types::Page::new(page, &self, book)?;
+        let rp = tpl_types::Page::new(page, self, book)?;
         let tpl = self.templates.get("page.html").unwrap();
         // TODO: Use render_to_file or something
         Ok(tpl.render(&rp))
@@ -148,6 +205,247 @@ mod tests {
         })});
     }
 
+    #[test]
+    fn flat_out_of_tree() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let files = [
+            (
+                "src/xmark.toml",
+                "books = ['b']\n[build]\nout-dir = '../site'\nurl-style = 'flat'\n\
+                 [redirects]\n'b/old.md' = 'b/one.md'\n",
+            ),
+            (
+                "src/b/SUMMARY.md",
+                "# B\n\n- [One](one.md)\n    - [Two](sub/two.md)\n",
+            ),
+            ("src/b/one.md", "# One\n"),
+            ("src/b/sub/two.md", "# Two\n"),
+        ];
+        for (path, contents) in &files {
+            temp.child(path).write_str(contents).unwrap();
+        }
+
+        let args = cli::Args {
+            dir: temp.path().join("src"),
+            ..Default::default()
+        };
+        let conf = config::load(&args).unwrap();
+        html_render::HTMLRender::new(&conf, &args)
+            .unwrap()
+            .render()
+            .unwrap();
+
+        assert!(!temp.child("src/_out").path().exists());
+        let out = temp.child("site/html/b");
+        let one = std::fs::read_to_string(out.child("one.html").path()).unwrap();
+        assert!(one.contains("href=\"/b/one.html\""));
+        assert!(one.contains("href=\"/b/sub/two.html\""));
+        // Without a host, there's nothing absolute to point at.
+        assert!(!one.contains("rel=\"canonical\""));
+        let two = std::fs::read_to_string(out.child("sub/two.html").path()).unwrap();
+        assert!(two.contains("href=\"/b/one.html\""));
+        // The book's index, and the old page, redirect to the first chapter.
+        for redirect in &["index.html", "old.html"] {
+            let src = std::fs::read_to_string(out.child(redirect).path()).unwrap();
+            assert!(src.contains("URL='/b/one.html'"), "{}", src);
+        }
+    }
+
+    /// Render a small book, hosted at `/docs/v2/`, and read a page back.
+    fn render_subpath(html: &str, page: &str) -> String {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let files = [
+            (
+                "xmark.toml",
+                &*format!(
+                    "books = ['b']\n[html]\nsite-url = 'https://host/docs/v2'\n{}",
+                    html
+                ),
+            ),
+            (
+                "b/SUMMARY.md",
+                "# B\n\n- [One](one.md)\n    - [Two](sub/two.md)\n\
+                 - [Café](<café au lait.md>)\n",
+            ),
+            (
+                "b/one.md",
+                "+++\ndescription = 'The \"first\" page'\n+++\n# One\n\nNot this.\n",
+            ),
+            ("b/sub/two.md", "# Two\n\nThe *second*\npage.\n"),
+            ("b/café au lait.md", "# Café\n"),
+        ];
+        for (path, contents) in &files {
+            temp.child(path).write_str(contents).unwrap();
+        }
+
+        let args = cli::Args {
+            dir: temp.path().to_owned(),
+            ..Default::default()
+        };
+        let conf = config::load(&args).unwrap();
+        html_render::HTMLRender::new(&conf, &args)
+            .unwrap()
+            .render()
+            .unwrap();
+        std::fs::read_to_string(temp.child("_out/html").child(page).path()).unwrap()
+    }
+
+    #[test]
+    fn subpath_urls() {
+        let two = render_subpath("", "b/sub/two/index.html");
+        assert!(two.contains("href=\"/docs/v2/css/general.css\""));
+        assert!(two.contains("href=\"/docs/v2/b/one/index.html\""));
+        assert!(two.contains("rel=\"prev\" href=\"/docs/v2/b/one\""));
+        assert!(two.contains("href=\"/docs/v2/b/print.html\""));
+
+        let index = render_subpath("", "b/index.html");
+        assert!(index.contains("URL='/docs/v2/b/one'"), "{}", index);
+    }
+
+    #[test]
+    fn relative_links() {
+        let two = render_subpath("relative-links = true", "b/sub/two/index.html");
+        assert!(two.contains("href=\"../../../css/general.css\""));
+        assert!(two.contains("href=\"../../one/index.html\""));
+        assert!(two.contains("rel=\"prev\" href=\"../../one/\""));
+        assert!(two.contains("href=\"../../print.html\""));
+        assert!(!two.contains("href=\"/docs/v2/"));
+
+        let index = render_subpath("relative-links = true", "b/index.html");
+        assert!(index.contains("URL='one/'"), "{}", index);
+    }
+
+    #[test]
+    fn link_previews() {
+        let two = render_subpath("", "b/sub/two/index.html");
+        assert!(two.contains("<link rel=\"canonical\" href=\"https://host/docs/v2/b/sub/two\">"));
+        assert!(two.contains("<meta property=\"og:title\" content=\"Two\">"));
+        assert!(two.contains("<meta property=\"og:site_name\" content=\"B\">"));
+        assert!(two.contains("<meta name=\"description\" content=\"The second page.\">"));
+
+        let one = render_subpath("", "b/one/index.html");
+        assert!(one
+            .contains("<meta property=\"og:description\" content=\"The &quot;first&quot; page\">"));
+
+        let cafe = render_subpath("", "b/café au lait/index.html");
+        assert!(cafe.contains(
+            "<link rel=\"canonical\" href=\"https://host/docs/v2/b/caf%C3%A9%20au%20lait\">"
+        ));
+        assert!(cafe.contains(
+            "<meta property=\"og:url\" content=\"https://host/docs/v2/b/caf%C3%A9%20au%20lait\">"
+        ));
+    }
+
+    #[test]
+    fn themes() {
+        let two = render_subpath("", "b/sub/two/index.html");
+        assert!(two.contains("<html lang=\"en\" class=\"sidebar-visible no-js rust\">"));
+
+        let html = "language = 'fr'\ndefault-theme = 'light'\n\
+                    [html.books.b]\npreferred-dark-theme = 'navy'\n";
+        let two = render_subpath(html, "b/sub/two/index.html");
+        assert!(two.contains("<html lang=\"fr\" class=\"sidebar-visible no-js light\">"));
+        assert!(two.contains("? \"navy\" : \"light\";"));
+    }
+
+    #[test]
+    fn git_links() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let files = [
+            (
+                "docs/xmark.toml",
+                "books = ['b']\n[html]\ngit-repository-url = 'https://example.com/me/docs'\n\
+                 edit-url-template = 'https://example.com/me/docs/edit/main/{path}'\n",
+            ),
+            (
+                "docs/b/SUMMARY.md",
+                "# B\n\n- [One](<my one.md>)\n- [Two](<100% two.md>)\n- [Out](out.md)\n",
+            ),
+            ("docs/b/my one.md", "# One\n"),
+            ("docs/b/100% two.md", "# Two\n"),
+        ];
+        for (path, contents) in &files {
+            temp.child(path).write_str(contents).unwrap();
+        }
+        // A chapter from outside the repo can't be edited in it.
+        let outside = assert_fs::TempDir::new().unwrap();
+        outside.child("out.md").write_str("# Out\n").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(
+            outside.child("out.md").path(),
+            temp.child("docs/b/out.md").path(),
+        )
+        .unwrap();
+        #[cfg(not(unix))]
+        std::fs::copy(
+            outside.child("out.md").path(),
+            temp.child("docs/b/out.md").path(),
+        )
+        .unwrap();
+        for args in &[
+            &["init", "-q"][..],
+            &["add", "."],
+            &["commit", "-q", "-m", "Docs"],
+        ] {
+            let status = std::process::Command::new("git")
+                .args(["-c", "user.name=X", "-c", "user.email=x@example.com"])
+                .args(*args)
+                .env("GIT_COMMITTER_DATE", "2020-11-01T12:00:00+00:00")
+                .current_dir(temp.path())
+                .status()
+                .unwrap();
+            assert!(status.success());
+        }
+
+        let args = cli::Args {
+            dir: temp.path().join("docs"),
+            ..Default::default()
+        };
+        let conf = config::load(&args).unwrap();
+        html_render::HTMLRender::new(&conf, &args)
+            .unwrap()
+            .render()
+            .unwrap();
+
+        let one = std::fs::read_to_string(temp.child("docs/_out/html/b/my one/index.html").path())
+            .unwrap();
+        assert!(one.contains("href=\"https://example.com/me/docs/edit/main/docs/b/my%20one.md\""));
+        assert!(one.contains("Last updated 2020-11-01"));
+        assert!(one.contains("<a href=\"https://example.com/me/docs\" title=\"Git repository\""));
+        assert!(one.contains("class=\"fa fa-github\""));
+
+        let two =
+            std::fs::read_to_string(temp.child("docs/_out/html/b/100% two/index.html").path())
+                .unwrap();
+        assert!(
+            two.contains("href=\"https://example.com/me/docs/edit/main/docs/b/100%25%20two.md\"")
+        );
+        #[cfg(unix)]
+        {
+            let out = std::fs::read_to_string(temp.child("docs/_out/html/b/out/index.html").path())
+                .unwrap();
+            assert!(!out.contains("/edit/main/"));
+        }
+    }
+
+    #[test]
+    fn offline() {
+        let two = render_subpath("offline = true", "b/sub/two/index.html");
+        assert!(two.contains("var offline = true;"));
+        assert!(two.contains("href=\"../../../css/general.css\""));
+        assert!(two.contains("href=\"../../one/index.html\""));
+        assert!(two.contains("rel=\"prev\" href=\"../../one/index.html\""));
+        assert!(two.contains("<a href=\"../../index.html\">"));
+        assert!(!two.contains("href=\"/docs/v2/"));
+
+        let index = render_subpath("offline = true", "b/index.html");
+        assert!(index.contains("URL='one/index.html'"), "{}", index);
+
+        let online = render_subpath("", "b/sub/two/index.html");
+        assert!(online.contains("var offline = false;"));
+        assert!(online.contains("href=\"/docs/v2/css/general.css\""));
+    }
+
     // #[test]
     // fn render_readmes() {
     //     glob!("render_html_tests/*.md", |path| {
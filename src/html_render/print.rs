@@ -0,0 +1,197 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! A page per book with every chapter on it, for printing.
+//!
+//! As all the chapters share one page, heading ids are prefixed with the
+//! chapter they're in, and links between chapters become links to the
+//! chapter's anchor.
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use eyre::Result;
+use ramhorns::Content as Rhc;
+use serde::Serialize;
+use tracing::instrument;
+
+use crate::cli::config::Theme;
+use crate::content::{front_matter, links, Book, Dirs, Page};
+
+use super::headings::{dedupe, slugify};
+use super::tpl_types::{render_markdown_with, Global};
+
+#[derive(Debug, Clone, Serialize, PartialEq, Rhc)]
+pub(crate) struct PrintPage<'a> {
+    title: &'a str,
+    chapters: Vec<Chapter<'a>>,
+    #[ramhorns(flatten)]
+    global: Global<'a>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Rhc)]
+struct Chapter<'a> {
+    id: String,
+    title: &'a str,
+    inner_html: String,
+}
+
+impl<'a> PrintPage<'a> {
+    #[instrument]
+    pub(crate) fn new(book: &'a Book, dirs: &'a Dirs, theme: &'a Theme) -> Result<Self> {
+        // `cli/init.md` and `cli-init.md` would have the same id otherwise.
+        let mut seen = HashMap::new();
+        let ids: HashMap<&Path, String> = book
+            .pages
+            .iter()
+            .map(|p| (p.input.as_path(), dedupe(&mut seen, chapter_id(book, p))))
+            .collect();
+
+        let chapters = book
+            .pages
+            .iter()
+            .map(|page| {
+                let id = &ids[page.input.as_path()];
+                let (_, body) = front_matter::read(&page.input)?;
+                let inner_html = render_markdown_with(&body, Some(id), |dest| {
+                    let link = links::resolve(&page.input, dest)?;
+                    let target = ids.get(link.path.as_path())?;
+                    Some(match link.fragment {
+                        Some(frag) => format!("#{}--{}", target, frag),
+                        None => format!("#{}", target),
+                    })
+                });
+                Ok(Chapter {
+                    id: id.clone(),
+                    title: page.title(),
+                    inner_html,
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            title: &book.title,
+            chapters,
+            global: Global {
+                path_to_root: dirs.link(&output(book, dirs)?, &dirs.base_url)?,
+                offline: dirs.offline,
+                ..Global::new(theme)
+            },
+        })
+    }
+}
+
+/// The anchor for a chapter, from it's path in the book.
+fn chapter_id(book: &Book, page: &Page) -> String {
+    let rel = page
+        .input
+        .strip_prefix(&book.location)
+        .unwrap_or(&page.input)
+        .with_extension("");
+    let rel = rel
+        .to_string_lossy()
+        .replace(std::path::MAIN_SEPARATOR, "-");
+    slugify(&rel)
+}
+
+/// Where the print page for a book goes.
+pub(crate) fn output(book: &Book, dirs: &Dirs) -> Result<PathBuf> {
+    Ok(dirs
+        .out_dir
+        .join(book.location.strip_prefix(&dirs.base_dir)?)
+        .join("print.html"))
+}
+
+/// The url of the print page for a book.
+pub(crate) fn url(book: &Book, dirs: &Dirs) -> Result<String> {
+    dirs.href(&output(book, dirs)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn print_page() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        temp.child("b/intro.md")
+            .write_str("# Intro\n\nSee [usage](cli/init.md#usage) and [below](#more).\n\n## More\n")
+            .unwrap();
+        temp.child("b/cli/init.md")
+            .write_str("# Init\n\n## Usage\n\nBack to [the start](../intro.md).\n")
+            .unwrap();
+
+        let book = Book {
+            title: "B".into(),
+            location: temp.path().join("b"),
+            pages: vec![
+                Page {
+                    name: "Intro".into(),
+                    input: temp.path().join("b/intro.md"),
+                    ..Default::default()
+                },
+                Page {
+                    name: "Init".into(),
+                    input: temp.path().join("b/cli/init.md"),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let dirs = Dirs {
+            base_dir: temp.path().to_owned(),
+            out_dir: temp.path().join("_out/html"),
+            base_url: "/".into(),
+            ..Default::default()
+        };
+
+        let theme = Theme::default();
+        let page = PrintPage::new(&book, &dirs, &theme).unwrap();
+        let ids: Vec<_> = page.chapters.iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(ids, ["intro", "cli-init"]);
+        insta::assert_snapshot!(page
+            .chapters
+            .iter()
+            .map(|c| c.inner_html.as_str())
+            .collect::<String>());
+
+        assert_eq!(
+            output(&book, &dirs).unwrap(),
+            temp.path().join("_out/html/b/print.html")
+        );
+        assert_eq!(url(&book, &dirs).unwrap(), "/b/print.html");
+    }
+
+    #[test]
+    fn duplicate_ids() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        temp.child("b/cli/init.md").write_str("# Init\n").unwrap();
+        temp.child("b/cli-init.md")
+            .write_str("# Also init\n\nNot [that one](cli/init.md).\n")
+            .unwrap();
+
+        let book = Book {
+            title: "B".into(),
+            location: temp.path().join("b"),
+            pages: ["cli/init.md", "cli-init.md"]
+                .iter()
+                .map(|p| Page {
+                    input: temp.path().join("b").join(p),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let dirs = Dirs {
+            base_dir: temp.path().to_owned(),
+            out_dir: temp.path().join("_out/html"),
+            base_url: "/".into(),
+            ..Default::default()
+        };
+
+        let theme = Theme::default();
+        let page = PrintPage::new(&book, &dirs, &theme).unwrap();
+        let ids: Vec<_> = page.chapters.iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(ids, ["cli-init", "cli-init-1"]);
+        assert!(page.chapters[1].inner_html.contains("href=\"#cli-init\""));
+    }
+}
@@ -0,0 +1,192 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! The `sitemap.xml` and `robots.txt`, for search engines.
+//!
+//! Both need absolute urls, so they're only written if the `site-url` has a
+//! host. Pages with `noindex` are left out of the sitemap.
+use std::fmt::Write as _;
+use std::fs;
+
+use chrono::SecondsFormat;
+use eyre::{Result, WrapErr};
+use pulldown_cmark::escape::escape_html;
+use tracing::{debug, instrument, warn};
+
+use crate::cli::config::RobotsConf;
+use crate::content::{git, links, Content, Dirs};
+
+#[instrument(skip(content))]
+pub(crate) fn write(
+    content: &Content,
+    dirs: &Dirs,
+    robots: &RobotsConf,
+    commits: &git::Commits,
+) -> Result<()> {
+    let origin = match &dirs.origin {
+        Some(origin) => origin,
+        None => {
+            debug!("The site-url has no host, so there's no sitemap");
+            return Ok(());
+        }
+    };
+
+    let path = dirs.out_dir.join("sitemap.xml");
+    fs::write(&path, sitemap(content, dirs, origin, commits)?)
+        .wrap_err_with(|| format!("Failed to create {:?}", path))?;
+
+    if robots.enabled && dirs.base_url != "/" {
+        // Crawlers would never find it.
+        if robots.disallow.is_empty() {
+            debug!("The site-url isn't the root of the host, so there's no robots.txt");
+        } else {
+            warn!(
+                "The site-url isn't the root of the host, so the robots.txt \
+                 (with it's disallow list) isn't written"
+            );
+        }
+    } else if robots.enabled {
+        let path = dirs.out_dir.join("robots.txt");
+        fs::write(&path, robots_txt(robots, dirs, origin))
+            .wrap_err_with(|| format!("Failed to create {:?}", path))?;
+    }
+    Ok(())
+}
+
+fn sitemap(content: &Content, dirs: &Dirs, origin: &str, commits: &git::Commits) -> Result<String> {
+    let mut out = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+    let pages = content.0.iter().flat_map(|b| &b.pages);
+    for page in pages.filter(|p| !p.front_matter.noindex) {
+        out.push_str("  <url>\n    <loc>");
+        let url = format!("{}{}", origin, links::percent_encode(&page.url(dirs)?));
+        escape_html(&mut out, &url)?;
+        out.push_str("</loc>\n");
+        if let Some(modified) = git::last_modified(&page.input, commits) {
+            writeln!(
+                out,
+                "    <lastmod>{}</lastmod>",
+                modified.to_rfc3339_opts(SecondsFormat::Secs, true)
+            )?;
+        }
+        out.push_str("  </url>\n");
+    }
+    out.push_str("</urlset>\n");
+    Ok(out)
+}
+
+fn robots_txt(robots: &RobotsConf, dirs: &Dirs, origin: &str) -> String {
+    let mut out = String::from("User-agent: *\n");
+    if robots.disallow.is_empty() {
+        out.push_str("Allow: /\n");
+    }
+    for path in &robots.disallow {
+        out.push_str(&format!(
+            "Disallow: {}{}\n",
+            dirs.base_url,
+            path.trim_start_matches('/')
+        ));
+    }
+    out.push_str(&format!(
+        "\nSitemap: {}{}sitemap.xml\n",
+        origin, dirs.base_url
+    ));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use assert_fs::prelude::*;
+
+    use crate::cli::{self, config};
+    use crate::html_render::HTMLRender;
+
+    use super::*;
+
+    fn render(dir: &Path, toml: &str) {
+        let files = [
+            ("xmark.toml", toml),
+            (
+                "b/SUMMARY.md",
+                "# B\n\n- [One](one.md)\n- [Hidden](hidden.md)\n- [A & B](a&b.md)\n\
+                 - [Café](<café au lait.md>)\n",
+            ),
+            ("b/one.md", "# One\n"),
+            ("b/hidden.md", "+++\nnoindex = true\n+++\n# Hidden\n"),
+            ("b/a&b.md", "# A & B\n"),
+            ("b/café au lait.md", "# Café\n"),
+        ];
+        for (path, contents) in &files {
+            fs::create_dir_all(dir.join(path).parent().unwrap()).unwrap();
+            fs::write(dir.join(path), contents).unwrap();
+        }
+
+        let args = cli::Args {
+            dir: dir.to_owned(),
+            ..Default::default()
+        };
+        let conf = config::load(&args).unwrap();
+        HTMLRender::new(&conf, &args).unwrap().render().unwrap();
+    }
+
+    #[test]
+    fn sitemap_and_robots() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        render(
+            temp.path(),
+            "books = ['b']\n[html]\nsite-url = 'https://host'\n\
+             [html.robots]\ndisallow = ['/b/drafts/']\n",
+        );
+
+        let sitemap = fs::read_to_string(temp.child("_out/html/sitemap.xml").path()).unwrap();
+        let locs: Vec<_> = sitemap
+            .lines()
+            .filter_map(|l| l.trim().strip_prefix("<loc>"))
+            .collect();
+        assert_eq!(
+            locs,
+            [
+                "https://host/b/one</loc>",
+                "https://host/b/a&amp;b</loc>",
+                "https://host/b/caf%C3%A9%20au%20lait</loc>"
+            ]
+        );
+        assert_eq!(sitemap.matches("<lastmod>").count(), 3);
+
+        temp.child("_out/html/robots.txt").assert(
+            "User-agent: *\nDisallow: /b/drafts/\n\n\
+             Sitemap: https://host/sitemap.xml\n",
+        );
+    }
+
+    #[test]
+    fn subpath() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        render(
+            temp.path(),
+            "books = ['b']\n[html]\nsite-url = 'https://host/docs'\n",
+        );
+        let sitemap = fs::read_to_string(temp.child("_out/html/sitemap.xml").path()).unwrap();
+        assert!(sitemap.contains("<loc>https://host/docs/b/one</loc>"));
+        // Crawlers only look at the root of the host.
+        assert!(!temp.child("_out/html/robots.txt").path().exists());
+    }
+
+    #[test]
+    fn needs_a_host() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        render(temp.path(), "books = ['b']\n[html]\nsite-url = '/docs'\n");
+        assert!(temp.child("_out/html/b/one/index.html").path().exists());
+        assert!(!temp.child("_out/html/sitemap.xml").path().exists());
+        assert!(!temp.child("_out/html/robots.txt").path().exists());
+
+        render(
+            temp.path(),
+            "books = ['b']\n[html]\nsite-url = 'https://host'\n[html.robots]\nenabled = false\n",
+        );
+        assert!(temp.child("_out/html/sitemap.xml").path().exists());
+        assert!(!temp.child("_out/html/robots.txt").path().exists());
+    }
+}
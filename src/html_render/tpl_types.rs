@@ -1,62 +1,145 @@
 use std::cmp::Ordering;
-use std::{fmt, fs};
+use std::fmt;
+use std::fs;
 
-use eyre::Result;
-use pulldown_cmark::{html, Options, Parser};
+use eyre::{Result, WrapErr};
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag};
 use ramhorns::Content as Rhc;
 use serde::Serialize;
-use tracing::instrument;
+use tracing::{instrument, warn};
 
-use crate::html_render::HTMLRender;
-
-use crate::content::{Book, Link, Page as CPage};
+use crate::cli::config::Theme;
+use crate::html_render::headings::HeadingIds;
+use crate::html_render::{admonition, print, xhtml, HTMLRender};
 
+use crate::content::{excerpt, front_matter, links, Book, Link, Page as CPage};
 
 // Because we borrow link, we cant Deserialize, so snapshot tests may not work.
 // If so, we can remove the Serialize bound
 #[derive(Debug, Clone, Serialize, PartialEq, Rhc)]
 pub(crate) struct Page<'a> {
     title: &'a str,
+    book_title: &'a str,
     inner_html: String,
-    pub(crate) heirachy: &'a [Link],
+    /// From the front matter, or the first paragraph.
+    pub(crate) description: Option<String>,
+    /// The full url of the page, if the `site-url` has a host.
+    pub(crate) canonical: Option<String>,
+    /// The feed this page is in, for autodiscovery.
+    pub(crate) feed: Option<FeedLink<'a>>,
+    pub(crate) edit_url: Option<String>,
+    /// The day the chapter was last committed.
+    pub(crate) last_updated: Option<String>,
+    pub(crate) noindex: bool,
+    pub(crate) heirachy: Vec<Link>,
     pub(crate) pagetoc: String,
     /// The link to the next page
-    pub(crate) next: Option<&'a str>,
+    pub(crate) next: Option<String>,
     /// The link to the previous
-    pub(crate) prev: Option<&'a str>,
+    pub(crate) prev: Option<String>,
     // This is unique to each chap, as the current page is highlighted.
     pub(crate) toc: String,
+    pub(crate) print_url: String,
     #[ramhorns(flatten)]
     pub(crate) global: Global<'a>,
 }
 
 impl<'a> Page<'a> {
     #[instrument]
-    pub(crate) fn new(from: &'a CPage, rd: &'a HTMLRender<'a>, book: &Book) -> Result<Self> {
+    pub(crate) fn new(from: &'a CPage, rd: &'a HTMLRender<'a>, book: &'a Book) -> Result<Self> {
         // TODO: Don't buffer the whole input
-        let inner_html = render_markdown(&fs::read_to_string(&from.input)?);
+        let (_, body) = front_matter::read(&from.input)?;
+        let inner_html = render_markdown(&body);
 
+        let dirs = &rd.dirs;
+        let link = |url: &str| dirs.link(&from.output, url);
         let global = Global {
-            path_to_root: &rd.dirs.base_url,
-            ..Default::default()
+            path_to_root: link(&dirs.base_url)?,
+            offline: dirs.offline,
+            git_repository_url: rd.html.git_repository_url.as_deref(),
+            git_repository_icon: rd
+                .html
+                .git_repository_icon
+                .as_deref()
+                .unwrap_or("fa-github"),
+            ..Global::new(rd.html.theme(&book.location))
         };
 
         let mut toc = String::new();
         Self::write_toc(&mut toc, book, rd, from).unwrap();
 
         Ok(Self {
-            title: &from.name,
+            title: from.title(),
+            book_title: &book.title,
+            description: from
+                .front_matter
+                .description
+                .clone()
+                .or_else(|| excerpt::excerpt(&body)),
+            canonical: match &dirs.origin {
+                Some(origin) => Some(format!(
+                    "{}{}",
+                    origin,
+                    links::percent_encode(&from.url(dirs)?)
+                )),
+                None => None,
+            },
+            feed: rd
+                .feeds
+                .iter()
+                .find(|f| f.has_book(&book.location))
+                .map(|f| -> Result<_> {
+                    Ok(FeedLink {
+                        title: &f.title,
+                        href: link(&dirs.href(&f.output)?)?,
+                    })
+                })
+                .transpose()?,
+            edit_url: Self::edit_url(from, rd)?,
+            last_updated: rd
+                .commits
+                .get(&from.input)
+                .map(|d| d.format("%Y-%m-%d").to_string()),
             inner_html,
+            noindex: from.front_matter.noindex,
             // TODO: The way to do this is to gen the pagetoc in render_markdown.
             pagetoc: String::new(),
-            next: from.next.as_deref(),
-            prev: from.prev.as_deref(),
-            heirachy: &from.heirachy,
+            next: from.next.as_deref().map(link).transpose()?,
+            prev: from.prev.as_deref().map(link).transpose()?,
+            heirachy: from
+                .heirachy
+                .iter()
+                .map(|l| {
+                    Ok(Link {
+                        prity: l.prity.clone(),
+                        link: link(&l.link)?,
+                    })
+                })
+                .collect::<Result<_>>()?,
             global,
             toc,
+            print_url: link(&print::url(book, dirs)?)?,
         })
     }
 
+    /// Where to edit the chapter, from the `edit-url-template`.
+    fn edit_url(from: &CPage, rd: &HTMLRender<'_>) -> Result<Option<String>> {
+        let (template, root) = match (&rd.html.edit_url_template, &rd.repo_root) {
+            (Some(template), Some(root)) => (template, root),
+            _ => return Ok(None),
+        };
+        let input = fs::canonicalize(&from.input)
+            .wrap_err_with(|| format!("Couldn't open {:?}", from.input))?;
+        let path = match input.strip_prefix(root) {
+            Ok(path) => links::percent_encode(&links::href(path)),
+            Err(_) => {
+                warn!("{:?} isn't in {:?}, so it has no edit link", input, root);
+                return Ok(None);
+            }
+        };
+        Ok(Some(template.replace("{path}", &path)))
+    }
+
     // TODO: This doesn't have draft pages, which have been eliminated earlyer.
     // This requires rearchetecting the whole thing.
     // https://github.com/rust-lang/mdBook/blob/e5f74b6c8674bf23ed9c8d9b702fc9be7d409f1d/src/renderer/html_handlebars/helpers/toc.rs#L38-L146
@@ -89,14 +172,11 @@ impl<'a> Page<'a> {
             }
             out.push_str("<li class=\"chapter-item expanded\">");
 
-            let mut href = rd.dirs.base_url.clone();
-            href.push_str(
-                i.output
-                    .strip_prefix(&rd.dirs.out_dir)
-                    .unwrap()
-                    .to_str()
-                    .unwrap(),
-            );
+            let href = rd
+                .dirs
+                .href(&i.output)
+                .and_then(|href| rd.dirs.link(&this.output, &href))
+                .map_err(|_| fmt::Error)?;
             out.push_str("<a href=\"");
             out.push_str(&href);
             out.push('"');
@@ -120,23 +200,35 @@ impl<'a> Page<'a> {
     }
 }
 
+#[derive(Debug, Clone, Serialize, PartialEq, Rhc)]
+pub(crate) struct FeedLink<'a> {
+    title: &'a str,
+    href: String,
+}
+
 /// Options every page needs not specific to a page
 #[derive(Debug, Clone, Serialize, PartialEq, Rhc)]
 pub(crate) struct Global<'a> {
-    pub(crate) path_to_root: &'a str,
+    pub(crate) path_to_root: String,
+    /// So the javascript doesn't try to `fetch` over `file://`.
+    pub(crate) offline: bool,
+    pub(crate) git_repository_url: Option<&'a str>,
+    pub(crate) git_repository_icon: &'a str,
     pub(crate) language: &'a str,
     pub(crate) preferred_dark_theme: &'a str,
     pub(crate) default_theme: &'a str,
 }
 
-impl<'a> Default for Global<'a> {
-    fn default() -> Self {
+impl<'a> Global<'a> {
+    pub(crate) fn new(theme: &'a Theme) -> Self {
         Global {
-            path_to_root: "/",
-            language: "en",
-            // THESE ARE FACTS.
-            default_theme: "rust",
-            preferred_dark_theme: "coal",
+            path_to_root: "/".to_owned(),
+            offline: false,
+            git_repository_url: None,
+            git_repository_icon: "fa-github",
+            language: &theme.language,
+            default_theme: &theme.default_theme,
+            preferred_dark_theme: &theme.preferred_dark_theme,
         }
     }
 }
@@ -144,9 +236,80 @@ impl<'a> Default for Global<'a> {
 // TODO: A million customizations
 #[instrument]
 pub(crate) fn render_markdown(content: &str) -> String {
+    render_markdown_with(content, None, |_| None)
+}
+
+/// Render markdown, with a prefix for the heading ids, and a function that
+/// can replace link (and image) destinations.
+pub(crate) fn render_markdown_with(
+    content: &str,
+    id_prefix: Option<&str>,
+    mut rewrite_link: impl FnMut(&str) -> Option<String>,
+) -> String {
+    render(
+        content,
+        id_prefix,
+        |dest, _| rewrite_link(dest).map_or(LinkDest::Keep, LinkDest::To),
+        false,
+    )
+}
+
+/// What to do with a link (or image) destination.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum LinkDest {
+    Keep,
+    To(String),
+    /// Drop the link, but keep it's text.
+    Unlink,
+}
+
+/// Like [`render_markdown_with`], but the output is XHTML, for the EPUB. The
+/// function is also told if the destination is an image.
+pub(crate) fn render_xhtml(
+    content: &str,
+    rewrite_link: impl FnMut(&str, bool) -> LinkDest,
+) -> String {
+    render(content, None, rewrite_link, true)
+}
+
+fn render(
+    content: &str,
+    id_prefix: Option<&str>,
+    mut rewrite_link: impl FnMut(&str, bool) -> LinkDest,
+    xhtml: bool,
+) -> String {
     let opts = Options::all();
-    let parser = Parser::new_ext(content, opts);
+    let content = admonition::expand_containers(content);
+    // Whether each link we're in was dropped, so we know to drop it's end too.
+    let mut unlinked = Vec::new();
+    let parser = Parser::new_ext(&content, opts).filter_map(|ev| match ev {
+        Event::Start(Tag::Link(ty, dest, title)) => {
+            let dest = new_dest(rewrite_link(&dest, false), dest, &mut unlinked)?;
+            Some(Event::Start(Tag::Link(ty, dest, title)))
+        }
+        // For images, the alt text is left as plain text.
+        Event::Start(Tag::Image(ty, dest, title)) => {
+            let dest = new_dest(rewrite_link(&dest, true), dest, &mut unlinked)?;
+            Some(Event::Start(Tag::Image(ty, dest, title)))
+        }
+        Event::End(Tag::Link(..)) | Event::End(Tag::Image(..)) if unlinked.pop() == Some(true) => {
+            None
+        }
+        Event::Html(html) if xhtml => Some(Event::Html(xhtml::fix(&html).into())),
+        ev => Some(ev),
+    });
+    let parser = HeadingIds::new(admonition::Admonitions::new(parser), id_prefix);
     let mut out = String::new();
     html::push_html(&mut out, parser);
     out
 }
+
+/// The destination to use, or `None` if the link should go.
+fn new_dest<'a>(new: LinkDest, old: CowStr<'a>, unlinked: &mut Vec<bool>) -> Option<CowStr<'a>> {
+    unlinked.push(new == LinkDest::Unlink);
+    match new {
+        LinkDest::Keep => Some(old),
+        LinkDest::To(to) => Some(to.into()),
+        LinkDest::Unlink => None,
+    }
+}
@@ -0,0 +1,151 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! Make raw html in markdown into something that's (more likely) valid XHTML.
+//!
+//! Browsers are happy with `<br>`, `&nbsp;` or `Vec<T>` in the middle of a
+//! paragraph, but XML parsers (and so e-readers) aren't. This closes void
+//! elements, escapes anything that looks like a tag but isn't an html element,
+//! and turns named entities into numeric ones.
+
+#[rustfmt::skip]
+const ELEMENTS: &[&str] = &[
+    "a", "abbr", "address", "area", "article", "aside", "audio", "b", "bdi", "bdo", "blockquote",
+    "br", "button", "caption", "cite", "code", "col", "colgroup", "dd", "del", "details", "dfn",
+    "div", "dl", "dt", "em", "embed", "figcaption", "figure", "footer", "h1", "h2", "h3", "h4",
+    "h5", "h6", "header", "hr", "i", "iframe", "img", "input", "ins", "kbd", "label", "li", "main",
+    "mark", "nav", "ol", "p", "picture", "pre", "q", "s", "samp", "section", "small", "source",
+    "span", "strong", "sub", "summary", "sup", "svg", "table", "tbody", "td", "tfoot", "th",
+    "thead", "time", "tr", "track", "u", "ul", "var", "video", "wbr",
+];
+
+const VOID: &[&str] = &[
+    "area", "br", "col", "embed", "hr", "img", "input", "source", "track", "wbr",
+];
+
+/// The named entities XML knows about, and the common html ones it doesn't.
+const ENTITIES: &[(&str, &str)] = &[
+    ("amp", "&amp;"),
+    ("lt", "&lt;"),
+    ("gt", "&gt;"),
+    ("quot", "&quot;"),
+    ("apos", "&apos;"),
+    ("nbsp", "&#160;"),
+    ("copy", "&#169;"),
+    ("reg", "&#174;"),
+    ("trade", "&#8482;"),
+    ("ndash", "&#8211;"),
+    ("mdash", "&#8212;"),
+    ("hellip", "&#8230;"),
+    ("lsquo", "&#8216;"),
+    ("rsquo", "&#8217;"),
+    ("ldquo", "&#8220;"),
+    ("rdquo", "&#8221;"),
+    ("larr", "&#8592;"),
+    ("rarr", "&#8594;"),
+    ("times", "&#215;"),
+];
+
+pub(crate) fn fix(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while let Some(i) = rest.find(['<', '&']) {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+
+        if rest.starts_with('&') {
+            let (replacement, len) = entity(rest);
+            out.push_str(&replacement);
+            rest = &rest[len..];
+        } else if rest.starts_with("<!--") {
+            let len = rest.find("-->").map_or(rest.len(), |end| end + 3);
+            out.push_str(&rest[..len]);
+            rest = &rest[len..];
+        } else if let Some(len) = tag(rest) {
+            let tag = &rest[..len];
+            if is_void(tag) && !tag.ends_with("/>") {
+                out.push_str(tag[..len - 1].trim_end());
+                out.push_str(" />");
+            } else {
+                out.push_str(tag);
+            }
+            rest = &rest[len..];
+        } else {
+            out.push_str("&lt;");
+            rest = &rest[1..];
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// The length of the html element tag at the start of `s`, if there is one.
+fn tag(s: &str) -> Option<usize> {
+    let name = tag_name(s)?;
+    if !ELEMENTS.contains(&name.to_ascii_lowercase().as_str()) {
+        return None;
+    }
+    // Attributes can have `>` in quotes.
+    let mut quote = None;
+    for (i, c) in s.char_indices() {
+        match (quote, c) {
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (None, '>') => return Some(i + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn tag_name(s: &str) -> Option<&str> {
+    let s = s.strip_prefix('<')?;
+    let s = s.strip_prefix('/').unwrap_or(s);
+    let end = s
+        .find(|c: char| !c.is_ascii_alphanumeric())
+        .unwrap_or(s.len());
+    Some(&s[..end]).filter(|name| !name.is_empty())
+}
+
+fn is_void(tag: &str) -> bool {
+    !tag.starts_with("</")
+        && tag_name(tag).is_some_and(|name| VOID.contains(&name.to_ascii_lowercase().as_str()))
+}
+
+/// What to replace the `&` at the start of `s` with, and how much of `s` that
+/// covers.
+fn entity(s: &str) -> (String, usize) {
+    let end = s[1..]
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '#'))
+        .map(|i| i + 1);
+    if let Some(end) = end.filter(|&end| end > 1 && s[end..].starts_with(';')) {
+        let name = &s[1..end];
+        if name.starts_with('#') {
+            return (s[..=end].to_owned(), end + 1);
+        }
+        if let Some((_, replacement)) = ENTITIES.iter().find(|(n, _)| *n == name) {
+            return ((*replacement).to_owned(), end + 1);
+        }
+    }
+    ("&amp;".to_owned(), 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixes() {
+        assert_eq!(fix("<br>"), "<br />");
+        assert_eq!(fix("<img src=\"a>b.png\">"), "<img src=\"a>b.png\" />");
+        assert_eq!(fix("<hr/>"), "<hr/>");
+        assert_eq!(fix("Box<T> and Vec<u8>"), "Box&lt;T> and Vec&lt;u8>");
+        assert_eq!(
+            fix("<span class=\"x\">&nbsp;&amp;&#39;</span>"),
+            "<span class=\"x\">&#160;&amp;&#39;</span>"
+        );
+        assert_eq!(fix("a & b &bogus;"), "a &amp; b &amp;bogus;");
+        assert_eq!(fix("<!-- <br> -->"), "<!-- <br> -->");
+        assert_eq!(fix("< 3"), "&lt; 3");
+    }
+}
@@ -1,7 +1,13 @@
 // SPDX-License-Identifier: GPL-3.0-only
+pub(crate) mod check;
 pub(crate) mod cli;
 pub(crate) mod content;
+pub(crate) mod dump;
+pub(crate) mod epub_render;
 pub(crate) mod html_render;
+pub(crate) mod man_render;
+pub(crate) mod markdown_render;
+pub(crate) mod pdf_render;
 pub(crate) mod render;
 
 #[cfg(test)]
@@ -9,14 +15,53 @@ mod test_utils;
 
 use eyre::{Result, WrapErr};
 
+use cli::config::GlobalConf;
+
 fn main() -> Result<()> {
     cli::init()?;
 
     let args = cli::Args::parse()?;
-    let conf = cli::config::load(&args).context("Failed to load config")?;
-    let render = html_render::HTMLRender::new(&conf, &args)?;
+    let conf = || cli::config::load(&args).context("Failed to load config");
+
+    match &args.command {
+        cli::Command::Build => build(&conf()?, &args),
+        cli::Command::Dump(opts) => {
+            let stdout = std::io::stdout();
+            dump::dump(&conf()?, &args, opts.format, stdout.lock())
+        }
+        cli::Command::Init(opts) => {
+            let (stdin, stdout) = (std::io::stdin(), std::io::stdout());
+            cli::scaffold::init(&args.dir, opts, stdin.lock(), stdout.lock())
+        }
+        cli::Command::GenSummary(opts) => cli::gen_summary::gen_summary(&args.dir, opts),
+        cli::Command::Check => check::run(&args),
+        cli::Command::Summary(opts) => cli::summary_edit::edit(&args.dir, opts),
+        cli::Command::Mv(opts) => cli::move_chapter::mv(&args, opts),
+    }
+}
+
+fn build(conf: &GlobalConf, args: &cli::Args) -> Result<()> {
+    content::orphans::report(conf)?;
+
+    let render = html_render::HTMLRender::new(conf, args)?;
 
     render.render()?;
 
+    if let Some(pdf) = &conf.pdf {
+        pdf_render::PdfRender::new(conf, args, pdf)?.render()?;
+    }
+
+    if let Some(epub) = &conf.epub {
+        epub_render::EpubRender::new(conf, args, epub)?.render()?;
+    }
+
+    if let Some(markdown) = &conf.markdown {
+        markdown_render::MarkdownRender::new(conf, args, markdown)?.render()?;
+    }
+
+    if let Some(man) = &conf.man {
+        man_render::ManRender::new(conf, args, man)?.render()?;
+    }
+
     Ok(())
 }
@@ -0,0 +1,175 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! Man pages, made from the chapters that document a command or file.
+//!
+//! Which chapters, and what section they go in, is set per book in the
+//! `[man.<book>]` tables. Each page ends up in `man<section>/<name>.<section>`,
+//! so the output directory can be used as a `MANPATH`.
+use std::fmt::Debug;
+use std::fs;
+use std::path::PathBuf;
+
+use eyre::{bail, Result, WrapErr};
+use pulldown_cmark::{Options, Parser};
+use tracing::instrument;
+
+use crate::cli;
+use crate::cli::config::{GlobalConf, ManConf, ManPage};
+use crate::content::{self, front_matter, Book, Content, Page};
+use crate::html_render::expand_containers;
+
+mod roff;
+
+pub(crate) struct ManRender<'a> {
+    content: Content,
+    conf: &'a ManConf,
+    out_dir: PathBuf,
+}
+
+impl Debug for ManRender<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ManRender")
+            .field("out_dir", &self.out_dir)
+            .field("conf", &self.conf)
+            .finish()
+    }
+}
+
+impl<'a> ManRender<'a> {
+    #[instrument]
+    pub(crate) fn new(conf: &GlobalConf, args: &cli::Args, man: &'a ManConf) -> Result<Self> {
+        let dirs = content::Dirs::new(conf, args);
+        let content = Content::new(conf, &dirs)?;
+        Ok(Self {
+            content,
+            conf: man,
+            out_dir: conf.build.out_dir.join("man"),
+        })
+    }
+
+    #[instrument]
+    pub(crate) fn render(&self) -> Result<()> {
+        let pages = || self.content.0.iter().flat_map(|book| &book.pages);
+        for path in self.conf.pages.keys() {
+            if !pages().any(|page| &page.input == path) {
+                bail!("Man page {:?} isn't a chapter", path);
+            }
+        }
+
+        if self.out_dir.exists() {
+            fs::remove_dir_all(&self.out_dir)?;
+        }
+        fs::create_dir_all(&self.out_dir)?;
+
+        let date = chrono::Utc::now().format("%Y-%m-%d").to_string();
+        for book in &self.content.0 {
+            for page in &book.pages {
+                if let Some(man) = self.conf.pages.get(&page.input) {
+                    self.page(book, page, man, &date)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[instrument]
+    fn page(&self, book: &Book, page: &Page, man: &ManPage, date: &str) -> Result<()> {
+        let name = match &man.name {
+            Some(name) => name.clone(),
+            None => page
+                .input
+                .file_stem()
+                .map_or_else(|| page.name.clone(), |x| x.to_string_lossy().into_owned()),
+        };
+        let header = roff::Header {
+            name: &name,
+            section: man.section,
+            date,
+            source: &book.title,
+            description: page
+                .front_matter
+                .description
+                .as_deref()
+                .unwrap_or_else(|| page.title()),
+        };
+
+        let (_, body) = front_matter::read(&page.input)?;
+        let body = expand_containers(&body);
+        let roff = roff::write(&header, Parser::new_ext(&body, Options::all()));
+
+        let dir = self.out_dir.join(format!("man{}", man.section));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(format!("{}.{}", name, man.section));
+        fs::write(&path, roff).wrap_err_with(|| format!("Failed to create {:?}", path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn pages() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        temp.child("cli/build.md")
+            .write_str("# Build\n\nBuilds things.\n")
+            .unwrap();
+        temp.child("cli/config.md")
+            .write_str("+++\ndescription = \"xmark's config\"\n+++\n# Config\n")
+            .unwrap();
+        temp.child("cli/other.md").write_str("# Other\n").unwrap();
+
+        let page = |path: &str, name: &str| Page {
+            name: name.into(),
+            input: temp.path().join(path),
+            front_matter: front_matter::read(&temp.path().join(path)).unwrap().0,
+            ..Default::default()
+        };
+        let content = Content(vec![Book {
+            title: "CLI".into(),
+            location: temp.path().join("cli"),
+            pages: vec![
+                page("cli/build.md", "Build"),
+                page("cli/config.md", "Config"),
+                page("cli/other.md", "Other"),
+            ],
+            ..Default::default()
+        }]);
+        let mut conf = ManConf::default();
+        conf.pages
+            .insert(temp.path().join("cli/build.md"), ManPage::default());
+        conf.pages.insert(
+            temp.path().join("cli/config.md"),
+            ManPage {
+                section: 5,
+                name: Some("xmark.toml".into()),
+            },
+        );
+
+        let render = ManRender {
+            content,
+            conf: &conf,
+            out_dir: temp.path().join("_out/man"),
+        };
+        render.render().unwrap();
+
+        let out = temp.path().join("_out/man");
+        let build = fs::read_to_string(out.join("man1/build.1")).unwrap();
+        assert!(build.contains(".SH NAME\nbuild \\- Build\n.PP\nBuilds things.\n"));
+        let config = fs::read_to_string(out.join("man5/xmark.toml.5")).unwrap();
+        assert!(config.contains(".SH NAME\nxmark.toml \\- xmark's config\n"));
+        assert_eq!(fs::read_dir(&out).unwrap().count(), 2);
+
+        let mut missing = conf.clone();
+        missing
+            .pages
+            .insert(temp.path().join("cli/missing.md"), ManPage::default());
+        let render = ManRender {
+            conf: &missing,
+            ..render
+        };
+        assert!(render.render().is_err());
+    }
+}
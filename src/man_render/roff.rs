@@ -0,0 +1,320 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! Turn markdown events into roff, using the `man` macros.
+//!
+//! Headings become `.SH`/`.SS`, lists `.IP`, code blocks are no-fill and
+//! indented, and tables are read as definition lists: the first column is the
+//! `.TP` tag, and the rest is what it means. Raw html is dropped.
+use pulldown_cmark::{Event, Tag};
+
+/// What goes in the `.TH` and `NAME` section.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Header<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) section: u8,
+    pub(crate) date: &'a str,
+    /// What the page is part of, ie the books title.
+    pub(crate) source: &'a str,
+    pub(crate) description: &'a str,
+}
+
+pub(crate) fn write<'a>(
+    header: &Header<'_>,
+    events: impl IntoIterator<Item = Event<'a>>,
+) -> String {
+    let mut writer = Writer::default();
+    writer.request(&format!(
+        "TH {} {} {} {}",
+        quote(&header.name.to_uppercase()),
+        header.section,
+        quote(header.date),
+        quote(header.source)
+    ));
+    writer.request("SH NAME");
+    writer.text(&format!("{} - {}", header.name, header.description));
+
+    // The chapters title is what the NAME section is for.
+    let mut events = events.into_iter().peekable();
+    if let Some(Event::Start(Tag::Heading(1))) = events.peek() {
+        events
+            .by_ref()
+            .find(|ev| matches!(ev, Event::End(Tag::Heading(1))));
+    }
+
+    for ev in events {
+        writer.event(ev);
+    }
+    writer.line();
+    writer.out
+}
+
+#[derive(Default)]
+struct Writer {
+    out: String,
+    /// Fonts we're inside, innermost last.
+    fonts: Vec<&'static str>,
+    /// The next number for each list we're in, `None` for bullet lists.
+    lists: Vec<Option<u64>>,
+    /// We've just started a list item, so the next block goes with it's bullet.
+    item_start: bool,
+    code: Option<String>,
+    /// Rows of the table we're in.
+    table: Option<Vec<Vec<String>>>,
+    /// The output from outside the table cell we're writing.
+    outside_cell: Option<String>,
+    /// The destination of the link we're in, and where it's text starts.
+    link: Option<(String, usize)>,
+}
+
+impl Writer {
+    fn at_line_start(&self) -> bool {
+        self.out.is_empty() || self.out.ends_with('\n')
+    }
+
+    fn line(&mut self) {
+        if !self.at_line_start() {
+            self.out.push('\n');
+        }
+    }
+
+    fn request(&mut self, request: &str) {
+        self.line();
+        self.out.push('.');
+        self.out.push_str(request);
+        self.out.push('\n');
+    }
+
+    fn text(&mut self, text: &str) {
+        let mut text = escape(text);
+        if self.at_line_start() {
+            // Leading space breaks the line, and a leading `.` or `'` is a request.
+            text = text.trim_start().to_owned();
+            if text.starts_with('.') || text.starts_with('\'') {
+                self.out.push_str("\\&");
+            }
+        }
+        self.out.push_str(&text);
+    }
+
+    fn push_font(&mut self, font: &'static str) {
+        self.fonts.push(font);
+        self.out.push_str("\\f");
+        self.out.push_str(font);
+    }
+
+    fn pop_font(&mut self) {
+        self.fonts.pop();
+        self.out.push_str("\\f");
+        self.out.push_str(self.fonts.last().copied().unwrap_or("R"));
+    }
+
+    fn indent(&self) -> u32 {
+        match self.lists.last() {
+            Some(Some(_)) => 4,
+            _ => 2,
+        }
+    }
+
+    /// Start a new paragraph-like thing.
+    fn block(&mut self) {
+        if self.item_start {
+            self.item_start = false;
+        } else if self.lists.is_empty() {
+            self.request("PP");
+        } else {
+            self.request(&format!("IP \"\" {}", self.indent()));
+        }
+    }
+
+    fn event(&mut self, ev: Event<'_>) {
+        if let Some(code) = &mut self.code {
+            match ev {
+                Event::Text(text) => code.push_str(&text),
+                Event::End(Tag::CodeBlock(_)) => {
+                    let code = self.code.take().unwrap();
+                    self.request("RS 4");
+                    self.request("nf");
+                    for line in code.lines() {
+                        let line = escape(line);
+                        if line.starts_with('.') || line.starts_with('\'') {
+                            self.out.push_str("\\&");
+                        }
+                        self.out.push_str(&line);
+                        self.out.push('\n');
+                    }
+                    self.request("fi");
+                    self.request("RE");
+                }
+                _ => {}
+            }
+            return;
+        }
+
+        match ev {
+            Event::Start(Tag::Paragraph) => self.block(),
+            Event::End(Tag::Paragraph) => self.line(),
+            Event::Start(Tag::Heading(level)) => {
+                self.item_start = false;
+                self.request(if level <= 2 { "SH" } else { "SS" });
+            }
+            Event::End(Tag::Heading(_)) => self.line(),
+            Event::Start(Tag::BlockQuote) => {
+                self.block();
+                self.request("RS 4");
+            }
+            Event::End(Tag::BlockQuote) => self.request("RE"),
+            Event::Start(Tag::CodeBlock(_)) => {
+                self.block();
+                self.code = Some(String::new());
+            }
+            Event::Start(Tag::List(start)) => {
+                if !self.lists.is_empty() {
+                    self.request(&format!("RS {}", self.indent()));
+                }
+                self.lists.push(start);
+                self.item_start = false;
+            }
+            Event::End(Tag::List(_)) => {
+                self.lists.pop();
+                if !self.lists.is_empty() {
+                    self.request("RE");
+                }
+            }
+            Event::Start(Tag::Item) => {
+                let bullet = match self.lists.last_mut() {
+                    Some(Some(n)) => {
+                        *n += 1;
+                        format!("{}.", *n - 1)
+                    }
+                    _ => "\\(bu".to_owned(),
+                };
+                self.request(&format!("IP {} {}", bullet, self.indent()));
+                self.item_start = true;
+            }
+            Event::End(Tag::Item) => {
+                self.item_start = false;
+                self.line();
+            }
+            Event::Start(Tag::Table(_)) => self.table = Some(Vec::new()),
+            Event::End(Tag::Table(_)) => {
+                let rows = self.table.take().unwrap_or_default();
+                // The header row just says what the columns are.
+                for row in rows.into_iter().skip(1) {
+                    let mut cells = row.into_iter();
+                    let tag = cells.next().unwrap_or_default();
+                    self.request("TP");
+                    self.out.push_str(if tag.is_empty() { "\\&" } else { &tag });
+                    for (i, cell) in cells.enumerate() {
+                        if i > 0 {
+                            self.request("br");
+                        }
+                        self.line();
+                        self.out.push_str(&cell);
+                    }
+                }
+            }
+            Event::Start(Tag::TableHead) | Event::Start(Tag::TableRow) => {
+                if let Some(table) = &mut self.table {
+                    table.push(Vec::new());
+                }
+            }
+            Event::Start(Tag::TableCell) => {
+                self.outside_cell = Some(std::mem::take(&mut self.out));
+            }
+            Event::End(Tag::TableCell) => {
+                let outside = self.outside_cell.take().unwrap_or_default();
+                let cell = std::mem::replace(&mut self.out, outside);
+                if let Some(row) = self.table.as_mut().and_then(|t| t.last_mut()) {
+                    row.push(cell.trim().to_owned());
+                }
+            }
+            Event::Start(Tag::Emphasis) => self.push_font("I"),
+            Event::Start(Tag::Strong) => self.push_font("B"),
+            Event::End(Tag::Emphasis) | Event::End(Tag::Strong) => self.pop_font(),
+            Event::Start(Tag::Link(_, dest, _)) => {
+                self.link = Some((dest.into_string(), self.out.len()));
+            }
+            Event::End(Tag::Link(..)) => {
+                // Links to other chapters mean nothing here, but urls do.
+                if let Some((dest, start)) = self.link.take() {
+                    let is_url = dest.contains("://") || dest.starts_with("mailto:");
+                    if is_url && self.out.get(start..) != Some(&escape(&dest)) {
+                        self.text(&format!(" <{}>", dest));
+                    }
+                }
+            }
+            Event::Start(Tag::FootnoteDefinition(name)) => {
+                self.block();
+                self.text(&format!("[{}] ", name));
+            }
+            Event::Text(text) => self.text(&text),
+            Event::Code(code) => {
+                self.push_font("B");
+                self.text(&code);
+                self.pop_font();
+            }
+            Event::FootnoteReference(name) => self.text(&format!("[{}]", name)),
+            Event::TaskListMarker(done) => self.text(if done { "[x] " } else { "[ ] " }),
+            Event::SoftBreak => self.line(),
+            Event::HardBreak => self.request("br"),
+            Event::Rule => self.request("PP"),
+            // Html means nothing to a terminal, and the rest (images,
+            // strikethrough) just has it's text.
+            _ => {}
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => out.push_str("\\e"),
+            '-' => out.push_str("\\-"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// An argument to a request.
+fn quote(arg: &str) -> String {
+    format!("\"{}\"", escape(arg).replace('"', "\\(dq"))
+}
+
+#[cfg(test)]
+mod tests {
+    use pulldown_cmark::{Options, Parser};
+
+    use super::*;
+
+    fn roff(md: &str) -> String {
+        let header = Header {
+            name: "xmark-build",
+            section: 1,
+            date: "2021-06-01",
+            source: "The xmark Book",
+            description: "Build a site",
+        };
+        write(&header, Parser::new_ext(md, Options::all()))
+    }
+
+    #[test]
+    fn page() {
+        insta::assert_snapshot!(roff(
+            "# Build\n\n## Synopsis\n\n`xmark build [--dir <DIR>]`\n\n## Options\n\n\
+             | Flag | Meaning |\n|---|---|\n| `--dir` | Where the *xmark.toml* is |\n\
+             | `-v`, `--verbose` | Say more |\n\n## Notes\n\n\
+             1. First\n2. Second, with **bold** and *ital **both***\n   - nested\n\n\
+             ```sh\n.hidden\n$ xmark \\\n  --dir docs\n```\n\n\
+             > Quoted\n> text\n\nSee [the site](https://example.com) or \
+             [https://example.com](https://example.com) or [chapter](other.md).\n\n\
+             .dot at the start\\\nafter a break\n\n<div>html</div>\n"
+        ));
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(escape("a\\b-c"), "a\\eb\\-c");
+        assert_eq!(quote("say \"hi\""), "\"say \\(dqhi\\(dq\"");
+    }
+}
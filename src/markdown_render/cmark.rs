@@ -0,0 +1,619 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! Turn markdown events back into (CommonMark) markdown.
+//!
+//! The output is normalized, not a copy of the input: emphasis is always `*`,
+//! code blocks are always fenced, and reference links become inline links.
+//! The aim is that it renders to the same html as whatever it came from.
+use std::vec::IntoIter;
+
+use pulldown_cmark::{Alignment, CodeBlockKind, CowStr, Event, LinkType, Options, Tag};
+
+/// What to parse markdown with before writing it back out. Smart punctuation
+/// would change the text, so it's off.
+pub(crate) fn options() -> Options {
+    let mut opts = Options::all();
+    opts.remove(Options::ENABLE_SMART_PUNCTUATION);
+    opts
+}
+
+pub(crate) fn write<'a>(events: impl IntoIterator<Item = Event<'a>>) -> String {
+    // Pulldown splits text at escapes and entities, but we need to see it all
+    // at once to know what needs escaping.
+    let mut merged: Vec<Event<'a>> = Vec::new();
+    for ev in events {
+        match (merged.last_mut(), ev) {
+            (Some(Event::Text(prev)), Event::Text(text)) => {
+                *prev = CowStr::from(format!("{}{}", prev, text));
+            }
+            (_, ev) => merged.push(ev),
+        }
+    }
+    let events = merged;
+    let mut writer = Writer {
+        tight: tight_lists(&events).into_iter(),
+        at_line_start: true,
+        first_block: true,
+        ..Default::default()
+    };
+    for ev in events {
+        writer.event(ev);
+    }
+    if !writer.out.is_empty() && !writer.out.ends_with('\n') {
+        writer.out.push('\n');
+    }
+    writer.out
+}
+
+/// Whether each list (in order) is tight, ie it's items aren't paragraphs.
+fn tight_lists(events: &[Event<'_>]) -> Vec<bool> {
+    let mut out = Vec::new();
+    // The open tags, and for lists, their index in `out`.
+    let mut stack: Vec<(&Tag<'_>, Option<usize>)> = Vec::new();
+    for ev in events {
+        match ev {
+            Event::Start(tag @ Tag::List(_)) => {
+                stack.push((tag, Some(out.len())));
+                out.push(true);
+            }
+            Event::Start(tag) => {
+                if let (Tag::Paragraph, Some((Tag::Item, _))) = (tag, stack.last()) {
+                    if let Some(i) = stack.iter().rev().find_map(|(_, i)| *i) {
+                        out[i] = false;
+                    }
+                }
+                stack.push((tag, None));
+            }
+            Event::End(_) => {
+                stack.pop();
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Container {
+    Quote,
+    List { tight: bool },
+    Item { tight: bool },
+    Footnote,
+}
+
+#[derive(Default)]
+struct Writer<'a> {
+    out: String,
+    /// What goes at the start of each line, for each container we're in.
+    prefixes: Vec<String>,
+    containers: Vec<Container>,
+    /// List markers (or footnote labels) to write instead of the prefix at
+    /// that index, on the next line.
+    markers: Vec<(usize, String)>,
+    at_line_start: bool,
+    /// Whether nothing has been written on this line, after the prefix.
+    line_begin: bool,
+    /// Whether the next block is the first in it's container.
+    first_block: bool,
+    tight: IntoIter<bool>,
+    /// For each list, the next number if it's ordered, and whether it uses the
+    /// other marker.
+    lists: Vec<(Option<u64>, bool)>,
+    /// If the last block was a list, whether it was ordered, and if it used
+    /// the other marker.
+    prev_list: Option<(bool, bool)>,
+    in_html_block: bool,
+    in_paragraph: bool,
+    /// Whether we've written inline content outside a paragraph, ie in a
+    /// tight list item.
+    in_inline: bool,
+    in_heading: bool,
+    in_autolink: bool,
+    code: Option<(String, String)>,
+    links: Vec<(LinkType, CowStr<'a>, CowStr<'a>)>,
+    table: Option<Table>,
+    /// The output from outside the table cell we're in.
+    outside_cell: Option<String>,
+}
+
+#[derive(Default)]
+struct Table {
+    alignments: Vec<Alignment>,
+    rows: Vec<Vec<String>>,
+}
+
+impl<'a> Writer<'a> {
+    fn newline(&mut self) {
+        self.out.push('\n');
+        self.at_line_start = true;
+    }
+
+    fn ensure_prefix(&mut self) {
+        if !self.at_line_start {
+            return;
+        }
+        self.at_line_start = false;
+        self.line_begin = true;
+        self.first_block = false;
+        let markers = std::mem::take(&mut self.markers);
+        for (i, prefix) in self.prefixes.iter().enumerate() {
+            match markers.iter().find(|(at, _)| *at == i) {
+                Some((_, marker)) => self.out.push_str(marker),
+                None => self.out.push_str(prefix),
+            }
+        }
+    }
+
+    /// The prefix for a blank line.
+    fn blank_prefix(&mut self) {
+        let prefix = self.prefixes.concat();
+        self.out.push_str(prefix.trim_end());
+    }
+
+    /// Separate the block we're starting from the one before.
+    fn block(&mut self) {
+        self.in_html_block = false;
+        self.in_inline = false;
+        if std::mem::take(&mut self.first_block) || self.out.is_empty() {
+            return;
+        }
+        if !self.at_line_start {
+            self.newline();
+        }
+        let tight = matches!(
+            self.containers.last(),
+            Some(Container::List { tight: true }) | Some(Container::Item { tight: true })
+        );
+        if !tight {
+            self.blank_prefix();
+            self.newline();
+        }
+    }
+
+    fn start_container(&mut self, container: Container, prefix: String, marker: Option<String>) {
+        self.block();
+        self.prev_list = None;
+        if let Some(marker) = marker {
+            self.markers.push((self.prefixes.len(), marker));
+        }
+        self.prefixes.push(prefix);
+        self.containers.push(container);
+        self.first_block = true;
+    }
+
+    fn end_container(&mut self) {
+        // Empty list items still need their marker.
+        if !self.markers.is_empty() {
+            for (_, marker) in &mut self.markers {
+                *marker = marker.trim_end().to_owned();
+            }
+            self.ensure_prefix();
+        }
+        self.prefixes.pop();
+        self.containers.pop();
+        self.first_block = false;
+        self.prev_list = None;
+        self.in_inline = false;
+    }
+
+    /// Write something without escaping it.
+    fn raw(&mut self, text: &str) {
+        let lines: Vec<_> = text.split('\n').collect();
+        for (i, line) in lines.iter().enumerate() {
+            if i > 0 {
+                self.newline();
+            }
+            if !line.is_empty() {
+                self.ensure_prefix();
+                self.out.push_str(line);
+                self.line_begin = false;
+            } else if i + 1 < lines.len() && self.at_line_start {
+                // Blank lines in code blocks mustn't end the blockquote.
+                self.blank_prefix();
+            }
+        }
+    }
+
+    fn inline(&mut self, syntax: &str) {
+        self.raw(syntax);
+        self.in_inline = true;
+    }
+
+    fn text(&mut self, text: &str) {
+        if self.in_autolink {
+            return self.raw(text);
+        }
+        self.ensure_prefix();
+        self.in_inline = true;
+        let escaped = escape(text, self.line_begin, self.outside_cell.is_some());
+        self.out.push_str(&escaped);
+        self.line_begin = false;
+    }
+
+    fn event(&mut self, ev: Event<'a>) {
+        if let Some((_, code)) = &mut self.code {
+            match ev {
+                Event::Text(text) => code.push_str(&text),
+                Event::End(Tag::CodeBlock(_)) => {
+                    let (info, code) = self.code.take().unwrap();
+                    self.code_block(&info, &code);
+                }
+                _ => {}
+            }
+            return;
+        }
+        if !matches!(ev, Event::Html(_)) {
+            self.in_html_block = false;
+        }
+
+        match ev {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) => self.text(&text),
+            Event::Code(code) => {
+                let ticks = "`".repeat(longest_run(&code, '`') + 1);
+                let pad = code.starts_with('`')
+                    || code.ends_with('`')
+                    || (code.starts_with(' ') && code.ends_with(' ') && !code.trim().is_empty());
+                let pad = if pad { " " } else { "" };
+                self.raw(&format!("{}{}{}{}{}", ticks, pad, code, pad, ticks));
+                self.in_inline = true;
+            }
+            Event::Html(html) => {
+                let inline = self.in_paragraph
+                    || self.in_heading
+                    || self.in_inline
+                    || self.outside_cell.is_some();
+                if !inline && !self.in_html_block {
+                    self.block();
+                    self.in_html_block = true;
+                }
+                self.raw(&html);
+            }
+            Event::FootnoteReference(name) => self.raw(&format!("[^{}]", name)),
+            Event::SoftBreak if self.in_heading => self.raw(" "),
+            Event::SoftBreak => self.newline(),
+            Event::HardBreak => {
+                self.raw("\\");
+                self.newline();
+            }
+            Event::Rule => {
+                self.block();
+                self.prev_list = None;
+                self.raw("---");
+            }
+            Event::TaskListMarker(done) => self.raw(if done { "[x] " } else { "[ ] " }),
+        }
+    }
+
+    fn start(&mut self, tag: Tag<'a>) {
+        match tag {
+            Tag::Paragraph => {
+                self.block();
+                self.prev_list = None;
+                self.in_paragraph = true;
+            }
+            Tag::Heading(level) => {
+                self.block();
+                self.prev_list = None;
+                self.in_heading = true;
+                self.raw(&format!("{} ", "#".repeat(level as usize)));
+            }
+            Tag::BlockQuote => self.start_container(Container::Quote, "> ".into(), None),
+            Tag::CodeBlock(kind) => {
+                let info = match kind {
+                    CodeBlockKind::Fenced(info) => info.to_string(),
+                    CodeBlockKind::Indented => String::new(),
+                };
+                self.code = Some((info, String::new()));
+            }
+            Tag::List(start) => {
+                let tight = self.tight.next().unwrap_or(true);
+                // Two lists in a row with the same marker would become one.
+                let alt = match self.prev_list {
+                    Some((ordered, alt)) if ordered == start.is_some() => !alt,
+                    _ => false,
+                };
+                self.start_container(Container::List { tight }, String::new(), None);
+                self.lists.push((start, alt));
+            }
+            Tag::Item => {
+                let marker = match self.lists.last_mut() {
+                    Some((Some(n), alt)) => {
+                        *n += 1;
+                        format!("{}{} ", *n - 1, if *alt { ')' } else { '.' })
+                    }
+                    Some((None, true)) => "* ".into(),
+                    _ => "- ".into(),
+                };
+                let tight = match self.containers.last() {
+                    Some(Container::List { tight }) => *tight,
+                    _ => false,
+                };
+                let prefix = " ".repeat(marker.len());
+                self.start_container(Container::Item { tight }, prefix, Some(marker));
+            }
+            Tag::FootnoteDefinition(name) => {
+                let marker = format!("[^{}]: ", name);
+                self.start_container(Container::Footnote, "    ".into(), Some(marker));
+            }
+            Tag::Table(alignments) => {
+                self.block();
+                self.prev_list = None;
+                self.table = Some(Table {
+                    alignments,
+                    rows: Vec::new(),
+                });
+            }
+            Tag::TableHead | Tag::TableRow => {
+                if let Some(table) = &mut self.table {
+                    table.rows.push(Vec::new());
+                }
+            }
+            Tag::TableCell => {
+                self.outside_cell = Some(std::mem::take(&mut self.out));
+                self.at_line_start = false;
+                self.line_begin = false;
+            }
+            Tag::Emphasis => self.inline("*"),
+            Tag::Strong => self.inline("**"),
+            Tag::Strikethrough => self.inline("~~"),
+            Tag::Link(LinkType::Autolink, ..) | Tag::Link(LinkType::Email, ..) => {
+                self.in_inline = true;
+                self.in_autolink = true;
+                self.raw("<");
+            }
+            Tag::Link(ty, dest, title) => {
+                self.inline("[");
+                self.links.push((ty, dest, title));
+            }
+            Tag::Image(ty, dest, title) => {
+                self.inline("![");
+                self.links.push((ty, dest, title));
+            }
+        }
+    }
+
+    fn end(&mut self, tag: Tag<'a>) {
+        match tag {
+            Tag::Paragraph => {
+                self.in_paragraph = false;
+                self.in_inline = false;
+            }
+            Tag::Heading(_) => {
+                self.in_heading = false;
+                self.in_inline = false;
+                // A trailing `#` would be taken as a closing sequence.
+                let trimmed = self.out.trim_end_matches('#');
+                if trimmed.len() != self.out.len() && trimmed.ends_with(' ') {
+                    self.out.insert(trimmed.len(), '\\');
+                }
+            }
+            Tag::BlockQuote | Tag::Item | Tag::FootnoteDefinition(_) => self.end_container(),
+            Tag::List(_) => {
+                self.end_container();
+                self.prev_list = self.lists.pop().map(|(n, alt)| (n.is_some(), alt));
+            }
+            Tag::CodeBlock(_) => {}
+            Tag::Table(_) => {
+                if let Some(table) = self.table.take() {
+                    self.write_table(table);
+                }
+            }
+            Tag::TableHead | Tag::TableRow => {}
+            Tag::TableCell => {
+                let outside = self.outside_cell.take().unwrap_or_default();
+                let cell = std::mem::replace(&mut self.out, outside);
+                if let Some(row) = self.table.as_mut().and_then(|t| t.rows.last_mut()) {
+                    row.push(cell);
+                }
+            }
+            Tag::Emphasis => self.raw("*"),
+            Tag::Strong => self.raw("**"),
+            Tag::Strikethrough => self.raw("~~"),
+            Tag::Link(LinkType::Autolink, ..) | Tag::Link(LinkType::Email, ..) => {
+                self.in_autolink = false;
+                self.raw(">");
+            }
+            Tag::Link(..) | Tag::Image(..) => {
+                if let Some((_, dest, title)) = self.links.pop() {
+                    self.raw(&format!("]({})", destination(&dest, &title)));
+                }
+            }
+        }
+    }
+
+    fn code_block(&mut self, info: &str, code: &str) {
+        self.block();
+        self.prev_list = None;
+        let (fence_char, info) = if info.contains('`') {
+            ('~', info)
+        } else {
+            ('`', info)
+        };
+        let fence = fence_char
+            .to_string()
+            .repeat((longest_run(code, fence_char) + 1).max(3));
+        self.raw(&format!("{}{}", fence, info));
+        self.newline();
+        self.raw(code);
+        if !code.is_empty() && !code.ends_with('\n') {
+            self.newline();
+        }
+        self.raw(&fence);
+    }
+
+    fn write_table(&mut self, table: Table) {
+        let columns = table.alignments.len();
+        let mut widths = vec![3; columns];
+        for row in &table.rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+
+        let mut lines = Vec::new();
+        for (i, row) in table.rows.iter().enumerate() {
+            let cells: Vec<_> = (0..columns)
+                .map(|col| {
+                    let cell = row.get(col).map_or("", String::as_str);
+                    format!("{:width$}", cell, width = widths[col])
+                })
+                .collect();
+            lines.push(format!("| {} |", cells.join(" | ")));
+
+            if i == 0 {
+                let delims: Vec<_> = table
+                    .alignments
+                    .iter()
+                    .zip(&widths)
+                    .map(|(align, &width)| match align {
+                        Alignment::None => "-".repeat(width),
+                        Alignment::Left => format!(":{}", "-".repeat(width - 1)),
+                        Alignment::Right => format!("{}:", "-".repeat(width - 1)),
+                        Alignment::Center => format!(":{}:", "-".repeat(width - 2)),
+                    })
+                    .collect();
+                lines.push(format!("| {} |", delims.join(" | ")));
+            }
+        }
+        self.raw(&lines.join("\n"));
+    }
+}
+
+fn longest_run(text: &str, c: char) -> usize {
+    let mut longest = 0;
+    let mut current = 0;
+    for ch in text.chars() {
+        if ch == c {
+            current += 1;
+            longest = longest.max(current);
+        } else {
+            current = 0;
+        }
+    }
+    longest
+}
+
+/// The `(dest "title")` bit of a link.
+fn destination(dest: &str, title: &str) -> String {
+    let mut out = if dest.contains(|c: char| c.is_whitespace() || c == '(' || c == ')') {
+        format!("<{}>", dest.replace('<', "\\<").replace('>', "\\>"))
+    } else {
+        dest.to_owned()
+    };
+    if !title.is_empty() {
+        out.push_str(&format!(
+            " \"{}\"",
+            title.replace('\\', "\\\\").replace('"', "\\\"")
+        ));
+    }
+    out
+}
+
+/// Escape anything in `text` that markdown would treat as syntax.
+fn escape(text: &str, line_begin: bool, in_table: bool) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::with_capacity(text.len());
+
+    for (i, &c) in chars.iter().enumerate() {
+        let prev = if i == 0 { None } else { Some(chars[i - 1]) };
+        let next = chars.get(i + 1).copied();
+        let escape = match c {
+            '\\' | '*' | '`' | '[' | ']' | '<' | '~' => true,
+            '|' => in_table,
+            // `_` inside a word can't be emphasis, so don't make snake_case ugly.
+            '_' => {
+                !(prev.is_some_and(char::is_alphanumeric)
+                    && next.is_some_and(char::is_alphanumeric))
+            }
+            '&' => {
+                let rest: String = chars[i + 1..].iter().collect();
+                let name_len = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '#'))
+                    .unwrap_or(rest.len());
+                name_len > 0 && rest[name_len..].starts_with(';')
+            }
+            _ if i == 0 && line_begin => match c {
+                '#' | '>' | '=' => true,
+                '-' | '+' => next.is_none_or(|n| n == ' ' || n == c),
+                _ => false,
+            },
+            // `1.` or `1)` at the start of a line is a list.
+            '.' | ')' if line_begin && i > 0 => chars[..i].iter().all(char::is_ascii_digit),
+            _ => false,
+        };
+        if escape {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use pulldown_cmark::{html, Parser};
+
+    use super::*;
+
+    fn render(md: &str) -> String {
+        let mut out = String::new();
+        html::push_html(&mut out, Parser::new_ext(md, options()));
+        out
+    }
+
+    fn round_trip(md: &str) -> String {
+        let out = write(Parser::new_ext(md, options()));
+        assert_eq!(render(md), render(&out), "\n{}", out);
+        out
+    }
+
+    #[test]
+    fn normalizes() {
+        let md = "Setext\n===\n\n_em_ __strong__ [ref][r] ![img](a.png \"t\")\n\n    indented\n\n[r]: https://example.com\n";
+        assert_eq!(
+            round_trip(md),
+            "# Setext\n\n*em* **strong** [ref](https://example.com) ![img](a.png \"t\")\n\n```\nindented\n```\n"
+        );
+    }
+
+    #[test]
+    fn containers() {
+        let md = "> quote\n> - a\n> - b\n>\n>   ```rust\n>   code\n>\n>   ```\n\n1. one\n\n   two\n2. three\n- x\n  - y\n+ z\n";
+        assert_eq!(
+            round_trip(md),
+            "> quote\n>\n> - a\n>\n> - b\n>\n>   ```rust\n>   code\n>\n>   ```\n\n1. one\n\n   two\n\n2. three\n\n- x\n  - y\n\n* z\n"
+        );
+    }
+
+    #[test]
+    fn escaping() {
+        round_trip("\\# not a heading\n\n1\\. not a list\n\n\\- nor this\n\nsnake_case \\*not em\\* a\\_b\\_ &amp;copy; \\<br> x \\~~y~~\n");
+        round_trip("# C# #\n\n`` a`b `` ` `` `\n\n<div>\nhtml\n</div>\n\ninline <b>html</b>\n");
+        round_trip(
+            "[a b](<x y.md> \"say \\\"hi\\\"\") <https://example.com> line  \nbreak\\\nagain\n",
+        );
+    }
+
+    #[test]
+    fn tables_and_footnotes() {
+        let md = "| a | b |\n|:-|-:|\n| `c` | d \\| e |\n\nNote[^1].\n\n[^1]: The note.\n\n- [x] done\n- [ ] todo\n";
+        assert_eq!(
+            round_trip(md),
+            "| a   | b      |\n| :-- | -----: |\n| `c` | d \\| e |\n\nNote[^1].\n\n[^1]: The note.\n\n- [x] done\n- [ ] todo\n"
+        );
+    }
+
+    #[test]
+    fn corpus() {
+        for dir in &["src/render_html_tests", "dummy-book"] {
+            let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join(dir);
+            for file in ignore::Walk::new(dir) {
+                let path = file.unwrap().into_path();
+                if path.extension().is_some_and(|x| x == "md") {
+                    round_trip(&std::fs::read_to_string(path).unwrap());
+                }
+            }
+        }
+    }
+}
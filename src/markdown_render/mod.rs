@@ -0,0 +1,316 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! Cleaned up markdown, for publishing somewhere else.
+//!
+//! Each book becomes either a tree of markdown files laid out like the source,
+//! or a single file with every chapter in it. Either way, front matter is
+//! stripped, `:::` containers are expanded, and the files chapters link to
+//! (like images) are copied alongside. In a single file, links between
+//! chapters become links to headings.
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Debug;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use eyre::{Result, WrapErr};
+use pulldown_cmark::{CowStr, Event, Parser, Tag};
+use tracing::instrument;
+
+use crate::cli;
+use crate::cli::config::{GlobalConf, MarkdownConf, MarkdownLayout};
+use crate::content::{self, front_matter, links, Book, Content, Page};
+use crate::html_render::{dedupe, expand_containers, slugify};
+
+mod cmark;
+
+pub(crate) struct MarkdownRender<'a> {
+    content: Content,
+    conf: &'a MarkdownConf,
+    out_dir: PathBuf,
+}
+
+impl Debug for MarkdownRender<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MarkdownRender")
+            .field("out_dir", &self.out_dir)
+            .field("conf", &self.conf)
+            .finish()
+    }
+}
+
+impl<'a> MarkdownRender<'a> {
+    #[instrument]
+    pub(crate) fn new(conf: &GlobalConf, args: &cli::Args, md: &'a MarkdownConf) -> Result<Self> {
+        let dirs = content::Dirs::new(conf, args);
+        let content = Content::new(conf, &dirs)?;
+        Ok(Self {
+            content,
+            conf: md,
+            out_dir: conf.build.out_dir.join("markdown"),
+        })
+    }
+
+    #[instrument]
+    pub(crate) fn render(&self) -> Result<()> {
+        if self.out_dir.exists() {
+            fs::remove_dir_all(&self.out_dir)?;
+        }
+        fs::create_dir_all(&self.out_dir)?;
+
+        for book in &self.content.0 {
+            match self.conf.layout {
+                MarkdownLayout::Tree => self.tree(book)?,
+                MarkdownLayout::Single => self.single(book)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Each chapter where it was, in `<book>/`.
+    #[instrument]
+    fn tree(&self, book: &Book) -> Result<()> {
+        let root = self.out_dir.join(book.dir_name());
+        let mut assets = BTreeSet::new();
+
+        for page in &book.pages {
+            let src = read(page)?;
+            let events = map_links(Parser::new_ext(&src, cmark::options()), |dest| {
+                assets.extend(asset(book, &page.input, dest));
+                None
+            });
+            let rel = page
+                .input
+                .strip_prefix(&book.location)
+                .wrap_err_with(|| format!("{:?} isn't in the book", page.input))?;
+            write(&root.join(rel), &cmark::write(events))?;
+        }
+
+        // The summary is markdown too, and says what order things go in.
+        let summary = book.location.join("SUMMARY.md");
+        if summary.is_file() {
+            let src = fs::read_to_string(&summary)
+                .wrap_err_with(|| format!("Couldn't open {:?}", summary))?;
+            let md = cmark::write(Parser::new_ext(&src, cmark::options()));
+            write(&root.join("SUMMARY.md"), &md)?;
+        }
+
+        copy_assets(book, &root, &assets)
+    }
+
+    /// Every chapter in `<book>.md`, with the files they link to in `<book>/`.
+    #[instrument]
+    fn single(&self, book: &Book) -> Result<()> {
+        let name = book.dir_name();
+        let sources = book.pages.iter().map(read).collect::<Result<Vec<_>>>()?;
+
+        let chapters: Vec<Vec<Event<'_>>> = book
+            .pages
+            .iter()
+            .zip(&sources)
+            .map(|(page, src)| {
+                let mut events: Vec<_> = Parser::new_ext(src, cmark::options()).collect();
+                // Every chapter needs a heading, to link to.
+                if !matches!(events.first(), Some(Event::Start(Tag::Heading(1)))) {
+                    let title = vec![
+                        Event::Start(Tag::Heading(1)),
+                        Event::Text(page.title().into()),
+                        Event::End(Tag::Heading(1)),
+                    ];
+                    events.splice(0..0, title);
+                }
+                events
+            })
+            .collect();
+
+        // Heading ids have to be unique across the whole file now, so work out
+        // what each one in each chapter became.
+        let mut seen = HashMap::new();
+        let mut anchors: HashMap<(&Path, String), String> = HashMap::new();
+        let mut starts: HashMap<&Path, String> = HashMap::new();
+        for (page, events) in book.pages.iter().zip(&chapters) {
+            let mut local = HashMap::new();
+            for text in heading_texts(events) {
+                let slug = slugify(&text);
+                let id = dedupe(&mut seen, slug.clone());
+                starts
+                    .entry(page.input.as_path())
+                    .or_insert_with(|| id.clone());
+                anchors.insert((page.input.as_path(), dedupe(&mut local, slug)), id);
+            }
+        }
+
+        let mut assets = BTreeSet::new();
+        let mut out = Vec::new();
+        for (i, (page, events)) in book.pages.iter().zip(chapters).enumerate() {
+            let events = map_links(events, |dest| {
+                let link = links::resolve(&page.input, dest)?;
+                if let Some(start) = starts.get(link.path.as_path()) {
+                    let id = match link.fragment {
+                        Some(frag) => anchors
+                            .get(&(link.path.as_path(), frag.to_owned()))
+                            .map_or(frag, String::as_str),
+                        None => start,
+                    };
+                    return Some(format!("#{}", id));
+                }
+                let rel = asset(book, &page.input, dest)?;
+                let href = Path::new(&name).join(&rel);
+                assets.insert(rel);
+                Some(href.to_string_lossy().into_owned())
+            });
+            // Footnotes from different chapters mustn't clash.
+            let events = events.map(|ev| match ev {
+                Event::FootnoteReference(name) => {
+                    Event::FootnoteReference(format!("{}-{}", i + 1, name).into())
+                }
+                Event::Start(Tag::FootnoteDefinition(name)) => Event::Start(
+                    Tag::FootnoteDefinition(format!("{}-{}", i + 1, name).into()),
+                ),
+                ev => ev,
+            });
+            out.push(cmark::write(events));
+        }
+
+        write(&self.out_dir.join(format!("{}.md", name)), &out.join("\n"))?;
+        copy_assets(book, &self.out_dir.join(&name), &assets)
+    }
+}
+
+/// The chapter's markdown, ready to be parsed.
+fn read(page: &Page) -> Result<String> {
+    let (_, body) = front_matter::read(&page.input)?;
+    Ok(expand_containers(&body).into_owned())
+}
+
+fn write(path: &Path, md: &str) -> Result<()> {
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(path, md).wrap_err_with(|| format!("Failed to create {:?}", path))
+}
+
+fn map_links<'a>(
+    events: impl IntoIterator<Item = Event<'a>>,
+    mut rewrite: impl FnMut(&str) -> Option<String>,
+) -> impl Iterator<Item = Event<'a>> {
+    events.into_iter().map(move |ev| match ev {
+        Event::Start(Tag::Link(ty, dest, title)) => {
+            let dest = rewrite(&dest).map_or(dest, CowStr::from);
+            Event::Start(Tag::Link(ty, dest, title))
+        }
+        Event::Start(Tag::Image(ty, dest, title)) => {
+            let dest = rewrite(&dest).map_or(dest, CowStr::from);
+            Event::Start(Tag::Image(ty, dest, title))
+        }
+        ev => ev,
+    })
+}
+
+/// If `dest` is a file in the book that isn't a chapter, where it is in the
+/// book.
+fn asset(book: &Book, from: &Path, dest: &str) -> Option<PathBuf> {
+    let link = links::resolve(from, dest)?;
+    if link.path.extension().is_some_and(|x| x == "md") || !link.path.is_file() {
+        return None;
+    }
+    Some(link.path.strip_prefix(&book.location).ok()?.to_owned())
+}
+
+fn copy_assets(book: &Book, root: &Path, assets: &BTreeSet<PathBuf>) -> Result<()> {
+    for rel in assets {
+        let to = root.join(rel);
+        fs::create_dir_all(to.parent().unwrap())?;
+        fs::copy(book.location.join(rel), &to)
+            .wrap_err_with(|| format!("Failed to create {:?}", to))?;
+    }
+    Ok(())
+}
+
+/// The text of each heading, the same as `HeadingIds` sees it.
+fn heading_texts(events: &[Event<'_>]) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut current: Option<String> = None;
+    for ev in events {
+        match (ev, &mut current) {
+            (Event::Start(Tag::Heading(_)), _) => current = Some(String::new()),
+            (Event::End(Tag::Heading(_)), _) => out.extend(current.take()),
+            (Event::Text(t), Some(text)) | (Event::Code(t), Some(text)) => text.push_str(t),
+            _ => {}
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::prelude::*;
+
+    use super::*;
+
+    fn book(temp: &assert_fs::TempDir) -> Book {
+        temp.child("b/SUMMARY.md")
+            .write_str("# Summary\n\n- [A](a.md)\n    - [B](sub/b.md)\n")
+            .unwrap();
+        temp.child("b/img/cat.png").write_binary(b"meow").unwrap();
+        temp.child("b/a.md")
+            .write_str(
+                "+++\ntitle = \"Alpha\"\n+++\n# Usage\n\nSee [b](sub/b.md#usage), [b](sub/b.md) \
+                 and [here](#usage).[^1]\n\n:::tip\n![cat](img/cat.png)\n:::\n\n[^1]: A note.\n",
+            )
+            .unwrap();
+        temp.child("b/sub/b.md")
+            .write_str(
+                "No heading here.\n\n## Usage\n\n![cat](../img/cat.png)[^1]\n\n[^1]: B's note.\n",
+            )
+            .unwrap();
+
+        let page = |path: &str, name: &str| Page {
+            name: name.into(),
+            input: temp.path().join(path),
+            front_matter: front_matter::read(&temp.path().join(path)).unwrap().0,
+            ..Default::default()
+        };
+        Book {
+            title: "B".into(),
+            location: temp.path().join("b"),
+            pages: vec![page("b/a.md", "A"), page("b/sub/b.md", "B")],
+            ..Default::default()
+        }
+    }
+
+    fn render(temp: &assert_fs::TempDir, layout: MarkdownLayout) {
+        let conf = MarkdownConf { layout };
+        MarkdownRender {
+            content: Content(vec![book(temp)]),
+            conf: &conf,
+            out_dir: temp.path().join("_out/markdown"),
+        }
+        .render()
+        .unwrap();
+    }
+
+    #[test]
+    fn tree() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        render(&temp, MarkdownLayout::Tree);
+
+        let out = temp.child("_out/markdown/b");
+        let a = fs::read_to_string(out.child("a.md").path()).unwrap();
+        assert!(a.starts_with("# Usage\n"));
+        assert!(a.contains("[b](sub/b.md#usage)"));
+        assert!(a.contains("<div class=\"admonition tip\">"));
+        out.child("sub/b.md").assert(
+            "No heading here.\n\n## Usage\n\n![cat](../img/cat.png)[^1]\n\n[^1]: B's note.\n",
+        );
+        out.child("SUMMARY.md")
+            .assert("# Summary\n\n- [A](a.md)\n  - [B](sub/b.md)\n");
+        out.child("img/cat.png").assert("meow");
+    }
+
+    #[test]
+    fn single() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        render(&temp, MarkdownLayout::Single);
+
+        insta::assert_snapshot!(fs::read_to_string(temp.path().join("_out/markdown/b.md")).unwrap());
+        temp.child("_out/markdown/b/img/cat.png").assert("meow");
+    }
+}
@@ -0,0 +1,259 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! Flatten the markdown event stream into blocks we know how to lay out.
+use pulldown_cmark::{Event, Options, Parser, Tag};
+
+use super::writer::Font;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Span {
+    pub(crate) text: String,
+    pub(crate) font: Font,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Block {
+    Heading {
+        level: u32,
+        spans: Vec<Span>,
+    },
+    Paragraph {
+        spans: Vec<Span>,
+        /// How deep in lists and blockquotes we are.
+        indent: u32,
+        /// For the first paragraph of a list item.
+        bullet: Option<String>,
+    },
+    Code {
+        text: String,
+        indent: u32,
+    },
+    Rule,
+}
+
+/// A forced line break in a paragraph.
+pub(crate) const LINE_BREAK: &str = "\n";
+
+#[derive(Default)]
+struct Builder {
+    blocks: Vec<Block>,
+    spans: Vec<Span>,
+    bold: u32,
+    italic: u32,
+    indent: u32,
+    /// The next number for each list we're in, `None` for bullet lists.
+    lists: Vec<Option<u64>>,
+    bullet: Option<String>,
+    code: Option<String>,
+}
+
+impl Builder {
+    fn font(&self) -> Font {
+        if self.bold > 0 {
+            Font::Bold
+        } else if self.italic > 0 {
+            Font::Italic
+        } else {
+            Font::Regular
+        }
+    }
+
+    fn push(&mut self, text: &str, font: Font) {
+        self.spans.push(Span {
+            text: text.to_owned(),
+            font,
+        })
+    }
+
+    /// End the current paragraph, if there is one.
+    fn flush(&mut self) {
+        if !self.spans.is_empty() {
+            self.blocks.push(Block::Paragraph {
+                spans: std::mem::take(&mut self.spans),
+                indent: self.indent,
+                bullet: self.bullet.take(),
+            });
+        }
+    }
+
+    fn event(&mut self, ev: Event<'_>) {
+        if let Some(code) = &mut self.code {
+            match ev {
+                Event::End(Tag::CodeBlock(_)) => {
+                    let text = self.code.take().unwrap();
+                    self.blocks.push(Block::Code {
+                        text: text.trim_end_matches('\n').to_owned(),
+                        indent: self.indent,
+                    });
+                }
+                Event::Text(t) => code.push_str(&t),
+                _ => {}
+            }
+            return;
+        }
+
+        match ev {
+            Event::Start(Tag::Paragraph) => self.flush(),
+            Event::End(Tag::Paragraph) => self.flush(),
+            Event::Start(Tag::Heading(_)) => self.flush(),
+            Event::End(Tag::Heading(level)) => {
+                let spans = std::mem::take(&mut self.spans);
+                self.blocks.push(Block::Heading { level, spans });
+            }
+            Event::Start(Tag::BlockQuote) => {
+                self.flush();
+                self.indent += 1;
+            }
+            Event::End(Tag::BlockQuote) => {
+                self.flush();
+                self.indent -= 1;
+            }
+            Event::Start(Tag::CodeBlock(_)) => {
+                self.flush();
+                self.code = Some(String::new());
+            }
+            Event::Start(Tag::List(start)) => {
+                self.flush();
+                self.lists.push(start);
+                self.indent += 1;
+            }
+            Event::End(Tag::List(_)) => {
+                self.flush();
+                self.lists.pop();
+                self.indent -= 1;
+            }
+            Event::Start(Tag::Item) => {
+                self.flush();
+                self.bullet = Some(match self.lists.last_mut() {
+                    Some(Some(n)) => {
+                        *n += 1;
+                        format!("{}.", *n - 1)
+                    }
+                    _ => "•".to_owned(),
+                });
+            }
+            Event::End(Tag::Item) => self.flush(),
+            Event::Start(Tag::Emphasis) => self.italic += 1,
+            Event::End(Tag::Emphasis) => self.italic -= 1,
+            Event::Start(Tag::Strong) | Event::Start(Tag::TableHead) => self.bold += 1,
+            Event::End(Tag::Strong) => self.bold -= 1,
+            Event::End(Tag::TableHead) => {
+                self.bold -= 1;
+                self.flush();
+            }
+            Event::Start(Tag::Image(..)) => self.italic += 1,
+            Event::End(Tag::Image(..)) => self.italic -= 1,
+            Event::Start(Tag::TableCell) if !self.spans.is_empty() => {
+                self.push("  |  ", Font::Regular)
+            }
+            Event::End(Tag::TableRow) => self.flush(),
+            Event::Text(t) => self.push(&t, self.font()),
+            Event::Code(t) => self.push(&t, Font::Mono),
+            Event::SoftBreak => self.push(" ", Font::Regular),
+            Event::HardBreak => self.push(LINE_BREAK, Font::Regular),
+            Event::FootnoteReference(name) => self.push(&format!("[{}]", name), Font::Regular),
+            Event::TaskListMarker(done) => {
+                self.push(if done { "[x] " } else { "[ ] " }, Font::Mono)
+            }
+            Event::Rule => {
+                self.flush();
+                self.blocks.push(Block::Rule);
+            }
+            // We can't do anything sensible with html, and the rest is just
+            // styling we don't support.
+            _ => {}
+        }
+    }
+}
+
+pub(crate) fn blocks(markdown: &str) -> Vec<Block> {
+    let mut builder = Builder::default();
+    for ev in Parser::new_ext(markdown, Options::all()) {
+        builder.event(ev);
+    }
+    builder.flush();
+    builder.blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(text: &str, font: Font) -> Span {
+        Span {
+            text: text.into(),
+            font,
+        }
+    }
+
+    #[test]
+    fn structure() {
+        let md = "# Title\n\nSome **bold** `code`\n\n1. one\n2. two\n   - nested\n\n```\nfn x() {}\n```\n---\n";
+        assert_eq!(
+            blocks(md),
+            vec![
+                Block::Heading {
+                    level: 1,
+                    spans: vec![span("Title", Font::Regular)]
+                },
+                Block::Paragraph {
+                    spans: vec![
+                        span("Some ", Font::Regular),
+                        span("bold", Font::Bold),
+                        span(" ", Font::Regular),
+                        span("code", Font::Mono)
+                    ],
+                    indent: 0,
+                    bullet: None,
+                },
+                Block::Paragraph {
+                    spans: vec![span("one", Font::Regular)],
+                    indent: 1,
+                    bullet: Some("1.".into()),
+                },
+                Block::Paragraph {
+                    spans: vec![span("two", Font::Regular)],
+                    indent: 1,
+                    bullet: Some("2.".into()),
+                },
+                Block::Paragraph {
+                    spans: vec![span("nested", Font::Regular)],
+                    indent: 2,
+                    bullet: Some("•".into()),
+                },
+                Block::Code {
+                    text: "fn x() {}".into(),
+                    indent: 0
+                },
+                Block::Rule,
+            ]
+        );
+    }
+
+    #[test]
+    fn tables() {
+        let md = "| a | b |\n|---|---|\n| c | d |\n";
+        assert_eq!(
+            blocks(md),
+            vec![
+                Block::Paragraph {
+                    spans: vec![
+                        span("a", Font::Bold),
+                        span("  |  ", Font::Regular),
+                        span("b", Font::Bold)
+                    ],
+                    indent: 0,
+                    bullet: None,
+                },
+                Block::Paragraph {
+                    spans: vec![
+                        span("c", Font::Regular),
+                        span("  |  ", Font::Regular),
+                        span("d", Font::Regular)
+                    ],
+                    indent: 0,
+                    bullet: None,
+                },
+            ]
+        );
+    }
+}
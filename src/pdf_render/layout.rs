@@ -0,0 +1,381 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! Put blocks onto pages.
+//!
+//! This is a very simple "fill the page top to bottom, then start a new one"
+//! layout, with greedy line breaking.
+use std::io::Write;
+
+use super::blocks::{Span, LINE_BREAK};
+use super::writer::{literal, Dest, Font, LinkAnnot, Page};
+
+const MARGIN: f32 = 72.0;
+const BODY_SIZE: f32 = 11.0;
+const CODE_SIZE: f32 = 9.0;
+const INDENT: f32 = 18.0;
+
+/// A line of text, as runs of the same font.
+type Line = Vec<(Font, String)>;
+
+pub(crate) struct Layout {
+    pub(crate) width: f32,
+    pub(crate) height: f32,
+    pages: Vec<Page>,
+    /// The index of our first page in the whole document.
+    first_page: usize,
+    /// Where the top of the next thing goes.
+    y: f32,
+    /// Whether to put page numbers at the bottom.
+    numbered: bool,
+}
+
+impl Layout {
+    pub(crate) fn new(width: f32, height: f32, first_page: usize, numbered: bool) -> Self {
+        Self {
+            width,
+            height,
+            pages: vec![Page::default()],
+            first_page,
+            y: height - MARGIN,
+            numbered,
+        }
+    }
+
+    /// The current position, for bookmarks and links.
+    pub(crate) fn here(&self) -> Dest {
+        Dest {
+            page: self.first_page + self.pages.len() - 1,
+            y: self.y,
+        }
+    }
+
+    fn at_top(&self) -> bool {
+        self.y >= self.height - MARGIN
+    }
+
+    pub(crate) fn new_page(&mut self) {
+        if !self.at_top() || !self.pages.last().unwrap().content.is_empty() {
+            self.pages.push(Page::default());
+        }
+        self.y = self.height - MARGIN;
+    }
+
+    /// Start a new page if we don't have `height` left on this one.
+    fn ensure(&mut self, height: f32) {
+        if self.y - height < MARGIN && !self.at_top() {
+            self.new_page();
+        }
+    }
+
+    pub(crate) fn space(&mut self, height: f32) {
+        if !self.at_top() {
+            self.y -= height;
+        }
+    }
+
+    fn max_width(&self) -> f32 {
+        self.width - 2.0 * MARGIN
+    }
+
+    fn draw(&mut self, ops: &[u8]) {
+        self.pages
+            .last_mut()
+            .unwrap()
+            .content
+            .extend_from_slice(ops);
+    }
+
+    /// Draw a line of text with it's baseline at `y`.
+    fn draw_line(&mut self, x: f32, y: f32, size: f32, line: &[(Font, String)]) {
+        let mut ops = format!("BT {:.2} {:.2} Td ", x, y).into_bytes();
+        for (font, text) in line {
+            write!(ops, "/{} {} Tf ", font.resource(), size).unwrap();
+            ops.extend(literal(text));
+            ops.extend(b" Tj ");
+        }
+        ops.extend(b"ET\n");
+        self.draw(&ops);
+    }
+
+    /// Draw each line, moving down the page as we go.
+    fn lines(&mut self, x: f32, size: f32, lines: &[Line], bullet: Option<&str>) {
+        let line_height = size * 1.4;
+        for (i, line) in lines.iter().enumerate() {
+            self.ensure(line_height);
+            let baseline = self.y - size;
+            if let (0, Some(bullet)) = (i, bullet) {
+                self.draw_line(
+                    x - INDENT + 4.0,
+                    baseline,
+                    size,
+                    &[(Font::Regular, bullet.into())],
+                );
+            }
+            self.draw_line(x, baseline, size, line);
+            self.y -= line_height;
+        }
+    }
+
+    pub(crate) fn paragraph(&mut self, spans: &[Span], indent: u32, bullet: Option<&str>) {
+        let x = MARGIN + indent as f32 * INDENT;
+        let lines = wrap(spans, BODY_SIZE, self.width - MARGIN - x);
+        self.lines(x, BODY_SIZE, &lines, bullet);
+        self.y -= BODY_SIZE * 0.6;
+    }
+
+    /// Returns where the heading is, for the bookmarks.
+    pub(crate) fn heading(&mut self, level: u32, spans: &[Span]) -> Dest {
+        let size = match level {
+            1 => 20.0,
+            2 => 16.0,
+            3 => 13.0,
+            _ => BODY_SIZE,
+        };
+        let spans: Vec<_> = spans
+            .iter()
+            .map(|s| Span {
+                text: s.text.clone(),
+                font: if s.font == Font::Mono {
+                    Font::Mono
+                } else {
+                    Font::Bold
+                },
+            })
+            .collect();
+
+        self.space(size * 0.8);
+        let lines = wrap(&spans, size, self.max_width());
+        // Don't leave a heading on it's own at the bottom of a page.
+        self.ensure(size * 1.4 * lines.len() as f32 + BODY_SIZE * 3.0);
+        let dest = self.here();
+        self.lines(MARGIN, size, &lines, None);
+        self.y -= size * 0.4;
+        dest
+    }
+
+    pub(crate) fn code(&mut self, text: &str, indent: u32) {
+        let x = MARGIN + indent as f32 * INDENT;
+        let line_height = CODE_SIZE * 1.3;
+        let width = self.width - MARGIN - x;
+        let per_line = ((width - 8.0) / Font::Mono.width("x", CODE_SIZE)).max(1.0) as usize;
+
+        for line in text.lines() {
+            let chars: Vec<char> = line.chars().collect();
+            let chunks: Vec<String> = if chars.is_empty() {
+                vec![String::new()]
+            } else {
+                chars.chunks(per_line).map(|c| c.iter().collect()).collect()
+            };
+            for chunk in chunks {
+                self.ensure(line_height);
+                let bg = format!(
+                    "0.94 g {:.2} {:.2} {:.2} {:.2} re f 0 g\n",
+                    x,
+                    self.y - line_height,
+                    width,
+                    line_height
+                );
+                self.draw(bg.as_bytes());
+                let baseline = self.y - CODE_SIZE;
+                self.draw_line(x + 4.0, baseline, CODE_SIZE, &[(Font::Mono, chunk)]);
+                self.y -= line_height;
+            }
+        }
+        self.y -= BODY_SIZE * 0.6;
+    }
+
+    pub(crate) fn rule(&mut self) {
+        self.ensure(BODY_SIZE);
+        let y = self.y - BODY_SIZE / 2.0;
+        let ops = format!(
+            "0.5 w 0.6 G {:.2} {:.2} m {:.2} {:.2} l S 0 G\n",
+            MARGIN,
+            y,
+            self.width - MARGIN,
+            y
+        );
+        self.draw(ops.as_bytes());
+        self.y -= BODY_SIZE;
+    }
+
+    /// A line of text in the middle of the page.
+    pub(crate) fn centered(&mut self, text: &str, font: Font, size: f32) {
+        let line = vec![(font, text.to_owned())];
+        let x = ((self.width - font.width(text, size)) / 2.0).max(MARGIN);
+        self.draw_line(x, self.y - size, size, &line);
+        self.y -= size * 1.6;
+    }
+
+    pub(crate) fn move_to(&mut self, y: f32) {
+        self.y = y;
+    }
+
+    /// An entry in the table of contents, which links to `dest`.
+    pub(crate) fn toc_entry(&mut self, title: &str, indent: u32, dest: Dest) {
+        let line_height = BODY_SIZE * 1.5;
+        self.ensure(line_height);
+
+        let x = MARGIN + indent as f32 * INDENT;
+        let label = (dest.page + 1).to_string();
+        let label_x = self.width - MARGIN - Font::Regular.width(&label, BODY_SIZE);
+        let title = truncate(title, Font::Regular, BODY_SIZE, label_x - x - 12.0);
+
+        let baseline = self.y - BODY_SIZE;
+        self.draw_line(x, baseline, BODY_SIZE, &[(Font::Regular, title)]);
+        self.draw_line(label_x, baseline, BODY_SIZE, &[(Font::Regular, label)]);
+
+        let rect = [x, self.y - line_height, self.width - MARGIN, self.y];
+        self.pages
+            .last_mut()
+            .unwrap()
+            .links
+            .push(LinkAnnot { rect, dest });
+        self.y -= line_height;
+    }
+
+    pub(crate) fn finish(mut self) -> Vec<Page> {
+        if self.numbered {
+            for i in 0..self.pages.len() {
+                let label = (self.first_page + i + 1).to_string();
+                let x = (self.width - Font::Regular.width(&label, 9.0)) / 2.0;
+                let mut ops = format!("BT {:.2} {:.2} Td /F1 9 Tf ", x, MARGIN / 2.0).into_bytes();
+                ops.extend(literal(&label));
+                ops.extend(b" Tj ET\n");
+                self.pages[i].content.extend(ops);
+            }
+        }
+        self.pages
+    }
+}
+
+/// Cut `text` down (adding `...`) so it fits in `width`.
+fn truncate(text: &str, font: Font, size: f32, width: f32) -> String {
+    if font.width(text, size) <= width {
+        return text.to_owned();
+    }
+    let mut out = String::new();
+    for c in text.chars() {
+        out.push(c);
+        if font.width(&out, size) + font.width("...", size) > width {
+            out.pop();
+            break;
+        }
+    }
+    out.push_str("...");
+    out
+}
+
+/// Greedy line breaking.
+fn wrap(spans: &[Span], size: f32, max_width: f32) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut line: Line = Vec::new();
+    let mut width = 0.0;
+
+    fn push(line: &mut Line, font: Font, text: &str) {
+        match line.last_mut() {
+            Some((f, t)) if *f == font => t.push_str(text),
+            _ => line.push((font, text.to_owned())),
+        }
+    }
+
+    for span in spans {
+        if span.text == LINE_BREAK {
+            lines.push(std::mem::take(&mut line));
+            width = 0.0;
+            continue;
+        }
+
+        for word in span.text.split_inclusive(' ') {
+            let font = span.font;
+            let word_width = font.width(word.trim_end(), size);
+            if width + word_width > max_width && !line.is_empty() {
+                lines.push(std::mem::take(&mut line));
+                width = 0.0;
+            }
+
+            let mut word = if line.is_empty() {
+                word.trim_start()
+            } else {
+                word
+            };
+
+            // Words too long for a line (eg urls) get split wherever.
+            while font.width(word.trim_end(), size) > max_width {
+                let mut split = word.len();
+                for (i, _) in word.char_indices().skip(1) {
+                    if font.width(&word[..i], size) > max_width {
+                        break;
+                    }
+                    split = i;
+                }
+                push(&mut line, font, &word[..split]);
+                lines.push(std::mem::take(&mut line));
+                word = &word[split..];
+            }
+
+            if !word.is_empty() {
+                push(&mut line, font, word);
+                width = line.iter().map(|(f, t)| f.width(t, size)).sum();
+            }
+        }
+    }
+
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(text: &str, font: Font) -> Span {
+        Span {
+            text: text.into(),
+            font,
+        }
+    }
+
+    #[test]
+    fn wrapping() {
+        let spans = vec![
+            span("aaaa bbbb ", Font::Mono),
+            span("cccc", Font::Bold),
+            span(LINE_BREAK, Font::Regular),
+            span("dddddddddddd", Font::Mono),
+        ];
+        // 10pt mono is 6pt a char, so 5 chars a line.
+        let lines = wrap(&spans, 10.0, 30.0);
+        assert_eq!(
+            lines,
+            vec![
+                vec![(Font::Mono, "aaaa ".into())],
+                vec![(Font::Mono, "bbbb ".into())],
+                vec![(Font::Bold, "cccc".into())],
+                vec![(Font::Mono, "ddddd".into())],
+                vec![(Font::Mono, "ddddd".into())],
+                vec![(Font::Mono, "dd".into())],
+            ]
+        );
+    }
+
+    #[test]
+    fn truncation() {
+        assert_eq!(truncate("abcdef", Font::Mono, 10.0, 100.0), "abcdef");
+        assert_eq!(truncate("abcdefghij", Font::Mono, 10.0, 36.0), "abc...");
+    }
+
+    #[test]
+    fn pagination() {
+        let mut layout = Layout::new(200.0, 200.0, 3, true);
+        let first = layout.here();
+        assert_eq!(first.page, 3);
+        for _ in 0..10 {
+            layout.paragraph(&[span("Hello", Font::Regular)], 0, None);
+        }
+        let pages = layout.finish();
+        assert!(pages.len() > 1);
+        let footer = String::from_utf8_lossy(&pages[1].content).to_string();
+        assert!(footer.contains("(5) Tj"), "{}", footer);
+    }
+}
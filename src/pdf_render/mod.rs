@@ -0,0 +1,277 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! A PDF per book, for offline reading.
+//!
+//! Each PDF has a title page, a table of contents, and then each chapter
+//! starting on a new page. Chapters (and their headings) are bookmarked,
+//! following the nesting in the `SUMMARY.md`.
+use std::fmt::Debug;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::PathBuf;
+
+use eyre::{Result, WrapErr};
+use tracing::instrument;
+
+use crate::cli;
+use crate::cli::config::{GlobalConf, PaperSize, PdfConf};
+use crate::content::{self, front_matter, Book, Content, Page};
+
+mod blocks;
+mod layout;
+mod writer;
+
+use blocks::Block;
+use layout::Layout;
+use writer::{Document, Font, Outline};
+
+pub(crate) struct PdfRender<'a> {
+    content: Content,
+    conf: &'a PdfConf,
+    out_dir: PathBuf,
+}
+
+impl Debug for PdfRender<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PdfRender")
+            .field("out_dir", &self.out_dir)
+            .field("conf", &self.conf)
+            .finish()
+    }
+}
+
+impl<'a> PdfRender<'a> {
+    #[instrument]
+    pub(crate) fn new(conf: &GlobalConf, args: &cli::Args, pdf: &'a PdfConf) -> Result<Self> {
+        let dirs = content::Dirs::new(conf, args);
+        let content = Content::new(conf, &dirs)?;
+        Ok(Self {
+            content,
+            conf: pdf,
+            out_dir: conf.build.out_dir.join("pdf"),
+        })
+    }
+
+    #[instrument]
+    pub(crate) fn render(&self) -> Result<()> {
+        if self.out_dir.exists() {
+            fs::remove_dir_all(&self.out_dir)?;
+        }
+        fs::create_dir_all(&self.out_dir)?;
+
+        for book in &self.content.0 {
+            let path = self.out_dir.join(format!("{}.pdf", book.dir_name()));
+            let file =
+                File::create(&path).wrap_err_with(|| format!("Failed to create {:?}", path))?;
+            self.document(book)?.write(BufWriter::new(file))?;
+        }
+
+        Ok(())
+    }
+
+    #[instrument]
+    fn document(&self, book: &Book) -> Result<Document> {
+        let (width, height) = match self.conf.paper_size {
+            PaperSize::A4 => (595.0, 842.0),
+            PaperSize::Letter => (612.0, 792.0),
+        };
+
+        let chapters = book
+            .pages
+            .iter()
+            .map(|page| Ok((page, blocks::blocks(&front_matter::read(&page.input)?.1))))
+            .collect::<Result<Vec<_>>>()?;
+
+        // We need to know how long the table of contents is before we know
+        // what page each chapter starts on, so do a dry run first.
+        let mut toc = Layout::new(width, height, 1, false);
+        let placeholders = vec![toc.here(); book.pages.len()];
+        table_of_contents(&mut toc, book, &placeholders);
+        let toc_pages = toc.finish().len();
+
+        let mut body = Layout::new(width, height, 1 + toc_pages, true);
+        let mut outlines = Vec::new();
+        let mut starts = Vec::new();
+        for (page, blocks) in &chapters {
+            body.new_page();
+            let start = body.here();
+            starts.push(start);
+
+            let depth = chapter_depth(page);
+            add_outline(
+                &mut outlines,
+                depth,
+                Outline {
+                    title: numbered_title(page, page.title()),
+                    dest: start,
+                    children: Vec::new(),
+                },
+            );
+
+            for (i, block) in blocks.iter().enumerate() {
+                // Number the chapters title, or make one up if it doesn't have one.
+                if i == 0 {
+                    if let Block::Heading { level: 1, spans } = block {
+                        let mut spans = spans.clone();
+                        if let Some(number) = &page.section_number {
+                            spans.insert(0, text_span(&format!("{} ", number)));
+                        }
+                        body.heading(1, &spans);
+                        continue;
+                    }
+                    body.heading(1, &[text_span(&numbered_title(page, page.title()))]);
+                }
+
+                match block {
+                    Block::Heading { level, spans } => {
+                        let dest = body.heading(*level, spans);
+                        if *level <= 3 {
+                            let title = spans.iter().map(|s| s.text.as_str()).collect();
+                            add_outline(
+                                &mut outlines,
+                                depth + (*level).max(2) - 1,
+                                Outline {
+                                    title,
+                                    dest,
+                                    children: Vec::new(),
+                                },
+                            );
+                        }
+                    }
+                    Block::Paragraph {
+                        spans,
+                        indent,
+                        bullet,
+                    } => body.paragraph(spans, *indent, bullet.as_deref()),
+                    Block::Code { text, indent } => body.code(text, *indent),
+                    Block::Rule => body.rule(),
+                }
+            }
+            if blocks.is_empty() {
+                body.heading(1, &[text_span(&numbered_title(page, page.title()))]);
+            }
+        }
+
+        let mut title_page = Layout::new(width, height, 0, false);
+        title_page.move_to(height * 0.65);
+        title_page.centered(&book.title, Font::Bold, 28.0);
+
+        let mut toc = Layout::new(width, height, 1, false);
+        table_of_contents(&mut toc, book, &starts);
+        let toc = toc.finish();
+        assert_eq!(toc.len(), toc_pages, "table of contents changed length");
+
+        let mut pages = title_page.finish();
+        pages.extend(toc);
+        pages.extend(body.finish());
+
+        Ok(Document {
+            title: book.title.clone(),
+            width,
+            height,
+            pages,
+            outlines,
+        })
+    }
+}
+
+fn table_of_contents(layout: &mut Layout, book: &Book, starts: &[writer::Dest]) {
+    layout.heading(1, &[text_span("Contents")]);
+    for (page, start) in book.pages.iter().zip(starts) {
+        layout.toc_entry(
+            &numbered_title(page, page.title()),
+            chapter_depth(page),
+            *start,
+        );
+    }
+}
+
+fn text_span(text: &str) -> blocks::Span {
+    blocks::Span {
+        text: text.to_owned(),
+        font: Font::Regular,
+    }
+}
+
+fn numbered_title(page: &Page, title: &str) -> String {
+    match &page.section_number {
+        Some(number) => format!("{} {}", number, title),
+        None => title.to_owned(),
+    }
+}
+
+/// How nested the chapter is in the `SUMMARY.md`.
+fn chapter_depth(page: &Page) -> u32 {
+    // The heirachy has the book, and the page itself.
+    page.heirachy.len().saturating_sub(2) as u32
+}
+
+/// Add a bookmark `depth` levels down, under the most recent bookmarks.
+fn add_outline(outlines: &mut Vec<Outline>, depth: u32, item: Outline) {
+    match outlines.last_mut() {
+        Some(last) if depth > 0 => add_outline(&mut last.children, depth - 1, item),
+        _ => outlines.push(item),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use assert_fs::prelude::*;
+
+    use super::*;
+    use crate::cli::config;
+
+    #[test]
+    fn outline_nesting() {
+        let item = |title: &str| Outline {
+            title: title.into(),
+            dest: writer::Dest { page: 0, y: 0.0 },
+            children: Vec::new(),
+        };
+        let mut outlines = Vec::new();
+        add_outline(&mut outlines, 0, item("1"));
+        add_outline(&mut outlines, 1, item("1.1"));
+        add_outline(&mut outlines, 2, item("1.1.1"));
+        add_outline(&mut outlines, 1, item("1.2"));
+        add_outline(&mut outlines, 0, item("2"));
+        add_outline(&mut outlines, 3, item("2.?"));
+
+        assert_eq!(outlines.len(), 2);
+        assert_eq!(outlines[0].children.len(), 2);
+        assert_eq!(outlines[0].children[0].children[0].title, "1.1.1");
+        assert_eq!(outlines[1].children[0].title, "2.?");
+    }
+
+    #[test]
+    fn dummy_e2e() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        temp.copy_from(
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("dummy-book"),
+            &["xmark.toml", "book-*/**"],
+        )
+        .unwrap();
+
+        let args = cli::Args {
+            dir: temp.path().to_owned(),
+            ..Default::default()
+        };
+        let conf = config::load(&args).unwrap();
+        let pdf = PdfConf::default();
+        let render = PdfRender::new(&conf, &args, &pdf).unwrap();
+        render.render().unwrap();
+
+        for book in &["book-1", "book-2", "book-3"] {
+            let out = fs::read(temp.path().join("_out/pdf").join(format!("{}.pdf", book))).unwrap();
+            assert!(out.starts_with(b"%PDF-1.4"));
+            assert!(out.ends_with(b"%%EOF\n"));
+        }
+
+        let book3 = fs::read(temp.path().join("_out/pdf/book-3.pdf")).unwrap();
+        let book3 = String::from_utf8_lossy(&book3);
+        // Section numbers and the front matter title.
+        assert!(book3.contains("(2. Chapter Two)"));
+        assert!(book3.contains("(3.2. c 3 2)"));
+        assert!(book3.contains("/Type /Outlines"));
+    }
+}
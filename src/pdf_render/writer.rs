@@ -0,0 +1,443 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! Just enough of PDF to write text documents.
+//!
+//! We only use the standard 14 fonts, which every PDF reader has built in, so
+//! we don't need to embed (or even find) any font files. The trade off is
+//! that text is limited to the WinAnsi (aka cp1252) character set.
+use std::io::{self, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Font {
+    Regular,
+    Bold,
+    Italic,
+    Mono,
+}
+
+impl Font {
+    const ALL: [Font; 4] = [Font::Regular, Font::Bold, Font::Italic, Font::Mono];
+
+    /// The name in the page resources dictionary.
+    pub(crate) fn resource(self) -> &'static str {
+        match self {
+            Font::Regular => "F1",
+            Font::Bold => "F2",
+            Font::Italic => "F3",
+            Font::Mono => "F4",
+        }
+    }
+
+    fn base_font(self) -> &'static str {
+        match self {
+            Font::Regular => "Helvetica",
+            Font::Bold => "Helvetica-Bold",
+            Font::Italic => "Helvetica-Oblique",
+            Font::Mono => "Courier",
+        }
+    }
+
+    /// The width of `text` in points.
+    pub(crate) fn width(self, text: &str, size: f32) -> f32 {
+        let units: u32 = text.chars().map(|c| self.char_width(c) as u32).sum();
+        units as f32 * size / 1000.0
+    }
+
+    /// Glyph width in 1/1000 of the font size, from the Adobe AFM files.
+    fn char_width(self, c: char) -> u16 {
+        let table = match self {
+            Font::Mono => return 600,
+            Font::Regular | Font::Italic => &HELVETICA,
+            Font::Bold => &HELVETICA_BOLD,
+        };
+        match c as u32 {
+            x @ 32..=126 => table[(x - 32) as usize],
+            // Close enough for the odd accented letter.
+            _ => 556,
+        }
+    }
+}
+
+#[rustfmt::skip]
+const HELVETICA: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278,
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556,
+    1015, 667, 667, 722, 722, 667, 611, 778, 722, 278, 500, 667, 556, 833, 722, 778,
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 278, 278, 278, 469, 556,
+    333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500, 222, 833, 556, 556,
+    556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584,
+];
+
+#[rustfmt::skip]
+const HELVETICA_BOLD: [u16; 95] = [
+    278, 333, 474, 556, 556, 889, 722, 238, 333, 333, 389, 584, 278, 333, 278, 278,
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 333, 333, 584, 584, 584, 611,
+    975, 722, 722, 722, 722, 667, 611, 778, 722, 278, 556, 722, 611, 833, 722, 778,
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 333, 278, 333, 584, 556,
+    333, 556, 611, 556, 611, 556, 333, 611, 611, 278, 278, 556, 278, 889, 611, 611,
+    611, 611, 389, 556, 333, 611, 556, 778, 556, 556, 500, 389, 280, 389, 584,
+];
+
+/// Encode text as WinAnsi, replacing anything that doesn't fit with `?`.
+pub(crate) fn encode(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| match c {
+            ' '..='~' => c as u8,
+            '\u{a0}'..='\u{ff}' => c as u32 as u8,
+            '€' => 0x80,
+            '…' => 0x85,
+            '‘' => 0x91,
+            '’' => 0x92,
+            '“' => 0x93,
+            '”' => 0x94,
+            '•' => 0x95,
+            '–' => 0x96,
+            '—' => 0x97,
+            '™' => 0x99,
+            '\t' => b' ',
+            _ => b'?',
+        })
+        .collect()
+}
+
+/// A string literal in a content stream.
+pub(crate) fn literal(text: &str) -> Vec<u8> {
+    let mut out = vec![b'('];
+    for b in encode(text) {
+        if matches!(b, b'(' | b')' | b'\\') {
+            out.push(b'\\');
+        }
+        out.push(b);
+    }
+    out.push(b')');
+    out
+}
+
+/// A string outside of a content stream (eg bookmark titles), which are
+/// either PDFDocEncoding or UTF-16.
+fn text_string(text: &str) -> String {
+    if text.chars().all(|c| (' '..='~').contains(&c)) {
+        String::from_utf8(literal(text)).unwrap()
+    } else {
+        let mut out = String::from("<FEFF");
+        for unit in text.encode_utf16() {
+            out.push_str(&format!("{:04X}", unit));
+        }
+        out.push('>');
+        out
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Page {
+    /// The content stream.
+    pub(crate) content: Vec<u8>,
+    pub(crate) links: Vec<LinkAnnot>,
+}
+
+/// A clickable area that goes somewhere else in the document.
+#[derive(Debug, Clone)]
+pub(crate) struct LinkAnnot {
+    /// x1, y1, x2, y2
+    pub(crate) rect: [f32; 4],
+    pub(crate) dest: Dest,
+}
+
+/// A position in the document.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Dest {
+    pub(crate) page: usize,
+    pub(crate) y: f32,
+}
+
+/// A bookmark.
+#[derive(Debug, Clone)]
+pub(crate) struct Outline {
+    pub(crate) title: String,
+    pub(crate) dest: Dest,
+    pub(crate) children: Vec<Outline>,
+}
+
+impl Outline {
+    fn count(&self) -> usize {
+        self.children.iter().map(|c| 1 + c.count()).sum()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Document {
+    pub(crate) title: String,
+    pub(crate) width: f32,
+    pub(crate) height: f32,
+    pub(crate) pages: Vec<Page>,
+    pub(crate) outlines: Vec<Outline>,
+}
+
+const CATALOG: usize = 1;
+const PAGES: usize = 2;
+const INFO: usize = 3;
+const FIRST_FONT: usize = 4;
+const FIRST_PAGE: usize = FIRST_FONT + Font::ALL.len();
+
+/// Keeps track of where each object starts, for the xref table.
+struct Writer<W> {
+    out: W,
+    pos: usize,
+    offsets: Vec<usize>,
+}
+
+impl<W: Write> Writer<W> {
+    fn raw(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.out.write_all(bytes)?;
+        self.pos += bytes.len();
+        Ok(())
+    }
+
+    fn object(&mut self, id: usize, body: &str) -> io::Result<()> {
+        self.start(id)?;
+        self.raw(body.as_bytes())?;
+        self.raw(b"\nendobj\n")
+    }
+
+    fn start(&mut self, id: usize) -> io::Result<()> {
+        if self.offsets.len() <= id {
+            self.offsets.resize(id + 1, 0);
+        }
+        self.offsets[id] = self.pos;
+        self.raw(format!("{} 0 obj\n", id).as_bytes())
+    }
+}
+
+impl Document {
+    fn page_id(&self, page: usize) -> usize {
+        FIRST_PAGE + 2 * page
+    }
+
+    fn dest(&self, dest: Dest) -> String {
+        format!("[{} 0 R /XYZ 0 {:.2} 0]", self.page_id(dest.page), dest.y)
+    }
+
+    pub(crate) fn write(&self, out: impl Write) -> io::Result<()> {
+        let mut w = Writer {
+            out,
+            pos: 0,
+            offsets: vec![0],
+        };
+        w.raw(b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n")?;
+
+        // Everything after the pages gets numbered as we go.
+        let mut next_id = FIRST_PAGE + 2 * self.pages.len();
+
+        let outline_root = if self.outlines.is_empty() {
+            None
+        } else {
+            let root = next_id;
+            next_id += 1;
+            Some(root)
+        };
+        let mut catalog = format!("<< /Type /Catalog /Pages {} 0 R", PAGES);
+        if let Some(root) = outline_root {
+            catalog.push_str(&format!(" /Outlines {} 0 R /PageMode /UseOutlines", root));
+        }
+        catalog.push_str(" >>");
+        w.object(CATALOG, &catalog)?;
+
+        let kids: Vec<_> = (0..self.pages.len())
+            .map(|i| format!("{} 0 R", self.page_id(i)))
+            .collect();
+        w.object(
+            PAGES,
+            &format!(
+                "<< /Type /Pages /Kids [{}] /Count {} /MediaBox [0 0 {:.2} {:.2}] >>",
+                kids.join(" "),
+                self.pages.len(),
+                self.width,
+                self.height
+            ),
+        )?;
+
+        w.object(
+            INFO,
+            &format!(
+                "<< /Title {} /Producer (xmark) >>",
+                text_string(&self.title)
+            ),
+        )?;
+
+        let mut fonts = String::new();
+        for (i, font) in Font::ALL.iter().enumerate() {
+            w.object(
+                FIRST_FONT + i,
+                &format!(
+                    "<< /Type /Font /Subtype /Type1 /BaseFont /{} /Encoding /WinAnsiEncoding >>",
+                    font.base_font()
+                ),
+            )?;
+            fonts.push_str(&format!("/{} {} 0 R ", font.resource(), FIRST_FONT + i));
+        }
+
+        for (i, page) in self.pages.iter().enumerate() {
+            let id = self.page_id(i);
+
+            let mut annots = Vec::new();
+            for link in &page.links {
+                let [x1, y1, x2, y2] = link.rect;
+                w.object(
+                    next_id,
+                    &format!(
+                        "<< /Type /Annot /Subtype /Link /Rect [{:.2} {:.2} {:.2} {:.2}] /Border [0 0 0] /Dest {} >>",
+                        x1, y1, x2, y2,
+                        self.dest(link.dest)
+                    ),
+                )?;
+                annots.push(format!("{} 0 R", next_id));
+                next_id += 1;
+            }
+
+            w.object(
+                id,
+                &format!(
+                    "<< /Type /Page /Parent {} 0 R /Resources << /Font << {}>> >> /Contents {} 0 R /Annots [{}] >>",
+                    PAGES,
+                    fonts,
+                    id + 1,
+                    annots.join(" ")
+                ),
+            )?;
+
+            w.start(id + 1)?;
+            w.raw(format!("<< /Length {} >>\nstream\n", page.content.len()).as_bytes())?;
+            w.raw(&page.content)?;
+            w.raw(b"\nendstream\nendobj\n")?;
+        }
+
+        if let Some(root) = outline_root {
+            let (first, last) = self.write_outlines(&mut w, &self.outlines, root, &mut next_id)?;
+            let count: usize = self.outlines.iter().map(|o| 1 + o.count()).sum();
+            w.object(
+                root,
+                &format!(
+                    "<< /Type /Outlines /First {} 0 R /Last {} 0 R /Count {} >>",
+                    first, last, count
+                ),
+            )?;
+        }
+
+        let xref = w.pos;
+        let mut table = format!("xref\n0 {}\n0000000000 65535 f \n", w.offsets.len());
+        for offset in &w.offsets[1..] {
+            table.push_str(&format!("{:010} 00000 n \n", offset));
+        }
+        table.push_str(&format!(
+            "trailer\n<< /Size {} /Root {} 0 R /Info {} 0 R >>\nstartxref\n{}\n%%EOF\n",
+            w.offsets.len(),
+            CATALOG,
+            INFO,
+            xref
+        ));
+        w.raw(table.as_bytes())
+    }
+
+    /// Write a list of sibling bookmarks, returning the first and last ids.
+    fn write_outlines<W: Write>(
+        &self,
+        w: &mut Writer<W>,
+        items: &[Outline],
+        parent: usize,
+        next_id: &mut usize,
+    ) -> io::Result<(usize, usize)> {
+        let ids: Vec<usize> = (0..items.len()).map(|i| *next_id + i).collect();
+        *next_id += items.len();
+
+        for (i, item) in items.iter().enumerate() {
+            let mut dict = format!(
+                "<< /Title {} /Parent {} 0 R /Dest {}",
+                text_string(&item.title),
+                parent,
+                self.dest(item.dest)
+            );
+            if i > 0 {
+                dict.push_str(&format!(" /Prev {} 0 R", ids[i - 1]));
+            }
+            if let Some(next) = ids.get(i + 1) {
+                dict.push_str(&format!(" /Next {} 0 R", next));
+            }
+            if !item.children.is_empty() {
+                let (first, last) = self.write_outlines(w, &item.children, ids[i], next_id)?;
+                dict.push_str(&format!(
+                    " /First {} 0 R /Last {} 0 R /Count {}",
+                    first,
+                    last,
+                    item.count()
+                ));
+            }
+            dict.push_str(" >>");
+            w.object(ids[i], &dict)?;
+        }
+
+        Ok((ids[0], ids[ids.len() - 1]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn widths() {
+        assert_eq!(Font::Mono.width("abcd", 10.0), 24.0);
+        assert!((Font::Regular.width("Hi", 10.0) - 9.44).abs() < 1e-4);
+        assert!(Font::Bold.width("Hi", 10.0) > Font::Regular.width("Hi", 10.0));
+    }
+
+    #[test]
+    fn encoding() {
+        assert_eq!(encode("a’b→"), b"a\x92b?");
+        assert_eq!(literal("f(x) \\"), b"(f\\(x\\) \\\\)");
+        assert_eq!(text_string("Hi"), "(Hi)");
+        assert_eq!(text_string("é"), "<FEFF00E9>");
+    }
+
+    #[test]
+    fn structure() {
+        let doc = Document {
+            title: "T".into(),
+            width: 100.0,
+            height: 100.0,
+            pages: vec![Page::default(), Page::default()],
+            outlines: vec![Outline {
+                title: "One".into(),
+                dest: Dest { page: 0, y: 100.0 },
+                children: vec![Outline {
+                    title: "Two".into(),
+                    dest: Dest { page: 1, y: 50.0 },
+                    children: vec![],
+                }],
+            }],
+        };
+        let mut out = Vec::new();
+        doc.write(&mut out).unwrap();
+        // Keep the byte offsets the same.
+        let out: Vec<u8> = out
+            .iter()
+            .map(|&b| if b < 128 { b } else { b'?' })
+            .collect();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out.starts_with("%PDF-1.4"));
+        assert!(out.ends_with("%%EOF\n"));
+        assert!(out.contains("/Count 2 /MediaBox"));
+        assert!(out.contains("/Title (Two) /Parent"));
+
+        // Every xref entry should point at the object it claims to.
+        let xref = out.rfind("xref\n").unwrap();
+        for (id, line) in out[xref..]
+            .lines()
+            .skip(3)
+            .take_while(|l| l.ends_with(" n "))
+            .enumerate()
+        {
+            let offset: usize = line[..10].parse().unwrap();
+            assert!(out[offset..].starts_with(&format!("{} 0 obj", id + 1)));
+        }
+    }
+}
@@ -1,20 +1,23 @@
 use eyre::Result;
 
 use crate::cli::config::{GlobalConf, HtmlConf};
+use crate::cli::Args;
 use crate::content::Content;
 use crate::html_render::HTMLRender;
-use crate::cli::{Args};
-
-pub(crate) struct HTML<'a>(HTMLRender<'a>);
 
+// TODO: Move the backends over to this.
+#[allow(dead_code)]
+pub(crate) struct Html<'a>(HTMLRender<'a>);
 
+#[allow(dead_code)]
 pub(crate) struct GlobalRenderContext {
     conf: GlobalConf,
     args: Args,
-    content: Content
+    content: Content,
 }
 
-pub(crate) trait Renderer  {
+#[allow(dead_code)]
+pub(crate) trait Renderer {
     type LocalContext;
 
     fn new(gc: &GlobalConf, lc: &Self::LocalContext) -> Self;
@@ -22,11 +25,10 @@ pub(crate) trait Renderer  {
     fn render(&self) -> Result<()>;
 }
 
-impl Renderer for HTML<'_> {
-
+impl Renderer for Html<'_> {
     type LocalContext = HtmlConf;
 
-    fn new(gc: &GlobalConf, lc: &HtmlConf) -> Self {
+    fn new(_gc: &GlobalConf, _lc: &HtmlConf) -> Self {
         todo!()
     }
 
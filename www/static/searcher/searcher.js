@@ -467,15 +467,21 @@ window.search = window.search || {};
         showResults(true);
     }
 
-    fetch(path_to_root + 'searchindex.json')
-        .then(response => response.json())
-        .then(json => init(json))        
-        .catch(error => { // Try to load searchindex.js if fetch failed
-            var script = document.createElement('script');
-            script.src = path_to_root + 'searchindex.js';
-            script.onload = () => init(window.search);
-            document.head.appendChild(script);
-        });
+    function loadIndexScript() {
+        var script = document.createElement('script');
+        script.src = path_to_root + 'searchindex.js';
+        script.onload = () => init(window.search);
+        document.head.appendChild(script);
+    }
+
+    if (offline) { // fetch doesn't work over file://, so don't even try
+        loadIndexScript();
+    } else {
+        fetch(path_to_root + 'searchindex.json')
+            .then(response => response.json())
+            .then(json => init(json))
+            .catch(error => loadIndexScript()); // Try to load searchindex.js if fetch failed
+    }
 
     // Exported functions
     search.hasFocus = hasFocus;